@@ -0,0 +1,206 @@
+//! Coverage of the executed program and of its assembly source.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    path::PathBuf,
+};
+
+use super::LcovError;
+use crate::machine_code::SourceMap;
+
+/// Counts of executed operations of the loaded program by their offsets.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    counts: BTreeMap<usize, u64>,
+}
+
+impl Coverage {
+    pub(crate) fn record(&mut self, offset: usize) {
+        *self.counts.entry(offset).or_default() += 1;
+    }
+
+    /// Returns number of executions of the operation at the offset.
+    pub fn count(&self, offset: usize) -> u64 {
+        self.counts.get(&offset).copied().unwrap_or_default()
+    }
+
+    /// Returns coverage of the source lines, which contain operations of the program.
+    pub fn lines(&self, source_map: &SourceMap) -> LineCoverage {
+        let mut lines = LineCoverage::default();
+        for (offset, location) in source_map.iter() {
+            let file = lines.files.entry(location.file.clone()).or_default();
+            *file.entry(location.line).or_default() += self.count(offset);
+        }
+        lines
+    }
+}
+
+/// Execution counts of the source lines, which contain operations.
+///
+/// Lines start from 0, like in `Location`, and from 1 in the reports.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineCoverage {
+    /// Counts of lines by file, `None` is the source not read from a file.
+    files: BTreeMap<Option<PathBuf>, BTreeMap<usize, u64>>,
+}
+
+/// Name of the source, which was not read from a file, in the reports.
+const NO_FILE: &str = "-";
+
+impl LineCoverage {
+    /// Returns number of executions of the line, `None` if it contains no operations.
+    pub fn count(&self, file: Option<&PathBuf>, line: usize) -> Option<u64> {
+        self.files.get(&file.cloned())?.get(&line).copied()
+    }
+
+    /// Adds counts of another run, possibly of another program.
+    pub fn merge(&mut self, other: &LineCoverage) {
+        for (file, lines) in other.files.iter() {
+            let counts = self.files.entry(file.clone()).or_default();
+            for (&line, &count) in lines.iter() {
+                *counts.entry(line).or_default() += count;
+            }
+        }
+    }
+
+    /// Returns the coverage in the lcov tracefile format.
+    pub fn to_lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (file, lines) in self.files.iter() {
+            let file = file
+                .as_ref()
+                .map_or(NO_FILE.into(), |f| f.display().to_string());
+            writeln!(lcov, "TN:\nSF:{}", file).unwrap();
+            for (line, count) in lines.iter() {
+                writeln!(lcov, "DA:{},{}", line + 1, count).unwrap();
+            }
+            let hit = lines.values().filter(|&&count| count > 0).count();
+            writeln!(lcov, "LH:{}\nLF:{}\nend_of_record", hit, lines.len()).unwrap();
+        }
+        lcov
+    }
+
+    /// Reads line counts from the lcov tracefile, other records are ignored.
+    pub fn from_lcov(lcov: &str) -> Result<Self, LcovError> {
+        let mut coverage = LineCoverage::default();
+        let mut file = None;
+        for (idx, text) in lcov.lines().enumerate() {
+            let error = || LcovError {
+                line: idx + 1,
+                text: text.to_owned(),
+            };
+            let text = text.trim();
+            if let Some(name) = text.strip_prefix("SF:") {
+                let name = Some(name)
+                    .filter(|&name| name != NO_FILE)
+                    .map(PathBuf::from);
+                file = Some(coverage.files.entry(name).or_default());
+            } else if let Some(data) = text.strip_prefix("DA:") {
+                let mut fields = data.split(',');
+                let line = fields.next().and_then(|line| line.parse::<usize>().ok());
+                let count = fields.next().and_then(|count| count.parse::<u64>().ok());
+                match (file.as_mut(), line, count) {
+                    (Some(file), Some(line), Some(count)) if line > 0 => {
+                        *file.entry(line - 1).or_default() += count
+                    }
+                    _ => return Err(error()),
+                }
+            } else if text == "end_of_record" {
+                file = None;
+            }
+        }
+        Ok(coverage)
+    }
+}
+
+/// Summary with the share of executed lines and the lines, which were not executed.
+impl fmt::Display for LineCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let summary = |f: &mut fmt::Formatter<'_>, name: &str, hit: usize, total: usize| {
+            let percent = if total == 0 {
+                100.0
+            } else {
+                hit as f64 * 100.0 / total as f64
+            };
+            writeln!(f, "{}: {} of {} lines ({:.1}%)", name, hit, total, percent)
+        };
+
+        let (mut total_hit, mut total) = (0, 0);
+        for (file, lines) in self.files.iter() {
+            let missed: Vec<_> = lines
+                .iter()
+                .filter(|(_, &count)| count == 0)
+                .map(|(line, _)| (line + 1).to_string())
+                .collect();
+            let hit = lines.len() - missed.len();
+            let name = file
+                .as_ref()
+                .map_or(NO_FILE.into(), |f| f.display().to_string());
+            summary(f, &name, hit, lines.len())?;
+            if !missed.is_empty() {
+                writeln!(f, "  not executed: {}", missed.join(", "))?;
+            }
+            total_hit += hit;
+            total += lines.len();
+        }
+        summary(f, "Total", total_hit, total)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Location;
+
+    #[test]
+    fn lines() {
+        let mut source_map = SourceMap::default();
+        let location = |line| Location {
+            file: Some("a.sasm".into()),
+            line,
+        };
+        // Two operations on line 1, for example an expanded macro
+        source_map.insert(0, location(1));
+        source_map.insert(5, location(1));
+        source_map.insert(6, location(2));
+        source_map.insert(8, location(4));
+        let mut coverage = Coverage::default();
+        for &offset in [0, 5, 0, 5, 8].iter() {
+            coverage.record(offset);
+        }
+
+        let mut lines = coverage.lines(&source_map);
+        let file = PathBuf::from("a.sasm");
+        assert_eq!(lines.count(Some(&file), 1), Some(4));
+        assert_eq!(lines.count(Some(&file), 2), Some(0));
+        assert_eq!(lines.count(Some(&file), 3), None);
+        assert_eq!(
+            lines.to_string(),
+            "a.sasm: 2 of 3 lines (66.7%)\n  not executed: 3\nTotal: 2 of 3 lines (66.7%)\n"
+        );
+
+        let lcov = lines.to_lcov();
+        assert_eq!(
+            lcov,
+            "TN:\nSF:a.sasm\nDA:2,4\nDA:3,0\nDA:5,1\nLH:2\nLF:3\nend_of_record\n"
+        );
+        assert_eq!(LineCoverage::from_lcov(&lcov), Ok(lines.clone()));
+
+        let other = LineCoverage::from_lcov("SF:a.sasm\nDA:3,2\nend_of_record\nSF:-\nDA:1,1\n");
+        lines.merge(&other.unwrap());
+        assert_eq!(lines.count(Some(&file), 2), Some(2));
+        assert_eq!(lines.count(None, 0), Some(1));
+        assert!(lines
+            .to_string()
+            .ends_with("Total: 4 of 4 lines (100.0%)\n"));
+
+        assert_eq!(
+            LineCoverage::from_lcov("DA:1,1"),
+            Err(LcovError {
+                line: 1,
+                text: "DA:1,1".into()
+            })
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Input and output devices, which programs access through numbered ports.
+//!
+//! Text devices read and write integers in decimal, one per line.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, IsTerminal, Write},
+};
+
+/// Device attached to a port of the machine.
+pub trait IoDevice {
+    /// Reads an integer, fails at the end of input or if the input is not an integer.
+    fn read_integer(&mut self) -> io::Result<i64>;
+
+    /// Reads a byte, returns `None` at the end of input.
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+
+    fn write_integer(&mut self, value: i64) -> io::Result<()>;
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Devices can be borrowed, so that their state is available after execution.
+impl<T: IoDevice + ?Sized> IoDevice for &mut T {
+    fn read_integer(&mut self) -> io::Result<i64> {
+        (**self).read_integer()
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        (**self).read_byte()
+    }
+
+    fn write_integer(&mut self, value: i64) -> io::Result<()> {
+        (**self).write_integer(value)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        (**self).write_byte(byte)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// Reads a line with an integer, returns `Ok(Err(line))` if the line is not an integer.
+fn read_integer_line(input: &mut impl BufRead) -> io::Result<Result<i64, String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "end of input, expected an integer",
+        ));
+    }
+    let line = line.trim();
+    Ok(line.parse().map_err(|_| line.to_owned()))
+}
+
+fn not_integer(line: String) -> io::Error {
+    let message = format!("expected an integer, got: {}", line);
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_byte(input: &mut impl BufRead) -> io::Result<Option<u8>> {
+    let byte = input.fill_buf()?.first().copied();
+    if byte.is_some() {
+        input.consume(1);
+    }
+    Ok(byte)
+}
+
+/// Standard input and output.
+///
+/// When the input is a terminal, the device prompts for integers on the standard error
+/// and asks again if the input is not an integer.
+#[derive(Debug)]
+pub struct StdioDevice {
+    interactive: bool,
+}
+
+impl StdioDevice {
+    pub fn new() -> Self {
+        StdioDevice {
+            interactive: io::stdin().is_terminal(),
+        }
+    }
+}
+
+impl Default for StdioDevice {
+    fn default() -> Self {
+        StdioDevice::new()
+    }
+}
+
+impl IoDevice for StdioDevice {
+    fn read_integer(&mut self) -> io::Result<i64> {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        if !self.interactive {
+            return read_integer_line(&mut input)?.map_err(not_integer);
+        }
+
+        eprint!("Enter number: ");
+        loop {
+            match read_integer_line(&mut input)? {
+                Ok(value) => return Ok(value),
+                Err(_) => eprint!("Try again: "),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        read_byte(&mut io::stdin().lock())
+    }
+
+    fn write_integer(&mut self, value: i64) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", value)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Device, which reads from a buffer and collects the output in memory.
+#[derive(Debug, Default)]
+pub struct MemoryDevice {
+    input: io::Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl MemoryDevice {
+    pub fn new(input: impl Into<Vec<u8>>) -> Self {
+        MemoryDevice {
+            input: io::Cursor::new(input.into()),
+            output: Vec::new(),
+        }
+    }
+
+    /// Returns everything written to the device.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn into_output(self) -> Vec<u8> {
+        self.output
+    }
+}
+
+impl IoDevice for MemoryDevice {
+    fn read_integer(&mut self) -> io::Result<i64> {
+        read_integer_line(&mut self.input)?.map_err(not_integer)
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        read_byte(&mut self.input)
+    }
+
+    fn write_integer(&mut self, value: i64) -> io::Result<()> {
+        writeln!(self.output, "{}", value)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.output.push(byte);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Expected interaction with a `ScriptedDevice`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Program reads an integer, the device returns it.
+    ReadInteger(i64),
+
+    /// Program reads a byte, the device returns it, `None` is the end of input.
+    ReadByte(Option<u8>),
+
+    /// Program writes an integer, which must be equal to this one.
+    WriteInteger(i64),
+
+    /// Program writes a byte, which must be equal to this one.
+    WriteByte(u8),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::ReadInteger(_) => write!(f, "read of an integer"),
+            Event::ReadByte(_) => write!(f, "read of a byte"),
+            Event::WriteInteger(value) => write!(f, "write of {}", value),
+            Event::WriteByte(byte) => write!(f, "write of byte {:#04x}", byte),
+        }
+    }
+}
+
+/// Device, which records all interactions with another device.
+///
+/// Recorded events can be replayed with a `ScriptedDevice`, which feeds the same input
+/// to the program and checks that it writes the same output.
+#[derive(Debug, Default)]
+pub struct RecordingDevice<D> {
+    device: D,
+    events: Vec<Event>,
+}
+
+impl<D: IoDevice> RecordingDevice<D> {
+    pub fn new(device: D) -> Self {
+        RecordingDevice {
+            device,
+            events: Vec::new(),
+        }
+    }
+
+    /// Returns successful interactions in the order they happened.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: IoDevice> IoDevice for RecordingDevice<D> {
+    fn read_integer(&mut self) -> io::Result<i64> {
+        let value = self.device.read_integer()?;
+        self.events.push(Event::ReadInteger(value));
+        Ok(value)
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.device.read_byte()?;
+        self.events.push(Event::ReadByte(byte));
+        Ok(byte)
+    }
+
+    fn write_integer(&mut self, value: i64) -> io::Result<()> {
+        self.device.write_integer(value)?;
+        self.events.push(Event::WriteInteger(value));
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.device.write_byte(byte)?;
+        self.events.push(Event::WriteByte(byte));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+}
+
+/// Test double, which follows a script of reads and writes.
+///
+/// Any interaction, which differs from the next event of the script, fails.
+#[derive(Debug, Default)]
+pub struct ScriptedDevice {
+    script: VecDeque<Event>,
+}
+
+impl ScriptedDevice {
+    pub fn new(script: impl IntoIterator<Item = Event>) -> Self {
+        ScriptedDevice {
+            script: script.into_iter().collect(),
+        }
+    }
+
+    /// Returns events, which didn't happen yet.
+    pub fn remaining(&self) -> &VecDeque<Event> {
+        &self.script
+    }
+
+    fn next(&mut self, actual: Event) -> io::Result<Event> {
+        let unexpected = |expected: Option<&Event>| {
+            let expected = expected.map_or("end of the script".to_owned(), Event::to_string);
+            let message = format!("expected {}, got {}", expected, actual);
+            io::Error::other(message)
+        };
+
+        match (self.script.front(), actual) {
+            (Some(&Event::ReadInteger(_)), Event::ReadInteger(_))
+            | (Some(&Event::ReadByte(_)), Event::ReadByte(_)) => {}
+            (Some(&expected), actual) if expected == actual => {}
+            (expected, _) => return Err(unexpected(expected)),
+        }
+        Ok(self.script.pop_front().unwrap())
+    }
+}
+
+impl IoDevice for ScriptedDevice {
+    fn read_integer(&mut self) -> io::Result<i64> {
+        match self.next(Event::ReadInteger(0))? {
+            Event::ReadInteger(value) => Ok(value),
+            _ => unreachable!(),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        match self.next(Event::ReadByte(None))? {
+            Event::ReadByte(byte) => Ok(byte),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_integer(&mut self, value: i64) -> io::Result<()> {
+        self.next(Event::WriteInteger(value)).map(drop)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.next(Event::WriteByte(byte)).map(drop)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_device() {
+        let mut device = MemoryDevice::new(" 12 \nx\n");
+        assert_eq!(device.read_integer().unwrap(), 12);
+        let error = device.read_integer().unwrap_err();
+        assert_eq!(error.to_string(), "expected an integer, got: x");
+        assert_eq!(
+            device.read_integer().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(device.read_byte().unwrap(), None);
+
+        device.write_integer(-5).unwrap();
+        device.write_byte(b'!').unwrap();
+        assert_eq!(device.output(), b"-5\n!");
+    }
+
+    #[test]
+    fn scripted_device() {
+        let mut device = ScriptedDevice::new(vec![
+            Event::ReadInteger(7),
+            Event::WriteInteger(14),
+            Event::ReadByte(Some(b'a')),
+        ]);
+        assert_eq!(device.read_integer().unwrap(), 7);
+        assert_eq!(
+            device.write_integer(15).unwrap_err().to_string(),
+            "expected write of 14, got write of 15"
+        );
+        device.write_integer(14).unwrap();
+        assert_eq!(device.read_byte().unwrap(), Some(b'a'));
+        assert!(device.remaining().is_empty());
+        assert_eq!(
+            device.read_integer().unwrap_err().to_string(),
+            "expected end of the script, got read of an integer"
+        );
+    }
+
+    #[test]
+    fn recording_device() {
+        let mut device = RecordingDevice::new(MemoryDevice::new("3\n"));
+        assert_eq!(device.read_integer().unwrap(), 3);
+        assert!(device.read_integer().is_err());
+        assert_eq!(device.read_byte().unwrap(), None);
+        device.write_integer(6).unwrap();
+        device.write_byte(b'\n').unwrap();
+        assert_eq!(
+            device.events(),
+            &[
+                Event::ReadInteger(3),
+                Event::ReadByte(None),
+                Event::WriteInteger(6),
+                Event::WriteByte(b'\n'),
+            ]
+        );
+        assert_eq!(device.into_inner().output(), b"6\n\n");
+    }
+}
@@ -1,6 +1,10 @@
 use thiserror::Error;
 
-use crate::machine_code::OpDecompileError;
+use super::Event;
+use crate::{
+    machine_code::{HeaderDecompileError, OpDecompileError},
+    models::{Op, Register},
+};
 
 #[derive(Error, Debug)]
 #[error("Can't read from the input stream: {inner}")]
@@ -16,11 +20,96 @@ pub struct OutputError {
     inner: std::io::Error,
 }
 
+/// Error returned by a host function.
+#[derive(Error, Debug)]
+pub enum HostError {
+    #[error("Attemted to pop from empty stack")]
+    StackUnderflow,
+
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl From<ExecutionError> for HostError {
+    fn from(error: ExecutionError) -> Self {
+        match error {
+            ExecutionError::StackUnderflow => HostError::StackUnderflow,
+            error => HostError::Failed(error.to_string()),
+        }
+    }
+}
+
+/// Error in a line of the lcov tracefile.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("Line {line} of the coverage report is incorrect: {text}")]
+pub struct LcovError {
+    pub line: usize,
+    pub text: String,
+}
+
+/// Error of replaying recorded interactions with a device.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    #[error("Line {line} of the replay is not an event: {text}")]
+    WrongEvent { line: usize, text: String },
+
+    #[error("Program terminated before the recorded {0}")]
+    Unfinished(Event),
+}
+
 #[derive(Error, Debug)]
 pub enum ExecutionError {
     #[error("Attemted to pop from empty stack")]
     StackUnderflow,
 
+    #[error("Attemted to pop from empty float stack")]
+    FloatStackUnderflow,
+
+    #[error("{op} by zero")]
+    DivisionByZero { op: Op },
+
+    #[error("Register {register} is used, but the machine has only {count} registers")]
+    MissingRegister { register: Register, count: u8 },
+
+    #[error("{op} is executed outside of a frame")]
+    NoFrame { op: Op },
+
+    #[error("Local {index} is out of bounds of the frame with {count} locals")]
+    LocalOutOfBounds { index: u8, count: u8 },
+
+    #[error("Argument {index} is out of bounds of the frame with {count} arguments")]
+    ArgumentOutOfBounds { index: u8, count: usize },
+
+    #[error("Host function {0} is not registered")]
+    UnknownHostFunction(String),
+
+    #[error("There is no host function number {0}")]
+    UnknownSyscall(u8),
+
+    #[error("There is no host function with index {0} in the imports of the program")]
+    UnknownImport(u8),
+
+    #[error("Host function {name} failed: {inner}")]
+    HostError { name: String, inner: HostError },
+
+    #[error("No device is attached to port {0}")]
+    NoDevice(u8),
+
+    #[error("Input value doesn't fit into the machine word: {0}")]
+    InputOutOfRange(i64),
+
+    #[error("{op} failed at offset {offset} with values {values:?}")]
+    AssertionFailed {
+        op: Op,
+        /// Offset of the operation in the program, including the header.
+        offset: usize,
+        /// Popped values, in the order they were pushed.
+        values: Vec<i64>,
+    },
+
+    #[error("Can't restore the machine state: {0}")]
+    WrongState(String),
+
     #[error("{inner}")]
     InputError {
         #[from]
@@ -38,4 +127,10 @@ pub enum ExecutionError {
         #[from]
         inner: OpDecompileError,
     },
+
+    #[error("{inner}")]
+    HeaderReadError {
+        #[from]
+        inner: HeaderDecompileError,
+    },
 }
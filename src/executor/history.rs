@@ -0,0 +1,56 @@
+//! Execution history, which allows stepping backwards.
+//!
+//! Each executed instruction is recorded with the part of the state it may change:
+//! values it may pop or overwrite, registers it uses, the frame it may remove and
+//! the input it consumes. Undone instructions are executed again with the recorded
+//! input and without repeating the output.
+
+use crate::models::Register;
+
+/// Changes made by a step, enough to undo it.
+#[derive(Debug)]
+pub(crate) struct Step {
+    pub position: usize,
+    pub executed: u64,
+
+    /// Values of the stack from `stack_base` to the top before the step.
+    pub stack_base: usize,
+    pub stack: Vec<i64>,
+
+    /// Values of the float stack from `floats_base` to the top before the step.
+    pub floats_base: usize,
+    pub floats: Vec<f64>,
+
+    pub registers: Vec<(Register, i64)>,
+
+    /// Number of frames and the innermost frame before the step, as `(base, locals)`.
+    pub frames_len: usize,
+    pub frame: Option<(usize, u8)>,
+
+    /// Value read from a device during the step.
+    pub input: Option<i64>,
+}
+
+/// Recorded steps of the loaded program.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    pub steps: Vec<Step>,
+
+    /// Inputs of undone steps, the last one is undone first.
+    pub redo: Vec<Option<i64>>,
+
+    /// Input of the step, which is being redone.
+    pub redo_input: Option<i64>,
+
+    /// `true` while an undone step is executed again.
+    pub redoing: bool,
+
+    /// Value read from a device during the current step.
+    pub input: Option<i64>,
+}
+
+impl History {
+    pub fn clear(&mut self) {
+        *self = History::default();
+    }
+}
@@ -0,0 +1,15 @@
+//! Functions of the host, which programs call with `SYSCALL n` and `CALLHOST name`.
+
+use super::{HostError, Stack};
+
+/// Function of the host, it takes its arguments from the stack and pushes results onto it.
+pub type HostFn<'a> = Box<dyn FnMut(&mut Stack) -> Result<(), HostError> + 'a>;
+
+pub(crate) struct HostFunction<'a> {
+    pub name: String,
+
+    /// Number of values the function pops, they are checked before it's called.
+    pub arity: usize,
+
+    pub function: HostFn<'a>,
+}
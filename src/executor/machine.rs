@@ -1,9 +1,16 @@
-use std::{convert::TryInto, io};
+use std::{collections::HashMap, convert::TryInto, time::Instant};
 
-use super::{ExecutionError, InputError, OutputError, Registers, Stack};
+use super::{
+    device::IoDevice,
+    history::{History, Step},
+    host::HostFunction,
+    program::{is_arithmetic, Instruction, Program},
+    Coverage, ExecutionError, HostError, InputError, OutputError, PairProfile, Profile, Registers,
+    Stack,
+};
 use crate::{
-    machine_code::{Decompile, OpCode},
-    models::{Op, Value},
+    machine_code::{FrameState, MachineState, OpCode},
+    models::{Float, Op, Register, Value, Width},
 };
 
 /// Result of successful operation execution.
@@ -20,114 +27,855 @@ pub enum NextOperation {
 /// Return value of `Machine::execute` method
 type ExecutionResult = Result<NextOperation, ExecutionError>;
 
-pub struct Machine<I, O>
-where
-    I: io::BufRead,
-    O: io::Write,
-{
+/// Frame started with `ENTER`.
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    /// Position of the first local on the stack, arguments are below it.
+    base: usize,
+    locals: u8,
+}
+
+impl Frame {
+    /// Position on the stack after the last local.
+    fn top(&self) -> usize {
+        self.base + usize::from(self.locals)
+    }
+}
+
+/// Stack machine, which executes compiled programs.
+///
+/// Programs talk to devices attached to numbered ports, `INPUT` and `OUTPUT` use port 0.
+pub struct Machine<'a> {
     registers: Registers,
     stack: Stack,
-    input: I,
-    output: O,
+    floats: Stack<f64>,
+    frames: Vec<Frame>,
+    devices: HashMap<u8, Box<dyn IoDevice + 'a>>,
+
+    /// Width of the machine word, taken from the header of the executed program.
+    width: Width,
+
+    /// Counts of executed pairs of operations, `None` unless profiling is enabled.
+    pair_profile: Option<PairProfile>,
+
+    /// Counts of executed operations of the loaded program, `None` unless profiling
+    /// is enabled.
+    profile: Option<Profile>,
+
+    /// Executed operations of the loaded program, `None` unless coverage is enabled.
+    coverage: Option<Coverage>,
+
+    /// Functions of the host, `SYSCALL n` calls the function with index `n`.
+    host_functions: Vec<HostFunction<'a>>,
+
+    /// Indices of host functions imported by the executed program.
+    imports: Vec<u8>,
+
+    /// Loaded program, `None` until `load_program` is called.
+    program: Option<Program>,
+
+    /// Index of the next instruction of the program.
+    position: usize,
+
+    /// Number of executed operations, superinstructions count as two.
+    executed: u64,
+
+    /// `true` after the program executed `HALT`.
+    halted: bool,
+
+    /// Executed steps, `None` unless recording is enabled.
+    history: Option<History>,
+
+    /// `true` if `run` pauses after `BREAKPOINT`.
+    breakpoints: bool,
+
+    /// `true` if the last step executed `BREAKPOINT`.
+    at_breakpoint: bool,
 }
 
-impl<I, O> Machine<I, O>
-where
-    I: io::BufRead,
-    O: io::Write,
-{
-    /// Returns Machine instance with specified input and output streams.
-    pub fn new(input: I, output: O) -> Self {
-        Machine {
+impl<'a> Machine<'a> {
+    /// Returns Machine instance with the device attached to port 0.
+    pub fn new(device: impl IoDevice + 'a) -> Self {
+        let mut machine = Machine {
             registers: Registers::default(),
             stack: Stack::default(),
-            input: input,
-            output: output,
-        }
+            floats: Stack::default(),
+            frames: Vec::new(),
+            devices: HashMap::new(),
+            width: Width::default(),
+            pair_profile: None,
+            profile: None,
+            coverage: None,
+            host_functions: Vec::new(),
+            imports: Vec::new(),
+            program: None,
+            position: 0,
+            executed: 0,
+            halted: false,
+            history: None,
+            breakpoints: false,
+            at_breakpoint: false,
+        };
+        machine.attach(0, device);
+        machine
+    }
+
+    /// Attaches the device to the port, replacing the device which was attached to it.
+    pub fn attach(&mut self, port: u8, device: impl IoDevice + 'a) {
+        self.devices.insert(port, Box::new(device));
+    }
+
+    /// Detaches the device from the port and returns it.
+    pub fn detach(&mut self, port: u8) -> Option<Box<dyn IoDevice + 'a>> {
+        self.devices.remove(&port)
+    }
+
+    /// Registers function of the host, which takes `arity` values from the stack.
+    ///
+    /// Returns number of the function for `SYSCALL`, programs may also call it by name
+    /// with `CALLHOST`. Function with the same name is replaced and keeps its number.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than 256 functions are registered.
+    pub fn register_host_fn<F>(&mut self, name: &str, arity: usize, function: F) -> u8
+    where
+        F: FnMut(&mut Stack) -> Result<(), HostError> + 'a,
+    {
+        let host_function = HostFunction {
+            name: name.to_owned(),
+            arity,
+            function: Box::new(function),
+        };
+        let functions = &mut self.host_functions;
+        let number = match functions.iter().position(|f| f.name == name) {
+            Some(number) => {
+                functions[number] = host_function;
+                number
+            }
+            None => {
+                functions.push(host_function);
+                functions.len() - 1
+            }
+        };
+        number.try_into().expect("at most 256 host functions")
+    }
+
+    /// Enables counting of executed pairs of operations, see `pair_profile`.
+    ///
+    /// Superinstructions are not used while profiling, so that every pair is counted.
+    pub fn enable_pair_profile(&mut self) {
+        self.pair_profile.get_or_insert_with(PairProfile::default);
+    }
+
+    /// Returns counts of executed pairs, if profiling was enabled.
+    pub fn pair_profile(&self) -> Option<&PairProfile> {
+        self.pair_profile.as_ref()
+    }
+
+    /// Enables recording of executed steps, so that they can be undone with `step_back`.
+    ///
+    /// Only steps executed with `step` and `run` are recorded. Calls of host functions
+    /// and written output can't be undone, undone steps are executed again with
+    /// the recorded input and without writing the output.
+    pub fn enable_history(&mut self) {
+        self.history.get_or_insert_with(History::default);
+    }
+
+    /// Returns the integer stack.
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    /// Returns the float stack.
+    pub fn floats(&self) -> &Stack<f64> {
+        &self.floats
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Enables profiling of executed operations, see `profile`.
+    ///
+    /// Superinstructions are not used while profiling, so that every operation is counted.
+    pub fn enable_profile(&mut self) {
+        self.profile.get_or_insert_with(Profile::default);
+    }
+
+    /// Returns profile of the loaded program, if profiling was enabled.
+    ///
+    /// Execution time includes only the time spent in `run`.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Enables tracking of executed operations, see `coverage`.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::default);
+    }
+
+    /// Returns executed operations of the loaded program, if coverage was enabled.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Makes `run` pause after `BREAKPOINT`, see `at_breakpoint`.
+    ///
+    /// Without it `BREAKPOINT` does nothing.
+    pub fn enable_breakpoints(&mut self) {
+        self.breakpoints = true;
+    }
+
+    /// Returns `true` if the last step executed `BREAKPOINT`.
+    ///
+    /// Calling `run` again continues execution after the breakpoint.
+    pub fn at_breakpoint(&self) -> bool {
+        self.at_breakpoint
     }
 
     /// Executes compiled program.
+    ///
+    /// Program is decoded before execution and frequent pairs of operations are fused
+    /// into superinstructions, which doesn't change the behaviour of the program.
     pub fn execute_program(&mut self, bytes: &[u8]) -> Result<(), ExecutionError> {
-        let mut idx = 0;
-        while idx < bytes.len() {
-            let op = Op::decompile(&bytes[idx..])?;
-            self.execute(op.value)?;
-            idx += op.bytes_read;
+        self.load_program(bytes)?;
+        self.run()
+    }
+
+    /// Clears the stacks, registers and frames and unloads the program.
+    ///
+    /// Attached devices, host functions and enabled profiling are kept.
+    pub fn reset(&mut self) {
+        self.registers = Registers::default();
+        self.stack = Stack::default();
+        self.floats = Stack::default();
+        self.frames.clear();
+        self.width = Width::default();
+        self.imports.clear();
+        self.program = None;
+        self.position = 0;
+        self.executed = 0;
+        self.halted = false;
+        self.at_breakpoint = false;
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+    }
+
+    /// Loads compiled program, which is then executed with `run` or `step`.
+    pub fn load_program(&mut self, bytes: &[u8]) -> Result<(), ExecutionError> {
+        let fuse = self.pair_profile.is_none() && self.profile.is_none();
+        let program = Program::load(bytes, fuse)?;
+        self.width = program.width;
+        self.registers.set_count(program.registers);
+        self.frames.clear();
+        self.stack.set_floor(0);
+        self.imports = program
+            .imports
+            .iter()
+            .map(|name| self.resolve_host_fn(name))
+            .collect::<Result<_, _>>()?;
+        if let Some(profile) = self.pair_profile.as_mut() {
+            profile.start_program();
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            *profile = Profile::default();
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            *coverage = Coverage::default();
+        }
+        self.program = Some(program);
+        self.position = 0;
+        self.executed = 0;
+        self.halted = false;
+        self.at_breakpoint = false;
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        Ok(())
+    }
+
+    /// Executes the loaded program until it terminates or pauses at a breakpoint,
+    /// then flushes all devices.
+    pub fn run(&mut self) -> Result<(), ExecutionError> {
+        let start = Instant::now();
+        // Output written before an error is flushed as well
+        let result = loop {
+            match self.step() {
+                Ok(true) if self.breakpoints && self.at_breakpoint => break Ok(()),
+                Ok(true) => {}
+                Ok(false) => break Ok(()),
+                Err(error) => break Err(error),
+            }
+        };
+        if let Some(profile) = self.profile.as_mut() {
+            profile.add_time(start.elapsed());
+        }
+        let flushed = self.flush();
+        result.and(flushed)
+    }
+
+    /// Executes the next instruction of the loaded program.
+    ///
+    /// Returns `false` if the program has terminated or no program is loaded.
+    /// Superinstruction is executed as a single step. When recording is enabled,
+    /// failed step is undone, so that the machine keeps the state before it.
+    pub fn step(&mut self) -> Result<bool, ExecutionError> {
+        let program = match self.program.as_ref() {
+            Some(program) if !self.halted => program,
+            _ => return Ok(false),
+        };
+        let instruction = match program.instructions.get(self.position) {
+            Some(&instruction) => instruction,
+            None => {
+                return match program.error.clone() {
+                    Some(error) => Err(error.into()),
+                    None => Ok(false),
+                }
+            }
+        };
+
+        let offset = program.offset(self.position);
+        self.at_breakpoint = false;
+        let step = self.history.is_some().then(|| self.record(instruction));
+        let redo = self.history.as_mut().and_then(|history| {
+            let redo = history.redo.pop();
+            history.redoing = redo.is_some();
+            history.redo_input = redo.flatten();
+            history.input = None;
+            redo
+        });
+
+        let result = self.execute_instruction(instruction);
+        if let (Some(mut step), Some(history)) = (step, self.history.as_mut()) {
+            history.redoing = false;
+            step.input = history.input.take();
+            match result {
+                Ok(_) => history.steps.push(step),
+                Err(_) => {
+                    history.redo.extend(redo);
+                    self.undo(step);
+                }
+            }
+        }
+
+        let next = result?;
+        if let (Some(profile), Instruction::Op(op)) = (self.profile.as_mut(), instruction) {
+            profile.record(offset, op, self.stack.len());
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(offset);
+            if let Some((first, _)) = instruction.split() {
+                coverage.record(offset + OpCode::from(&first).op_len(self.width));
+            }
+        }
+        self.position += 1;
+        self.executed += instruction.op_count();
+        self.halted = matches!(next, NextOperation::None);
+        self.at_breakpoint = instruction == Instruction::Op(Op::Breakpoint);
+        Ok(!self.halted)
+    }
+
+    /// Undoes the last recorded step, returns `false` if there is no step to undo.
+    pub fn step_back(&mut self) -> bool {
+        let history = match self.history.as_mut() {
+            Some(history) => history,
+            None => return false,
+        };
+        match history.steps.pop() {
+            Some(step) => {
+                history.redo.push(step.input);
+                self.undo(step);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Undoes recorded steps until `stop` returns `true` or there are no more steps.
+    ///
+    /// The condition is checked after each undone step, returns number of undone steps.
+    pub fn reverse_continue<F>(&mut self, mut stop: F) -> usize
+    where
+        F: FnMut(&Self) -> bool,
+    {
+        let mut count = 0;
+        while self.step_back() {
+            count += 1;
+            if stop(self) {
+                break;
+            }
+        }
+        count
+    }
+
+    /// Saves the part of the state, which may be changed by the instruction.
+    fn record(&self, instruction: Instruction) -> Step {
+        let ops = match instruction.split() {
+            Some((first, second)) => vec![first, second],
+            None => match instruction {
+                Instruction::Op(op) => vec![op],
+                _ => unreachable!(),
+            },
+        };
+
+        // Operations pop at most two values, except the ones which change the frames
+        // and host functions, which may pop all available values
+        let len = self.stack.len();
+        let stack_base = match ops[0] {
+            Op::LocalSet(index) => self.local(ops[0], index).unwrap_or(len),
+            Op::Leave => self.frames.last().map_or(len, |frame| frame.base),
+            Op::Syscall(_) | Op::CallHost(_) => len - self.stack.available(),
+            _ => len.saturating_sub(2),
+        };
+        let floats_base = self.floats.len().saturating_sub(2);
+        let registers = ops.iter().flat_map(Op::registers);
+        Step {
+            position: self.position,
+            executed: self.executed,
+            stack_base,
+            stack: self.stack.values()[stack_base..].to_vec(),
+            floats_base,
+            floats: self.floats.values()[floats_base..].to_vec(),
+            registers: registers
+                .filter_map(|r| Some((r, self.registers.get(r).ok()?)))
+                .collect(),
+            frames_len: self.frames.len(),
+            frame: self.frames.last().map(|frame| (frame.base, frame.locals)),
+            input: None,
+        }
+    }
+
+    fn undo(&mut self, step: Step) {
+        self.position = step.position;
+        self.executed = step.executed;
+        self.halted = false;
+        self.at_breakpoint = false;
+        self.stack.replace_top(step.stack_base, &step.stack);
+        self.floats.replace_top(step.floats_base, &step.floats);
+        for (register, value) in step.registers {
+            self.registers.set(register, value).unwrap();
+        }
+        self.frames.truncate(step.frames_len);
+        if self.frames.len() < step.frames_len {
+            let (base, locals) = step.frame.unwrap();
+            self.frames.push(Frame { base, locals });
+        }
+        self.stack
+            .set_floor(self.frames.last().map_or(0, Frame::top));
+    }
+
+    /// Returns offset of the next operation in the loaded program, including the header.
+    pub fn pc(&self) -> Option<usize> {
+        let program = self.program.as_ref()?;
+        Some(program.offset(self.position))
+    }
+
+    /// Returns number of operations executed since the program was loaded.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Returns current state of the machine.
+    pub fn snapshot(&self) -> MachineState {
+        let frames = self.frames.iter();
+        MachineState {
+            width: self.width,
+            pc: self.pc().unwrap_or(0),
+            executed: self.executed,
+            halted: self.halted,
+            registers: self.registers.values().to_vec(),
+            stack: self.stack.values().to_vec(),
+            floats: self.floats.values().to_vec(),
+            frames: frames
+                .map(|frame| FrameState {
+                    base: frame.base,
+                    locals: frame.locals,
+                })
+                .collect(),
+        }
+    }
+
+    /// Restores state of the machine, which executes the loaded program.
+    ///
+    /// The state must be taken while executing the same program, the machine is not
+    /// changed if the state doesn't match the program. Without a program, the state
+    /// must be taken while executing operations with `execute`, its program counter is 0.
+    pub fn restore(&mut self, state: &MachineState) -> Result<(), ExecutionError> {
+        let wrong = |message: &str| Err(ExecutionError::WrongState(message.to_owned()));
+        let (width, registers) = match self.program.as_ref() {
+            Some(program) => (program.width, usize::from(program.registers)),
+            None if state.pc == 0 => (self.width, self.registers.values().len()),
+            None => return wrong("no program is loaded"),
+        };
+        if state.width != width {
+            return wrong("word width differs from the program");
+        }
+        if state.registers.len() != registers {
+            return wrong("number of registers differs from the program");
+        }
+        if !state
+            .registers
+            .iter()
+            .chain(state.stack.iter())
+            .all(|&x| state.width.contains(x))
+        {
+            return wrong("value doesn't fit into the machine word");
+        }
+        let mut floor = 0;
+        for frame in state.frames.iter() {
+            let top = frame.base + usize::from(frame.locals);
+            if frame.base < floor || top > state.stack.len() {
+                return wrong("frames don't fit on the stack");
+            }
+            floor = top;
+        }
+        let position = match self
+            .program
+            .as_mut()
+            .map(|program| program.position(state.pc))
+        {
+            Some(Some(position)) => position,
+            Some(None) => return wrong("program counter is not at an operation of the program"),
+            None => 0,
+        };
+
+        self.position = position;
+        self.executed = state.executed;
+        self.halted = state.halted;
+        self.at_breakpoint = false;
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        for (index, &value) in state.registers.iter().enumerate() {
+            let register = Register::new(index as u8).expect("registers of the program");
+            self.registers.set(register, value)?;
+        }
+        self.stack.replace(state.stack.clone(), floor);
+        self.floats.replace(state.floats.clone(), 0);
+        self.frames = state
+            .frames
+            .iter()
+            .map(|frame| Frame {
+                base: frame.base,
+                locals: frame.locals,
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Flushes all devices.
+    pub fn flush(&mut self) -> Result<(), ExecutionError> {
+        for device in self.devices.values_mut() {
+            device.flush().map_err(OutputError::from)?;
         }
         Ok(())
     }
 
+    fn resolve_host_fn(&self, name: &str) -> Result<u8, ExecutionError> {
+        match self.host_functions.iter().position(|f| f.name == name) {
+            Some(number) => Ok(number as u8),
+            None => Err(ExecutionError::UnknownHostFunction(name.to_owned())),
+        }
+    }
+
+    fn execute_instruction(&mut self, instruction: Instruction) -> ExecutionResult {
+        match instruction {
+            Instruction::Op(op) => {
+                if let Some(profile) = self.pair_profile.as_mut() {
+                    profile.record(OpCode::from(&op));
+                }
+                return self.execute(op);
+            }
+            Instruction::PushValueAndApply(value, op) => {
+                let a = self.stack.pop()?;
+                self.stack.push(apply(op, a, value, self.width)?);
+            }
+            Instruction::PushRegisterAndApply(r, op) => {
+                let a = self.stack.pop()?;
+                let b = self.registers.get(r)?;
+                self.stack.push(apply(op, a, b, self.width)?);
+            }
+            Instruction::PushRegisters(a, b) => {
+                self.stack.push(self.registers.get(a)?);
+                self.stack.push(self.registers.get(b)?);
+            }
+            Instruction::SetRegister(r, value) => self.registers.set(r, value)?,
+        }
+        let offset = instruction.op_len(self.width).try_into().unwrap();
+        Ok(NextOperation::Offset(offset))
+    }
+
+    /// Executes single operation, using the word width of the last executed program.
     pub fn execute(&mut self, op: Op) -> ExecutionResult {
         use Op::*;
 
+        let width = self.width;
         match op {
-            Add => self.binary_fn(|a, b| a + b)?,
-            Sub => self.binary_fn(|a, b| a - b)?,
-            Mul => self.binary_fn(|a, b| a * b)?,
-            Div => self.binary_fn(|a, b| a / b)?,
-            Mod => self.binary_fn(|a, b| a % b)?,
-            Input => self.input()?,
-            Output => self.output()?,
+            Add | Sub | Mul | Div | Mod | Shl => self.binary_fn(|a, b| apply(op, a, b, width))?,
+            Input => self.input(0)?,
+            Output => self.output(0)?,
+            In(port) => self.input(port)?,
+            Out(port) => self.output(port)?,
             Halt => return Ok(NextOperation::None),
             PushValue(Value(v)) => self.stack.push(v),
-            PushRegister(r) => self.stack.push(self.registers[r]),
-            PopRegister(r) => self.registers[r] = self.stack.pop()?,
+            PushRegister(r) => self.stack.push(self.registers.get(r)?),
+            PopRegister(r) => {
+                // Check the register first, so that failed operation keeps the stack
+                self.registers.get(r)?;
+                let value = self.stack.pop()?;
+                self.registers.set(r, value)?;
+            }
+            MovRegister(r, source) => self.registers.set(r, self.registers.get(source)?)?,
+            MovValue(r, Value(v)) => self.registers.set(r, v)?,
+            Inc(r) => self.register_fn(r, |x| width.wrap(x.wrapping_add(1)))?,
+            Dec(r) => self.register_fn(r, |x| width.wrap(x.wrapping_sub(1)))?,
+            AddValue(r, Value(v)) => self.register_fn(r, |x| width.wrap(x.wrapping_add(v)))?,
+            PushFloat(Float(v)) => self.floats.push(v),
+            FAdd => self.float_fn(|a, b| a + b)?,
+            FSub => self.float_fn(|a, b| a - b)?,
+            FMul => self.float_fn(|a, b| a * b)?,
+            FDiv => self.float_fn(|a, b| a / b)?,
+            FSqrt => {
+                let x = self.pop_float()?;
+                self.floats.push(x.sqrt());
+            }
+            IToF => {
+                let x = self.stack.pop()?;
+                self.floats.push(x as f64);
+            }
+            FToI => {
+                // Casts saturate and turn NaN into 0
+                let x = self.pop_float()? as i64;
+                self.stack.push(x.clamp(width.min(), width.max()));
+            }
+            FOutput => {
+                let x = self.pop_float()?;
+                if !self.is_redoing() {
+                    let device = self.device(0)?;
+                    for byte in format!("{}\n", Float(x)).bytes() {
+                        device.write_byte(byte).map_err(OutputError::from)?;
+                    }
+                }
+            }
+            Enter(locals) => {
+                let frame = Frame {
+                    base: self.stack.len(),
+                    locals,
+                };
+                for _ in 0..locals {
+                    self.stack.push(0);
+                }
+                self.stack.set_floor(frame.top());
+                self.frames.push(frame);
+            }
+            Leave => {
+                let frame = self.frames.pop().ok_or(ExecutionError::NoFrame { op })?;
+                self.stack.remove(frame.base..frame.top());
+                self.stack
+                    .set_floor(self.frames.last().map_or(0, Frame::top));
+            }
+            LocalGet(index) => {
+                let position = self.local(op, index)?;
+                let value = self
+                    .stack
+                    .get(position)
+                    .expect("locals are below the floor");
+                self.stack.push(value);
+            }
+            LocalSet(index) => {
+                let position = self.local(op, index)?;
+                let value = self.stack.pop()?;
+                *self
+                    .stack
+                    .get_mut(position)
+                    .expect("locals are below the floor") = value;
+            }
+            Arg(index) => {
+                let frame = *self.frames.last().ok_or(ExecutionError::NoFrame { op })?;
+                let outer_top = self.frames.iter().rev().nth(1).map_or(0, Frame::top);
+                let count = frame.base - outer_top;
+                if usize::from(index) >= count {
+                    return Err(ExecutionError::ArgumentOutOfBounds { index, count });
+                }
+                let position = frame.base - 1 - usize::from(index);
+                let value = self
+                    .stack
+                    .get(position)
+                    .expect("arguments are below the floor");
+                self.stack.push(value);
+            }
+            Syscall(number) => self.call_host(number)?,
+            CallHost(import) => {
+                let number = self.imports.get(usize::from(import)).copied();
+                self.call_host(number.ok_or(ExecutionError::UnknownImport(import))?)?
+            }
+            Assert => {
+                let value = self.stack.pop()?;
+                if value == 0 {
+                    return Err(self.assertion_failed(op, vec![value]));
+                }
+            }
+            AssertEq => {
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                if a != b {
+                    return Err(self.assertion_failed(op, vec![a, b]));
+                }
+            }
+            Breakpoint => {}
         };
 
-        let offset = OpCode::from(&op).op_len().try_into().unwrap();
+        let offset = OpCode::from(&op).op_len(width).try_into().unwrap();
         Ok(NextOperation::Offset(offset))
     }
 
+    /// Returns error of the failed assertion at the next operation of the loaded program.
+    fn assertion_failed(&self, op: Op, values: Vec<i64>) -> ExecutionError {
+        ExecutionError::AssertionFailed {
+            op,
+            offset: self.pc().unwrap_or_default(),
+            values,
+        }
+    }
+
     fn binary_fn<F>(&mut self, f: F) -> Result<(), ExecutionError>
     where
-        F: FnOnce(i32, i32) -> i32,
+        F: FnOnce(i64, i64) -> Result<i64, ExecutionError>,
     {
         let b = self.stack.pop()?;
         let a = self.stack.pop()?;
-        let result = f(a, b);
+        let result = f(a, b)?;
         self.stack.push(result);
         Ok(())
     }
 
-    fn input(&mut self) -> Result<(), ExecutionError> {
-        let mut buf = String::new();
-        print!("Enter number: ");
-        let input = loop {
-            self.input.read_line(&mut buf).map_err(InputError::from)?;
-            if let Ok(x) = buf.parse() {
-                break x;
-            } else {
-                print!("Try again: ");
-            }
+    fn call_host(&mut self, number: u8) -> Result<(), ExecutionError> {
+        let host_function = self.host_functions.get_mut(usize::from(number));
+        let host_function = host_function.ok_or(ExecutionError::UnknownSyscall(number))?;
+        if self.stack.available() < host_function.arity {
+            return Err(ExecutionError::StackUnderflow);
+        }
+        (host_function.function)(&mut self.stack).map_err(|inner| ExecutionError::HostError {
+            name: host_function.name.clone(),
+            inner,
+        })
+    }
+
+    /// Returns position of the local of the current frame on the stack.
+    fn local(&self, op: Op, index: u8) -> Result<usize, ExecutionError> {
+        let frame = self.frames.last().ok_or(ExecutionError::NoFrame { op })?;
+        if index < frame.locals {
+            Ok(frame.base + usize::from(index))
+        } else {
+            Err(ExecutionError::LocalOutOfBounds {
+                index,
+                count: frame.locals,
+            })
+        }
+    }
+
+    fn register_fn<F>(&mut self, register: Register, f: F) -> Result<(), ExecutionError>
+    where
+        F: FnOnce(i64) -> i64,
+    {
+        let value = self.registers.get(register)?;
+        self.registers.set(register, f(value))
+    }
+
+    fn float_fn<F>(&mut self, f: F) -> Result<(), ExecutionError>
+    where
+        F: FnOnce(f64, f64) -> f64,
+    {
+        let b = self.pop_float()?;
+        let a = self.pop_float()?;
+        self.floats.push(f(a, b));
+        Ok(())
+    }
+
+    fn pop_float(&mut self) -> Result<f64, ExecutionError> {
+        self.floats
+            .pop()
+            .map_err(|_| ExecutionError::FloatStackUnderflow)
+    }
+
+    fn device(&mut self, port: u8) -> Result<&mut (dyn IoDevice + 'a), ExecutionError> {
+        match self.devices.get_mut(&port) {
+            Some(device) => Ok(device.as_mut()),
+            None => Err(ExecutionError::NoDevice(port)),
+        }
+    }
+
+    /// Returns `true` while an undone step is executed again.
+    fn is_redoing(&self) -> bool {
+        self.history.as_ref().is_some_and(|history| history.redoing)
+    }
+
+    fn input(&mut self, port: u8) -> Result<(), ExecutionError> {
+        let width = self.width;
+        let recorded = self.history.as_mut().and_then(|h| h.redo_input.take());
+        let value = match recorded {
+            Some(value) => value,
+            None => self
+                .device(port)?
+                .read_integer()
+                .map_err(InputError::from)?,
         };
-        self.stack.push(input);
+        if !width.contains(value) {
+            return Err(ExecutionError::InputOutOfRange(value));
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.input = Some(value);
+        }
+        self.stack.push(value);
         Ok(())
     }
 
-    fn output(&mut self) -> Result<(), ExecutionError> {
+    fn output(&mut self, port: u8) -> Result<(), ExecutionError> {
+        // Check the device first, so that failed operation keeps the stack
+        self.device(port)?;
         let value = self.stack.pop()?;
-        writeln!(self.output, "{}", value).map_err(OutputError::from)?;
+        if self.is_redoing() {
+            return Ok(());
+        }
+        let device = self.device(port)?;
+        device.write_integer(value).map_err(OutputError::from)?;
         Ok(())
     }
 }
 
+/// Computes result of an arithmetic operation, which wraps around on overflow.
+fn apply(op: Op, a: i64, b: i64, width: Width) -> Result<i64, ExecutionError> {
+    debug_assert!(is_arithmetic(op));
+
+    if b == 0 && matches!(op, Op::Div | Op::Mod) {
+        return Err(ExecutionError::DivisionByZero { op });
+    }
+    let result = match op {
+        Op::Add => a.wrapping_add(b),
+        Op::Sub => a.wrapping_sub(b),
+        Op::Mul => a.wrapping_mul(b),
+        Op::Div => a.wrapping_div(b),
+        Op::Mod => a.wrapping_rem(b),
+        _ => a.wrapping_shl(b as u32 % width.bits()),
+    };
+    Ok(width.wrap(result))
+}
+
 #[cfg(test)]
 mod test {
-    use std::io;
-
     use super::*;
-    use crate::models::*;
+    use crate::{
+        executor::{Event, MemoryDevice, ScriptedDevice},
+        models::*,
+    };
 
-    fn default_machine() -> Machine<impl io::BufRead, impl io::Write> {
-        Machine {
-            registers: Registers::default(),
-            stack: Stack::default(),
-            input: io::BufReader::new(io::stdin()),
-            output: io::BufWriter::new(io::stdout()),
-        }
+    fn default_machine() -> Machine<'static> {
+        Machine::new(MemoryDevice::default())
     }
 
-    fn execute(program: &[Op]) -> Option<i32> {
+    fn execute(program: &[Op]) -> Option<i64> {
         let mut machine = default_machine();
         for &op in program.iter() {
             machine.execute(op).unwrap();
@@ -163,4 +911,544 @@ mod test {
         ];
         assert_eq!(execute(&program[..]).unwrap(), 1);
     }
+
+    type Pairs = Vec<((OpCode, OpCode), u64)>;
+
+    fn run(source: &str, profile: bool) -> (String, Option<Pairs>) {
+        use crate::machine_code::Compile;
+
+        let mut program = Vec::new();
+        source
+            .parse::<Assembly>()
+            .unwrap()
+            .compile(&mut program)
+            .unwrap();
+        let mut device = MemoryDevice::default();
+        let mut machine = Machine::new(&mut device);
+        if profile {
+            machine.enable_pair_profile();
+        }
+        machine.execute_program(&program).unwrap();
+        let pairs = machine.pair_profile().map(PairProfile::hottest);
+        drop(machine);
+        (String::from_utf8(device.into_output()).unwrap(), pairs)
+    }
+
+    #[test]
+    fn superinstructions() {
+        let source = "
+            PUSH 6
+            POP A
+            PUSH 7
+            POP B
+            PUSH A
+            PUSH B
+            MUL
+            PUSH 2
+            SUB
+            PUSH A
+            DIV
+            OUTPUT
+            HALT
+            OUTPUT
+        ";
+        let (fused, _) = run(source, false);
+        let (plain, pairs) = run(source, true);
+        assert_eq!(fused, "6\n");
+        assert_eq!(fused, plain);
+
+        let pairs = pairs.unwrap();
+        assert_eq!(pairs.len(), 11);
+        assert_eq!(pairs[0], ((OpCode::PushValue, OpCode::PopRegister), 2));
+        assert!(pairs[1..].iter().all(|&(_, count)| count == 1));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        use crate::machine_code::Compile;
+
+        let mut machine = default_machine();
+        for op in [Op::Div, Op::Mod].iter() {
+            machine.execute(Op::PushValue(1.into())).unwrap();
+            machine.execute(Op::PushValue(0.into())).unwrap();
+            let error = machine.execute(*op).err().unwrap();
+            assert_eq!(error.to_string(), format!("{} by zero", op));
+        }
+
+        // PUSH 0 and PUSH A are fused with the division, unless pairs are profiled
+        for source in [
+            "PUSH 7\nPUSH 0\nDIV\nOUTPUT",
+            "PUSH 7\nPUSH 0\nMOD\nOUTPUT",
+            "PUSH 7\nPUSH A\nDIV\nOUTPUT",
+            "PUSH 7\nPUSH A\nMOD\nOUTPUT",
+        ]
+        .iter()
+        {
+            let mut program = Vec::new();
+            source
+                .parse::<Assembly>()
+                .unwrap()
+                .compile(&mut program)
+                .unwrap();
+            for &profile in [false, true].iter() {
+                let mut machine = default_machine();
+                if profile {
+                    machine.enable_pair_profile();
+                }
+                let result = machine.execute_program(&program);
+                assert!(
+                    matches!(result, Err(ExecutionError::DivisionByZero { .. })),
+                    "{}",
+                    source
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        let mut device = MemoryDevice::default();
+        let mut machine = Machine::new(&mut device);
+        let result = machine.execute_program(&[8, 0, 0, 0, 1, 6, 0xff]);
+        assert!(matches!(result, Err(ExecutionError::OpReadError { .. })));
+        drop(machine);
+        assert_eq!(device.output(), b"1\n");
+
+        let mut machine = default_machine();
+        machine.execute_program(&[7, 0xff]).unwrap();
+    }
+
+    #[test]
+    fn word_width() {
+        let source = "
+            PUSH 0x7FFF_FFFF
+            PUSH 1
+            ADD
+            OUTPUT
+            PUSH 3
+            PUSH 33
+            SHL
+            OUTPUT
+        ";
+        let (narrow, _) = run(source, false);
+        assert_eq!(narrow, "-2147483648\n6\n");
+
+        let (wide, _) = run(&format!(".width 64\n{}", source), false);
+        assert_eq!(wide, "2147483648\n25769803776\n");
+
+        let (wrapped, _) = run(
+            ".width 64\nPUSH 0x7FFF_FFFF_FFFF_FFFF\nPUSH 2\nMUL\nOUTPUT",
+            false,
+        );
+        assert_eq!(wrapped, "-2\n");
+    }
+
+    #[test]
+    fn floats() {
+        let source = "
+            PUSHF 2
+            FSQRT
+            PUSHF 2
+            FSQRT
+            FMUL
+            FOUTPUT
+            PUSH 7
+            ITOF
+            PUSHF 2
+            FDIV
+            FTOI
+            OUTPUT
+            PUSHF -1e300
+            FTOI
+            OUTPUT
+            PUSHF 1
+            PUSHF 0
+            FDIV
+            FOUTPUT
+        ";
+        let (output, _) = run(source, false);
+        assert_eq!(output, "2.0000000000000004\n3\n-2147483648\ninf\n");
+    }
+
+    #[test]
+    fn float_stack_underflow() {
+        let mut machine = default_machine();
+        machine.execute(Op::PushValue(1.into())).unwrap();
+        assert!(matches!(
+            machine.execute(Op::FOutput),
+            Err(ExecutionError::FloatStackUnderflow)
+        ));
+    }
+
+    #[test]
+    fn register_operations() {
+        let source = "
+            MOV A, 0x7FFF_FFFE
+            INC A
+            MOV B, A
+            INC B
+            DEC A
+            ADD A, 10
+            PUSH A
+            OUTPUT
+            PUSH B
+            OUTPUT
+        ";
+        let (output, _) = run(source, false);
+        assert_eq!(output, "-2147483640\n-2147483648\n");
+    }
+
+    #[test]
+    fn register_count() {
+        let (output, _) = run(
+            ".registers 16\nMOV R15, 7\nMOV R14, R15\nPUSH R14\nOUTPUT",
+            false,
+        );
+        assert_eq!(output, "7\n");
+
+        let mut machine = default_machine();
+        machine.execute(Op::PushValue(1.into())).unwrap();
+        let result = machine.execute(Op::PopRegister(Register::new(4).unwrap()));
+        assert!(matches!(
+            result,
+            Err(ExecutionError::MissingRegister { count: 4, .. })
+        ));
+        assert_eq!(machine.stack.pop().unwrap(), 1);
+    }
+
+    #[test]
+    fn frames() {
+        let source = "
+            PUSH 10
+            PUSH 3
+            ENTER 2
+            ARG 1
+            ARG 0
+            SUB
+            LOCAL.SET 1
+            LOCAL.GET 1
+            LOCAL.GET 0
+            ADD
+            LEAVE
+            OUTPUT
+            SUB
+            OUTPUT
+        ";
+        let (output, _) = run(source, false);
+        assert_eq!(output, "7\n7\n");
+    }
+
+    #[test]
+    fn frame_errors() {
+        let mut machine = default_machine();
+        assert!(matches!(
+            machine.execute(Op::LocalGet(0)),
+            Err(ExecutionError::NoFrame {
+                op: Op::LocalGet(0)
+            })
+        ));
+        assert!(matches!(
+            machine.execute(Op::Leave),
+            Err(ExecutionError::NoFrame { op: Op::Leave })
+        ));
+
+        machine.execute(Op::PushValue(1.into())).unwrap();
+        machine.execute(Op::Enter(0)).unwrap();
+        machine.execute(Op::PushValue(2.into())).unwrap();
+        machine.execute(Op::Enter(1)).unwrap();
+        assert!(matches!(
+            machine.execute(Op::LocalSet(1)),
+            Err(ExecutionError::LocalOutOfBounds { index: 1, count: 1 })
+        ));
+        assert!(matches!(
+            machine.execute(Op::Arg(1)),
+            Err(ExecutionError::ArgumentOutOfBounds { index: 1, count: 1 })
+        ));
+        // Locals and arguments can't be popped
+        assert!(matches!(
+            machine.execute(Op::Output),
+            Err(ExecutionError::StackUnderflow)
+        ));
+
+        machine.execute(Op::Leave).unwrap();
+        assert_eq!(machine.stack.pop().unwrap(), 2);
+        assert!(machine.stack.pop().is_err());
+        machine.execute(Op::Leave).unwrap();
+        assert_eq!(machine.stack.pop().unwrap(), 1);
+    }
+
+    fn compile(source: &str) -> Vec<u8> {
+        use crate::machine_code::Compile;
+
+        let mut program = Vec::new();
+        let assembly: Assembly = source.parse().unwrap();
+        assembly.compile(&mut program).unwrap();
+        program
+    }
+
+    #[test]
+    fn host_functions() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut machine = default_machine();
+        let double = machine.register_host_fn("double", 1, |stack| {
+            let x = stack.pop()?;
+            stack.push(x * 2);
+            Ok(())
+        });
+        let logged = Rc::clone(&log);
+        let log_number = machine.register_host_fn("log", 1, move |stack| {
+            logged.borrow_mut().push(stack.pop()?);
+            Ok(())
+        });
+        machine.register_host_fn("fail", 0, |_| Err(HostError::Failed("no reason".into())));
+        assert_eq!((double, log_number), (0, 1));
+
+        let program = compile("PUSH 21\nCALLHOST double\nSYSCALL 1\nPUSH 1\nCALLHOST log\nHALT");
+        machine.execute_program(&program).unwrap();
+        assert_eq!(*log.borrow(), vec![42, 1]);
+
+        let result = machine.execute_program(&compile("CALLHOST log\nCALLHOST missing"));
+        assert!(matches!(
+            result,
+            Err(ExecutionError::UnknownHostFunction(name)) if name == "missing"
+        ));
+        let result = machine.execute_program(&compile("CALLHOST log"));
+        assert!(matches!(result, Err(ExecutionError::StackUnderflow)));
+        let result = machine.execute_program(&compile("SYSCALL 3"));
+        assert!(matches!(result, Err(ExecutionError::UnknownSyscall(3))));
+        let result = machine.execute_program(&compile("CALLHOST fail"));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Host function fail failed: no reason"
+        );
+        assert_eq!(log.borrow().len(), 2);
+    }
+
+    #[test]
+    fn ports() {
+        let program = compile("INPUT\nIN 1\nADD\nOUT 1\nPUSH 2\nOUTPUT\nPUSHF 0.5\nFOUTPUT");
+        let mut console = MemoryDevice::new("40\n");
+        let mut sensor = ScriptedDevice::new(vec![Event::ReadInteger(2), Event::WriteInteger(42)]);
+        let mut machine = Machine::new(&mut console);
+        machine.attach(1, &mut sensor);
+        machine.execute_program(&program).unwrap();
+        drop(machine);
+        assert_eq!(console.output(), b"2\n0.5\n");
+        assert!(sensor.remaining().is_empty());
+
+        let mut machine = default_machine();
+        let result = machine.execute_program(&compile("PUSH 1\nOUT 3"));
+        assert!(matches!(result, Err(ExecutionError::NoDevice(3))));
+        assert_eq!(machine.stack.pop().unwrap(), 1);
+
+        let mut machine = Machine::new(MemoryDevice::new("4294967296\nx\n"));
+        let result = machine.execute_program(&compile("INPUT"));
+        assert!(matches!(
+            result,
+            Err(ExecutionError::InputOutOfRange(4294967296))
+        ));
+        let result = machine.execute_program(&compile("INPUT"));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Can't read from the input stream: expected an integer, got: x"
+        );
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        use crate::machine_code::{Compile, Decompile};
+
+        let source = "
+            .width 64
+            PUSH 5
+            ENTER 1
+            ARG 0
+            PUSH 3
+            MUL
+            LOCAL.SET 0
+            PUSH 2
+            POP A
+            PUSHF 1.5
+            LOCAL.GET 0
+            OUTPUT
+            LEAVE
+            OUTPUT
+            HALT
+        ";
+        let program = compile(source);
+        // Pairs are not fused while profiling, so the state is taken after PUSH 2
+        let mut machine = default_machine();
+        machine.enable_pair_profile();
+        machine.load_program(&program).unwrap();
+        for _ in 0..7 {
+            assert!(machine.step().unwrap());
+        }
+        let state = machine.snapshot();
+        assert_eq!(state.executed, 7);
+        assert_eq!(state.stack, vec![5, 15, 2]);
+        assert_eq!(state.frames, vec![FrameState { base: 1, locals: 1 }]);
+        let mut bytes = Vec::new();
+        state.compile(&mut bytes).unwrap();
+        let state = MachineState::decompile(&bytes).unwrap().value;
+        machine.run().unwrap();
+
+        // Superinstruction PUSH 2, POP A is split, since the state points inside it
+        let mut device = MemoryDevice::default();
+        let mut restored = Machine::new(&mut device);
+        restored.load_program(&program).unwrap();
+        restored.restore(&state).unwrap();
+        assert_eq!(restored.snapshot(), state);
+        restored.run().unwrap();
+        assert_eq!(restored.snapshot(), machine.snapshot());
+        assert!(!restored.step().unwrap());
+        drop(restored);
+        assert_eq!(device.output(), b"15\n5\n");
+
+        let mut other = default_machine();
+        let error = other.restore(&state).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Can't restore the machine state: no program is loaded"
+        );
+        other.load_program(&compile("PUSH 1\nHALT")).unwrap();
+        assert!(matches!(
+            other.restore(&state),
+            Err(ExecutionError::WrongState(_))
+        ));
+        let wrong_pc = MachineState {
+            pc: 1,
+            registers: vec![0; 4],
+            ..MachineState::default()
+        };
+        assert!(matches!(
+            other.restore(&wrong_pc),
+            Err(ExecutionError::WrongState(_))
+        ));
+    }
+
+    #[test]
+    fn step_back() {
+        let source = "
+            INPUT
+            ENTER 1
+            ARG 0
+            LOCAL.SET 0
+            PUSH 3
+            POP B
+            PUSHF 0.5
+            FOUTPUT
+            LOCAL.GET 0
+            PUSH B
+            MUL
+            LEAVE
+            OUTPUT
+            INPUT
+            OUTPUT
+            HALT
+        ";
+        let mut device = MemoryDevice::new("7\n8\n");
+        let mut machine = Machine::new(&mut device);
+        machine.enable_history();
+        machine.load_program(&compile(source)).unwrap();
+        let mut states = vec![machine.snapshot()];
+        while machine.step().unwrap() {
+            states.push(machine.snapshot());
+        }
+        states.push(machine.snapshot());
+        assert_eq!(machine.stack().values(), &[7]);
+
+        while machine.step_back() {
+            states.pop();
+            assert_eq!(machine.snapshot(), *states.last().unwrap());
+        }
+        assert_eq!(states.len(), 1);
+        machine.run().unwrap();
+        assert_eq!(machine.registers().values(), &[0, 3, 0, 0]);
+        assert_eq!(machine.reverse_continue(|m| m.stack().len() == 3), 5);
+        assert_eq!(machine.stack().values(), &[7, 7, 21]);
+        drop(machine);
+        assert_eq!(device.output(), b"0.5\n21\n8\n");
+    }
+
+    #[test]
+    fn rewind_stack_underflow() {
+        let program = compile("PUSH 1\nPUSH 2\nADD\nOUTPUT\nADD\nHALT");
+        let mut machine = default_machine();
+        machine.enable_history();
+        machine.load_program(&program).unwrap();
+        let result = machine.run();
+        assert!(matches!(result, Err(ExecutionError::StackUnderflow)));
+        assert_eq!(machine.pc(), Some(program.len() - 2));
+
+        // Failed step is undone, rewinding stops before the operation, which emptied the stack
+        machine.reverse_continue(|m| !m.stack().is_empty());
+        assert_eq!(machine.pc(), Some(program.len() - 3));
+        assert_eq!(machine.stack().values(), &[3]);
+        assert_eq!(machine.reverse_continue(|_| false), 2);
+        assert!(machine.stack().is_empty());
+    }
+
+    #[test]
+    fn coverage() {
+        let assembly: Assembly = "PUSH 1\nPUSH 2\nADD\nOUTPUT\nHALT\nOUTPUT".parse().unwrap();
+        let mut program = Vec::new();
+        let source_map = assembly.compile_with_source_map(&mut program).unwrap();
+        let mut machine = default_machine();
+        machine.enable_coverage();
+        machine.execute_program(&program).unwrap();
+
+        // PUSH 2 and ADD are fused, but both are covered
+        let lines = machine.coverage().unwrap().lines(&source_map);
+        let counts: Vec<_> = (0..6).map(|line| lines.count(None, line)).collect();
+        assert_eq!(
+            counts,
+            vec![Some(1), Some(1), Some(1), Some(1), Some(1), Some(0)]
+        );
+    }
+
+    #[test]
+    fn assertions() {
+        let program = compile("PUSH 1\nASSERT\nPUSH 2\nPUSH 2\nASSERTEQ\nPUSH 3\nPUSH 4\nASSERTEQ");
+        let mut machine = default_machine();
+        let error = machine.execute_program(&program).unwrap_err();
+        let offset = program.len() - 1;
+        assert!(matches!(
+            &error,
+            ExecutionError::AssertionFailed { op: Op::AssertEq, offset: o, values }
+                if *o == offset && values == &[3, 4]
+        ));
+        assert_eq!(
+            error.to_string(),
+            format!("ASSERTEQ failed at offset {} with values [3, 4]", offset)
+        );
+
+        let result = machine.execute_program(&compile("PUSH 0\nASSERT"));
+        assert!(matches!(
+            result,
+            Err(ExecutionError::AssertionFailed { op: Op::Assert, .. })
+        ));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let program = compile("PUSH 1\nBREAKPOINT\nPUSH 2\nBREAKPOINT\nADD\nOUTPUT");
+        let mut device = MemoryDevice::default();
+        let mut machine = Machine::new(&mut device);
+        machine.execute_program(&program).unwrap();
+        assert!(!machine.at_breakpoint());
+
+        machine.enable_breakpoints();
+        machine.load_program(&program).unwrap();
+        machine.run().unwrap();
+        assert!(machine.at_breakpoint());
+        assert_eq!(machine.stack().values(), &[1]);
+        machine.run().unwrap();
+        assert_eq!(machine.stack().values(), &[1, 2]);
+        machine.run().unwrap();
+        assert!(!machine.at_breakpoint());
+        drop(machine);
+        assert_eq!(device.output(), b"3\n3\n");
+    }
 }
@@ -1,9 +1,23 @@
+mod coverage;
+mod device;
 mod error;
+mod history;
+mod host;
 mod machine;
+mod pair_profile;
+mod profile;
+mod program;
 mod registers;
+mod replay;
 mod stack;
 
+pub use coverage::{Coverage, LineCoverage};
+pub use device::{Event, IoDevice, MemoryDevice, RecordingDevice, ScriptedDevice, StdioDevice};
 pub use error::*;
+pub use host::HostFn;
 pub use machine::Machine;
+pub use pair_profile::PairProfile;
+pub use profile::Profile;
 pub use registers::Registers;
+pub use replay::{read_replay, write_replay};
 pub use stack::Stack;
@@ -0,0 +1,90 @@
+use std::{collections::HashMap, fmt};
+
+use crate::machine_code::OpCode;
+
+/// Counts of executed pairs of adjacent operations.
+///
+/// Shows which pairs are worth fusing into superinstructions.
+#[derive(Default, Debug)]
+pub struct PairProfile {
+    counts: HashMap<(OpCode, OpCode), u64>,
+    previous: Option<OpCode>,
+}
+
+impl PairProfile {
+    /// Records execution of an operation.
+    pub(crate) fn record(&mut self, op_code: OpCode) {
+        if let Some(previous) = self.previous {
+            *self.counts.entry((previous, op_code)).or_default() += 1;
+        }
+        self.previous = Some(op_code);
+    }
+
+    /// Forgets the previous operation, so that programs don't form pairs with each other.
+    pub(crate) fn start_program(&mut self) {
+        self.previous = None;
+    }
+
+    /// Returns pairs with their counts, most frequent first.
+    pub fn hottest(&self) -> Vec<((OpCode, OpCode), u64)> {
+        let mut pairs: Vec<_> = self.counts.iter().map(|(&k, &v)| (k, v)).collect();
+        pairs.sort_by_key(|&((a, b), count)| (std::cmp::Reverse(count), a as u8, b as u8));
+        pairs
+    }
+}
+
+/// Returns mnemonic of the op code with placeholders for the arguments.
+pub(crate) fn name(op_code: OpCode) -> &'static str {
+    use OpCode::*;
+
+    match op_code {
+        Add => "ADD",
+        Sub => "SUB",
+        Mul => "MUL",
+        Div => "DIV",
+        Mod => "MOD",
+        Shl => "SHL",
+        Input => "INPUT",
+        Output => "OUTPUT",
+        Halt => "HALT",
+        PushValue => "PUSH <value>",
+        PushRegister => "PUSH <register>",
+        PopRegister => "POP <register>",
+        PushFloat => "PUSHF <value>",
+        FAdd => "FADD",
+        FSub => "FSUB",
+        FMul => "FMUL",
+        FDiv => "FDIV",
+        FSqrt => "FSQRT",
+        IToF => "ITOF",
+        FToI => "FTOI",
+        FOutput => "FOUTPUT",
+        MovRegister => "MOV <register>, <register>",
+        MovValue => "MOV <register>, <value>",
+        Inc => "INC <register>",
+        Dec => "DEC <register>",
+        AddValue => "ADD <register>, <value>",
+        Enter => "ENTER <n>",
+        Leave => "LEAVE",
+        LocalGet => "LOCAL.GET <i>",
+        LocalSet => "LOCAL.SET <i>",
+        Arg => "ARG <i>",
+        Syscall => "SYSCALL <n>",
+        CallHost => "CALLHOST <name>",
+        In => "IN <port>",
+        Out => "OUT <port>",
+        Assert => "ASSERT",
+        AssertEq => "ASSERTEQ",
+        Breakpoint => "BREAKPOINT",
+    }
+}
+
+impl fmt::Display for PairProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>10}  pair", "count")?;
+        for ((a, b), count) in self.hottest() {
+            writeln!(f, "{:>10}  {}; {}", count, name(a), name(b))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Instruction-level profile of the executed program.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    time::Duration,
+};
+
+use super::pair_profile::name;
+use crate::{
+    json::Json,
+    machine_code::{OpCode, SourceMap},
+    models::Op,
+};
+
+/// Counts of executed operations, maximum depth of the stack and execution time.
+#[derive(Default, Debug)]
+pub struct Profile {
+    /// Operation at each executed offset and number of its executions.
+    offsets: BTreeMap<usize, (Op, u64)>,
+
+    max_stack_depth: usize,
+    time: Duration,
+}
+
+impl Profile {
+    /// Records execution of the operation at the offset.
+    pub(crate) fn record(&mut self, offset: usize, op: Op, stack_depth: usize) {
+        self.offsets.entry(offset).or_insert((op, 0)).1 += 1;
+        self.max_stack_depth = self.max_stack_depth.max(stack_depth);
+    }
+
+    pub(crate) fn add_time(&mut self, time: Duration) {
+        self.time += time;
+    }
+
+    /// Returns total number of executed operations.
+    pub fn executed(&self) -> u64 {
+        self.offsets.values().map(|&(_, count)| count).sum()
+    }
+
+    /// Returns maximum number of values on the integer stack.
+    pub fn max_stack_depth(&self) -> usize {
+        self.max_stack_depth
+    }
+
+    /// Returns wall time of the execution.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// Returns executed offsets with their operations and counts, most frequent first.
+    pub fn hot_spots(&self) -> Vec<(usize, Op, u64)> {
+        let mut spots: Vec<_> = self
+            .offsets
+            .iter()
+            .map(|(&offset, &(op, count))| (offset, op, count))
+            .collect();
+        spots.sort_by_key(|&(offset, _, count)| (Reverse(count), offset));
+        spots
+    }
+
+    /// Returns counts of executed operations by op code, most frequent first.
+    pub fn op_codes(&self) -> Vec<(OpCode, u64)> {
+        let mut counts = HashMap::new();
+        for (op, count) in self.offsets.values() {
+            *counts.entry(OpCode::from(op)).or_default() += count;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by_key(|&(op_code, count)| (Reverse(count), op_code as u8));
+        counts
+    }
+
+    /// Returns the profile as text tables, annotated with locations from the source map.
+    pub fn report(&self, source_map: Option<&SourceMap>) -> String {
+        let mut report = String::new();
+        let time = self.time.as_secs_f64() * 1000.0;
+        let (executed, depth) = (self.executed(), self.max_stack_depth);
+        writeln!(report, "Executed {} operations in {:.3} ms", executed, time).unwrap();
+        writeln!(report, "Maximum stack depth: {}", depth).unwrap();
+
+        writeln!(
+            report,
+            "\n{:>10}  {:>10}  {:<24}  source",
+            "offset", "count", "operation"
+        )
+        .unwrap();
+        for (offset, op, count) in self.hot_spots() {
+            let location = source_map.and_then(|map| map.location(offset));
+            let location = location.map_or(String::new(), |location| location.to_string());
+            let op = op.to_string();
+            writeln!(
+                report,
+                "{:>10}  {:>10}  {:<24}  {}",
+                offset, count, op, location
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\n{:>10}  operation", "count").unwrap();
+        for (op_code, count) in self.op_codes() {
+            writeln!(report, "{:>10}  {}", count, name(op_code)).unwrap();
+        }
+        report
+    }
+
+    /// Returns the profile as JSON, annotated with locations from the source map.
+    ///
+    /// Lines of the source start from 1, time is in milliseconds.
+    pub fn to_json(&self, source_map: Option<&SourceMap>) -> String {
+        let hot_spots = self.hot_spots().into_iter().map(|(offset, op, count)| {
+            let mut members = vec![
+                ("offset", offset.into()),
+                ("count", count.into()),
+                ("operation", op.to_string().into()),
+            ];
+            if let Some(location) = source_map.and_then(|map| map.location(offset)) {
+                if let Some(file) = &location.file {
+                    members.push(("file", file.display().to_string().into()));
+                }
+                members.push(("line", (location.line + 1).into()));
+            }
+            Json::object(members)
+        });
+        let op_codes = self
+            .op_codes()
+            .into_iter()
+            .map(|(op_code, count)| (name(op_code), count.into()));
+        Json::object(vec![
+            ("executed", self.executed().into()),
+            ("time_ms", (self.time.as_secs_f64() * 1000.0).into()),
+            ("max_stack_depth", self.max_stack_depth.into()),
+            ("hot_spots", Json::Array(hot_spots.collect())),
+            ("op_codes", Json::object(op_codes)),
+        ])
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{Location, Register, Value};
+
+    #[test]
+    fn report() {
+        let mut profile = Profile::default();
+        for depth in 1..=3 {
+            profile.record(0, Op::PushValue(Value(1)), depth);
+            profile.record(5, Op::PopRegister(Register::A), depth - 1);
+        }
+        profile.record(7, Op::PushValue(Value(2)), 1);
+        assert_eq!(profile.executed(), 7);
+        assert_eq!(profile.max_stack_depth(), 3);
+        assert_eq!(
+            profile.op_codes(),
+            vec![(OpCode::PushValue, 4), (OpCode::PopRegister, 3)]
+        );
+
+        let mut source_map = SourceMap::default();
+        let location = Location {
+            file: Some("a\"b.sasm".into()),
+            line: 4,
+        };
+        source_map.insert(5, location);
+        let report = profile.report(Some(&source_map));
+        let lines: Vec<_> = report.lines().map(str::trim_end).collect();
+        assert_eq!(lines[1], "Maximum stack depth: 3");
+        assert_eq!(
+            lines[5],
+            "         5           3  POP A                     a\"b.sasm:5"
+        );
+        assert_eq!(lines[9], "         4  PUSH <value>");
+
+        let json = profile.to_json(Some(&source_map));
+        assert!(json.starts_with("{\"executed\":7,\"time_ms\":0,\"max_stack_depth\":3,"));
+        assert!(json.contains(
+            "{\"offset\":5,\"count\":3,\"operation\":\"POP A\",\"file\":\"a\\\"b.sasm\",\"line\":5}"
+        ));
+        assert!(json.ends_with("\"op_codes\":{\"PUSH <value>\":4,\"POP <register>\":3}}"));
+    }
+}
@@ -0,0 +1,275 @@
+//! Programs loaded for execution.
+//!
+//! Loading decodes the machine code once and fuses frequent pairs of operations into
+//! superinstructions, which are dispatched at once. Superinstructions exist only in
+//! memory, compiled code never contains them.
+
+use crate::{
+    machine_code::{Decompile, Header, HeaderDecompileError, OpCode, OpDecompileError},
+    models::{Op, Register, Value, Width},
+};
+
+/// Single unit of execution.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Instruction {
+    Op(Op),
+
+    /// `PUSH value` followed by an arithmetic operation.
+    PushValueAndApply(i64, Op),
+
+    /// `PUSH register` followed by an arithmetic operation.
+    PushRegisterAndApply(Register, Op),
+
+    /// `PUSH register; PUSH register`.
+    PushRegisters(Register, Register),
+
+    /// `PUSH value; POP register`.
+    SetRegister(Register, i64),
+}
+
+impl Instruction {
+    /// Length of the machine code of the instruction, including both fused operations.
+    pub fn op_len(&self, width: Width) -> usize {
+        use OpCode::*;
+
+        let len = |op| OpCode::from(op).op_len(width);
+        match self {
+            Instruction::Op(op) => len(op),
+            Instruction::PushValueAndApply(_, op) => PushValue.op_len(width) + len(op),
+            Instruction::PushRegisterAndApply(_, op) => PushRegister.op_len(width) + len(op),
+            Instruction::PushRegisters(..) => 2 * PushRegister.op_len(width),
+            Instruction::SetRegister(..) => PushValue.op_len(width) + PopRegister.op_len(width),
+        }
+    }
+
+    /// Number of operations in the instruction.
+    pub fn op_count(&self) -> u64 {
+        match self {
+            Instruction::Op(_) => 1,
+            _ => 2,
+        }
+    }
+
+    /// Splits superinstruction into the fused operations, returns `None` for operations.
+    pub fn split(&self) -> Option<(Op, Op)> {
+        match *self {
+            Instruction::Op(_) => None,
+            Instruction::PushValueAndApply(v, op) => Some((Op::PushValue(Value(v)), op)),
+            Instruction::PushRegisterAndApply(r, op) => Some((Op::PushRegister(r), op)),
+            Instruction::PushRegisters(a, b) => Some((Op::PushRegister(a), Op::PushRegister(b))),
+            Instruction::SetRegister(r, v) => Some((Op::PushValue(Value(v)), Op::PopRegister(r))),
+        }
+    }
+}
+
+/// Decoded program.
+#[derive(Debug)]
+pub(crate) struct Program {
+    /// Width of the machine word, declared in the header.
+    pub width: Width,
+
+    /// Number of registers, declared in the header.
+    pub registers: u8,
+
+    /// Names of host functions, which are called with `Op::CallHost`.
+    pub imports: Vec<String>,
+
+    pub instructions: Vec<Instruction>,
+
+    /// Offsets of the instructions in the machine code, including the header.
+    pub offsets: Vec<usize>,
+
+    /// Offset after the last instruction, where the code ends or the error is.
+    pub end: usize,
+
+    /// Error in the code after the last instruction.
+    ///
+    /// It's returned only if execution reaches it, like it would be without loading.
+    pub error: Option<OpDecompileError>,
+}
+
+impl Program {
+    /// Decodes machine code, fusing pairs of operations into superinstructions if `fuse` is set.
+    ///
+    /// Fails only if the header is wrong, errors in the code are returned with the program.
+    pub fn load(bytes: &[u8], fuse: bool) -> Result<Self, HeaderDecompileError> {
+        let header = Header::decompile(bytes)?;
+        let Header {
+            width,
+            registers,
+            imports,
+        } = header.value;
+
+        let mut ops = Vec::new();
+        let mut error = None;
+        let mut idx = header.bytes_read;
+        while idx < bytes.len() {
+            match Op::decompile_with(&bytes[idx..], width) {
+                Ok(op) => {
+                    ops.push(op.value);
+                    idx += op.bytes_read;
+                }
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        let instructions: Vec<_> = if fuse {
+            fuse_pairs(&ops)
+        } else {
+            ops.into_iter().map(Instruction::Op).collect()
+        };
+        let mut offsets = Vec::with_capacity(instructions.len());
+        let mut offset = header.bytes_read;
+        for instruction in instructions.iter() {
+            offsets.push(offset);
+            offset += instruction.op_len(width);
+        }
+
+        Ok(Program {
+            width,
+            registers,
+            imports,
+            instructions,
+            offsets,
+            end: idx,
+            error,
+        })
+    }
+
+    /// Returns offset of the instruction at `position`, `end` if it's after the last one.
+    pub fn offset(&self, position: usize) -> usize {
+        self.offsets.get(position).copied().unwrap_or(self.end)
+    }
+
+    /// Returns position of the instruction at `offset`.
+    ///
+    /// Superinstruction, which contains the offset, is split into the fused operations.
+    /// Returns `None` if the offset is not at the start of an operation.
+    pub fn position(&mut self, offset: usize) -> Option<usize> {
+        if offset == self.end {
+            return Some(self.instructions.len());
+        }
+        let position = match self.offsets.binary_search(&offset) {
+            Ok(position) => return Some(position),
+            Err(0) => return None,
+            Err(next) => next - 1,
+        };
+
+        let instruction = self.instructions[position];
+        let (first, second) = instruction.split()?;
+        let first_len = OpCode::from(&first).op_len(self.width);
+        if self.offsets[position] + first_len != offset {
+            return None;
+        }
+        let first = Instruction::Op(first);
+        let second = Instruction::Op(second);
+        self.instructions
+            .splice(position..=position, vec![first, second]);
+        self.offsets.insert(position + 1, offset);
+        Some(position + 1)
+    }
+}
+
+/// Returns `true` for operations, which pop two values and push the result.
+pub(crate) fn is_arithmetic(op: Op) -> bool {
+    use Op::*;
+
+    matches!(op, Add | Sub | Mul | Div | Mod | Shl)
+}
+
+fn fuse_pairs(ops: &[Op]) -> Vec<Instruction> {
+    use Op::*;
+
+    let mut instructions = Vec::with_capacity(ops.len());
+    let mut idx = 0;
+    while idx < ops.len() {
+        let fused = match (ops[idx], ops.get(idx + 1).copied()) {
+            (PushValue(v), Some(op)) if is_arithmetic(op) => {
+                Some(Instruction::PushValueAndApply(v.value(), op))
+            }
+            (PushRegister(r), Some(op)) if is_arithmetic(op) => {
+                Some(Instruction::PushRegisterAndApply(r, op))
+            }
+            (PushRegister(a), Some(PushRegister(b))) => Some(Instruction::PushRegisters(a, b)),
+            (PushValue(v), Some(PopRegister(r))) => Some(Instruction::SetRegister(r, v.value())),
+            _ => None,
+        };
+
+        match fused {
+            Some(instruction) => {
+                instructions.push(instruction);
+                idx += 2;
+            }
+            None => {
+                instructions.push(Instruction::Op(ops[idx]));
+                idx += 1;
+            }
+        }
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{machine_code::Compile, models::Assembly};
+
+    fn load(source: &str, fuse: bool) -> Program {
+        let assembly: Assembly = source.parse().unwrap();
+        let mut bytes = Vec::new();
+        assembly.compile(&mut bytes).unwrap();
+        Program::load(&bytes, fuse).unwrap()
+    }
+
+    #[test]
+    fn fusion() {
+        let source = "PUSH 1\nPOP A\nPUSH A\nPUSH B\nADD\nPUSH 2\nMUL\nPUSH 3\nOUTPUT\nHALT";
+        let program = load(source, true);
+        assert!(program.error.is_none());
+        assert_eq!(
+            program.instructions,
+            vec![
+                Instruction::SetRegister(Register::A, 1),
+                Instruction::PushRegisters(Register::A, Register::B),
+                Instruction::Op(Op::Add),
+                Instruction::PushValueAndApply(2, Op::Mul),
+                Instruction::Op(Op::PushValue(3.into())),
+                Instruction::Op(Op::Output),
+                Instruction::Op(Op::Halt),
+            ]
+        );
+
+        let program = load(source, false);
+        assert_eq!(program.instructions.len(), 10);
+
+        let len = |program: &Program| -> usize {
+            let op_len = |instruction: &Instruction| instruction.op_len(program.width);
+            program.instructions.iter().map(op_len).sum()
+        };
+        assert_eq!(len(&load(source, true)), len(&program));
+    }
+
+    #[test]
+    fn trailing_error() {
+        let program = Program::load(&[7, 0xff, 7], true).unwrap();
+        assert_eq!(program.instructions, vec![Instruction::Op(Op::Halt)]);
+        assert!(matches!(
+            program.error,
+            Some(OpDecompileError::WrongOpCode(_))
+        ));
+    }
+
+    #[test]
+    fn wide_program() {
+        let program = load(".width 64\nPUSH 0x1_0000_0000\nPOP A", true);
+        assert_eq!(program.width, Width::W64);
+        assert_eq!(
+            program.instructions,
+            vec![Instruction::SetRegister(Register::A, 1 << 32)]
+        );
+        assert_eq!(program.instructions[0].op_len(Width::W64), 11);
+    }
+}
@@ -1,38 +1,68 @@
+use super::ExecutionError;
 use crate::models::Register;
-use std::ops::{Index, IndexMut};
 
-#[derive(Debug, Default)]
+/// Register file of the machine.
+///
+/// Only the first `count` registers are available, access to others fails.
+#[derive(Debug)]
 pub struct Registers {
-    a: i32,
-    b: i32,
-    c: i32,
-    d: i32,
+    values: [i64; Register::MAX_COUNT as usize],
+    count: u8,
 }
 
-impl Index<Register> for Registers {
-    type Output = i32;
+impl Registers {
+    pub fn new(count: u8) -> Self {
+        let mut registers = Registers {
+            values: Default::default(),
+            count: 0,
+        };
+        registers.set_count(count);
+        registers
+    }
 
-    fn index(&self, index: Register) -> &Self::Output {
-        use Register::*;
+    pub fn count(&self) -> u8 {
+        self.count
+    }
 
-        match index {
-            A => &self.a,
-            B => &self.b,
-            C => &self.c,
-            D => &self.d,
+    /// Changes the number of available registers, removed registers are zeroed.
+    pub fn set_count(&mut self, count: u8) {
+        let count = count.min(Register::MAX_COUNT);
+        for value in self.values[count as usize..].iter_mut() {
+            *value = 0;
         }
+        self.count = count;
     }
-}
 
-impl IndexMut<Register> for Registers {
-    fn index_mut(&mut self, index: Register) -> &mut Self::Output {
-        use Register::*;
+    /// Returns values of the available registers.
+    pub fn values(&self) -> &[i64] {
+        &self.values[..self.count as usize]
+    }
+
+    pub fn get(&self, register: Register) -> Result<i64, ExecutionError> {
+        self.check(register)?;
+        Ok(self.values[register.index() as usize])
+    }
 
-        match index {
-            A => &mut self.a,
-            B => &mut self.b,
-            C => &mut self.c,
-            D => &mut self.d,
+    pub fn set(&mut self, register: Register, value: i64) -> Result<(), ExecutionError> {
+        self.check(register)?;
+        self.values[register.index() as usize] = value;
+        Ok(())
+    }
+
+    fn check(&self, register: Register) -> Result<(), ExecutionError> {
+        if register.index() < self.count {
+            Ok(())
+        } else {
+            Err(ExecutionError::MissingRegister {
+                register,
+                count: self.count,
+            })
         }
     }
 }
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers::new(Register::DEFAULT_COUNT)
+    }
+}
@@ -0,0 +1,83 @@
+//! Text format of recorded interactions with a device, one event per line:
+//!
+//! ```text
+//! read 7
+//! read-byte 97
+//! read-byte eof
+//! write 14
+//! write-byte 10
+//! ```
+//!
+//! Empty lines are ignored.
+
+use std::io;
+
+use super::{Event, ReplayError};
+
+/// Writes events in the replay format.
+pub fn write_replay(output: &mut impl io::Write, events: &[Event]) -> io::Result<()> {
+    for event in events.iter() {
+        match event {
+            Event::ReadInteger(value) => writeln!(output, "read {}", value)?,
+            Event::ReadByte(Some(byte)) => writeln!(output, "read-byte {}", byte)?,
+            Event::ReadByte(None) => writeln!(output, "read-byte eof")?,
+            Event::WriteInteger(value) => writeln!(output, "write {}", value)?,
+            Event::WriteByte(byte) => writeln!(output, "write-byte {}", byte)?,
+        }
+    }
+    Ok(())
+}
+
+/// Reads events written by `write_replay`.
+pub fn read_replay(text: &str) -> Result<Vec<Event>, ReplayError> {
+    let mut events = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let event = match words[..] {
+            [] => continue,
+            ["read", value] => value.parse().ok().map(Event::ReadInteger),
+            ["read-byte", "eof"] => Some(Event::ReadByte(None)),
+            ["read-byte", byte] => byte.parse().ok().map(|b| Event::ReadByte(Some(b))),
+            ["write", value] => value.parse().ok().map(Event::WriteInteger),
+            ["write-byte", byte] => byte.parse().ok().map(Event::WriteByte),
+            _ => None,
+        };
+        events.push(event.ok_or_else(|| ReplayError::WrongEvent {
+            line: idx + 1,
+            text: line.to_owned(),
+        })?);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_read() {
+        let events = vec![
+            Event::ReadInteger(-7),
+            Event::ReadByte(Some(b'a')),
+            Event::ReadByte(None),
+            Event::WriteInteger(14),
+            Event::WriteByte(b'\n'),
+        ];
+        let mut text = Vec::new();
+        write_replay(&mut text, &events).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(
+            text,
+            "read -7\nread-byte 97\nread-byte eof\nwrite 14\nwrite-byte 10\n"
+        );
+        assert_eq!(read_replay(&text), Ok(events));
+
+        assert_eq!(
+            read_replay("read 1\n\nwrite-byte 256\n"),
+            Err(ReplayError::WrongEvent {
+                line: 3,
+                text: "write-byte 256".into()
+            })
+        );
+    }
+}
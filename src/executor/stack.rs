@@ -1,16 +1,74 @@
+use std::ops::Range;
+
 use super::ExecutionError;
 
+/// Stack of integers, or of floats for the float operations.
 #[derive(Default, Debug)]
-pub struct Stack {
-    data: Vec<i32>,
+pub struct Stack<T = i64> {
+    data: Vec<T>,
+
+    /// Values below the floor belong to outer frames and can't be popped.
+    floor: usize,
 }
 
-impl Stack {
-    pub fn push(&mut self, value: i32) {
+impl<T> Stack<T> {
+    pub fn push(&mut self, value: T) {
         self.data.push(value);
     }
 
-    pub fn pop(&mut self) -> Result<i32, ExecutionError> {
-        self.data.pop().ok_or(ExecutionError::StackUnderflow)
+    pub fn pop(&mut self) -> Result<T, ExecutionError> {
+        if self.data.len() > self.floor {
+            self.data.pop().ok_or(ExecutionError::StackUnderflow)
+        } else {
+            Err(ExecutionError::StackUnderflow)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Number of values, which can be popped.
+    pub fn available(&self) -> usize {
+        self.data.len() - self.floor
+    }
+
+    /// Returns all values, from the bottom of the stack.
+    pub fn values(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Replaces all values of the stack.
+    pub(crate) fn replace(&mut self, data: Vec<T>, floor: usize) {
+        self.data = data;
+        self.floor = floor;
+    }
+
+    pub(crate) fn set_floor(&mut self, floor: usize) {
+        self.floor = floor;
+    }
+
+    pub(crate) fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        self.data.get_mut(idx)
+    }
+
+    pub(crate) fn remove(&mut self, range: Range<usize>) {
+        self.data.drain(range);
+    }
+}
+
+impl<T: Copy> Stack<T> {
+    pub(crate) fn get(&self, idx: usize) -> Option<T> {
+        self.data.get(idx).copied()
+    }
+
+    /// Replaces values from `base` to the top.
+    pub(crate) fn replace_top(&mut self, base: usize, values: &[T]) {
+        self.data.truncate(base);
+        self.data.extend_from_slice(values);
     }
 }
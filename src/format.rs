@@ -0,0 +1,276 @@
+//! Canonical layout of the assembly source, which is written by `smachine fmt`:
+//!
+//! ```text
+//! .equ TEN 10
+//!
+//!     ; Adds ten to the input
+//!     INPUT
+//!     PUSH TEN  ; constant
+//!     ADD
+//!     MOV  A, 1 ; registers
+//!     OUTPUT
+//! ```
+//!
+//! Directives start at the beginning of the line, instructions are indented. Known
+//! mnemonics are uppercase, operands of consecutive instructions start in the same
+//! column and so do their comments. Comments on their own lines are indented like
+//! the following statement. Runs of empty lines become a single empty line.
+//!
+//! Only the layout changes, operands are kept as they are written except for the
+//! spaces around commas, so constants and macro parameters are preserved.
+//!
+//! The layout is built from the text rather than from a parsed `Assembly`, which has
+//! constants substituted, macros expanded and comments dropped. Operands are split
+//! like the assembler splits them. Callers parse the source first, so that only
+//! correct assembly is formatted.
+
+use std::fmt::Write;
+
+use crate::models::{split_comment, split_operands, MNEMONICS};
+
+/// Indentation of instructions.
+const INDENT: &str = "    ";
+
+/// Line of the source, split into its parts.
+#[derive(Debug, PartialEq, Eq)]
+enum Line<'a> {
+    Empty,
+    /// Comment on its own line.
+    Comment(&'a str),
+    /// Directive, like `.equ` or `.macro`, with its arguments and comment.
+    Directive {
+        name: &'a str,
+        args: &'a str,
+        comment: Option<&'a str>,
+    },
+    /// Operation or macro invocation with its operands and comment.
+    Instruction {
+        mnemonic: &'a str,
+        operands: Vec<&'a str>,
+        comment: Option<&'a str>,
+    },
+}
+
+impl<'a> Line<'a> {
+    fn parse(text: &'a str) -> Self {
+        let (code, comment) = split_comment(text);
+        let code = code.trim();
+        let (word, rest) = match code.find(char::is_whitespace) {
+            Some(idx) => (&code[..idx], code[idx..].trim()),
+            None => (code, ""),
+        };
+        match (word, comment) {
+            ("", None) => Line::Empty,
+            ("", Some(comment)) => Line::Comment(comment),
+            (name, comment) if name.starts_with('.') => Line::Directive {
+                name,
+                args: rest,
+                comment,
+            },
+            (mnemonic, comment) => Line::Instruction {
+                mnemonic,
+                operands: split_operands(rest),
+                comment,
+            },
+        }
+    }
+}
+
+/// Returns the comment with a single space after `;`, `;;` and similar are kept.
+fn comment(text: &str) -> String {
+    let text = text.trim_end();
+    if text.is_empty() || text.starts_with(';') {
+        format!(";{}", text)
+    } else {
+        format!("; {}", text.trim_start())
+    }
+}
+
+/// Returns the source in the canonical layout.
+pub fn format_source(source: &str) -> String {
+    let lines: Vec<_> = source.lines().map(Line::parse).collect();
+
+    // Comments on their own lines are indented like the next statement
+    let mut indented = vec![false; lines.len()];
+    let mut next_indented = false;
+    for (idx, line) in lines.iter().enumerate().rev() {
+        match line {
+            Line::Directive { .. } => next_indented = false,
+            Line::Instruction { .. } => next_indented = true,
+            Line::Empty | Line::Comment(_) => (),
+        }
+        indented[idx] = next_indented;
+    }
+
+    let mut output = String::new();
+    let mut empty = false;
+    let mut idx = 0;
+    while idx < lines.len() {
+        match &lines[idx] {
+            Line::Empty => {
+                empty = true;
+                idx += 1;
+                continue;
+            }
+            _ if empty && !output.is_empty() => output.push('\n'),
+            _ => (),
+        }
+        empty = false;
+
+        // Consecutive instructions and comments between them are aligned together
+        let end = match lines[idx] {
+            Line::Directive { .. } => idx + 1,
+            _ => {
+                let len = lines[idx..]
+                    .iter()
+                    .take_while(|line| matches!(line, Line::Instruction { .. } | Line::Comment(_)))
+                    .count();
+                idx + len.max(1)
+            }
+        };
+        format_block(&lines[idx..end], &indented[idx..end], &mut output);
+        idx = end;
+    }
+    output
+}
+
+fn format_block(lines: &[Line], indented: &[bool], output: &mut String) {
+    let mnemonic_width = lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Instruction {
+                mnemonic, operands, ..
+            } if !operands.is_empty() => Some(mnemonic.len()),
+            _ => None,
+        })
+        .max()
+        .unwrap_or_default();
+
+    let codes: Vec<_> = lines
+        .iter()
+        .map(|line| match line {
+            Line::Empty | Line::Comment(_) => None,
+            Line::Directive { name, args, .. } => {
+                let name = name.to_ascii_lowercase();
+                Some(if args.is_empty() {
+                    name
+                } else {
+                    format!("{} {}", name, args)
+                })
+            }
+            Line::Instruction {
+                mnemonic, operands, ..
+            } => {
+                let upper = mnemonic.to_ascii_uppercase();
+                let mnemonic = if MNEMONICS.contains(&upper.as_str()) {
+                    upper
+                } else {
+                    mnemonic.to_string()
+                };
+                Some(if operands.is_empty() {
+                    format!("{}{}", INDENT, mnemonic)
+                } else {
+                    let operands = operands.join(", ");
+                    let width = mnemonic_width;
+                    format!("{}{:<width$} {}", INDENT, mnemonic, operands, width = width)
+                })
+            }
+        })
+        .collect();
+
+    let comment_column = lines
+        .iter()
+        .zip(codes.iter())
+        .filter_map(|(line, code)| match line {
+            Line::Directive {
+                comment: Some(_), ..
+            }
+            | Line::Instruction {
+                comment: Some(_), ..
+            } => code.as_ref().map(String::len),
+            _ => None,
+        })
+        .max()
+        .unwrap_or_default()
+        + 1;
+
+    for ((line, code), &indented) in lines.iter().zip(codes).zip(indented) {
+        match (line, code) {
+            (Line::Comment(text), _) => {
+                let indent = if indented { INDENT } else { "" };
+                writeln!(output, "{}{}", indent, comment(text)).unwrap();
+            }
+            (
+                Line::Directive {
+                    comment: Some(text),
+                    ..
+                }
+                | Line::Instruction {
+                    comment: Some(text),
+                    ..
+                },
+                Some(code),
+            ) => {
+                let column = comment_column;
+                writeln!(
+                    output,
+                    "{:<column$}{}",
+                    code,
+                    comment(text),
+                    column = column
+                )
+                .unwrap();
+            }
+            (_, Some(code)) => writeln!(output, "{}", code).unwrap(),
+            (_, None) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format() {
+        let source = "
+
+.EQU TEN 10 ;ten
+;   Adds ten
+   input
+ push  TEN;constant
+add
+  mov A,1 ;;registers
+
+
+ ;; the end
+   .macro TWICE x
+push \\x
+    Push   \\x
+.endm
+TWICE ','
+    PUSH 'a' ; ';'
+";
+        let formatted = format_source(source);
+        assert_eq!(
+            formatted,
+            "\
+.equ TEN 10 ; ten
+    ; Adds ten
+    INPUT
+    PUSH TEN  ; constant
+    ADD
+    MOV  A, 1 ;;registers
+
+;; the end
+.macro TWICE x
+    PUSH \\x
+    PUSH \\x
+.endm
+    TWICE ','
+    PUSH  'a' ; ';'
+"
+        );
+        assert_eq!(format_source(&formatted), formatted);
+    }
+}
@@ -0,0 +1,264 @@
+//! Golden tests, assembly programs with their input and expected output in comments:
+//!
+//! ```text
+//! ; input: 3 4
+//! ; expect-output: 7
+//! INPUT
+//! INPUT
+//! ADD
+//! OUTPUT
+//! ```
+//!
+//! Values are separated by whitespace, each value is a line of the input or output.
+//! Directives may be repeated, their values are appended. Output is always compared,
+//! a program without `expect-output` must not write anything.
+//!
+//! `; expect-error: StackUnderflow` expects the execution to fail with the error,
+//! which has this name or contains this text in its message. A test with an empty
+//! `expect-error` is malformed, since it would accept any error.
+
+use std::{
+    fmt, fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::{
+    executor::{Machine, MemoryDevice},
+    machine_code::Compile,
+    models::Assembly,
+};
+
+/// Input and expectations of a golden test.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Expectations {
+    pub input: Vec<String>,
+    pub output: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl Expectations {
+    /// Reads directives from the comments of the source, other comments are ignored.
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+        for line in source.lines() {
+            let comment = match line.trim_start().strip_prefix(';') {
+                Some(comment) => comment.trim(),
+                None => continue,
+            };
+            let values = |directive: &str| {
+                let value = comment.strip_prefix(directive)?.strip_prefix(':')?;
+                Some(value.split_whitespace().map(str::to_owned))
+            };
+            if let Some(values) = values("input") {
+                expectations.input.extend(values);
+            } else if let Some(values) = values("expect-output") {
+                expectations.output.extend(values);
+            } else if let Some(values) = values("expect-error") {
+                expectations.error = Some(values.collect::<Vec<_>>().join(" "));
+            }
+        }
+        expectations
+    }
+}
+
+/// Reason of a failed golden test.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Failure {
+    #[error("Can't assemble the program:\n{0}")]
+    Assembly(String),
+
+    /// Directives in the comments of the test are not well-formed.
+    #[error("Malformed test: {0}")]
+    Malformed(&'static str),
+
+    #[error("Output differs from the expected one:\n{}", diff(expected, actual))]
+    Output {
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+
+    #[error("Execution failed: {0}")]
+    UnexpectedError(String),
+
+    #[error("Expected error {0}, but execution succeeded")]
+    MissingError(String),
+
+    #[error("Expected error {expected}, got: {actual}")]
+    WrongError { expected: String, actual: String },
+
+    /// Machine panicked, which is a bug of the machine rather than of the program.
+    #[error("Execution panicked: {0}")]
+    Panic(String),
+}
+
+/// Lists lines, which differ, with `-` for the expected lines and `+` for the actual ones.
+fn diff(expected: &[String], actual: &[String]) -> String {
+    let mut diff = String::new();
+    for idx in 0..expected.len().max(actual.len()) {
+        let (expected, actual) = (expected.get(idx), actual.get(idx));
+        if expected == actual {
+            continue;
+        }
+        diff.push_str(&format!("  line {}:\n", idx + 1));
+        let lines = [('-', expected), ('+', actual)];
+        for (sign, line) in lines.iter() {
+            match line {
+                Some(line) => diff.push_str(&format!("  {} {}\n", sign, line)),
+                None => diff.push_str(&format!("  {} <no line>\n", sign)),
+            }
+        }
+    }
+    diff
+}
+
+/// Assembles and executes the program with the input from its comments and checks
+/// its output and errors.
+///
+/// Included files are resolved relative to `path`, or to the current directory.
+pub fn run_test(source: &str, path: Option<&Path>) -> Result<(), Failure> {
+    let expectations = Expectations::parse(source);
+    if expectations.error.as_deref() == Some("") {
+        return Err(Failure::Malformed(
+            "expect-error without an error name or message",
+        ));
+    }
+    let assembly = Assembly::parse_with(source, path, |path| fs::read_to_string(path))
+        .map_err(|error| Failure::Assembly(error.to_string()))?;
+    let mut program = Vec::new();
+    assembly
+        .compile(&mut program)
+        .map_err(|error| Failure::Assembly(error.to_string()))?;
+
+    let input: String = expectations
+        .input
+        .iter()
+        .map(|x| format!("{}\n", x))
+        .collect();
+    let mut device = MemoryDevice::new(input);
+    // A panic fails this test only, so the rest of the tests still run
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        Machine::new(&mut device).execute_program(&program)
+    }))
+    .map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned());
+        Failure::Panic(message.unwrap_or_default())
+    })?;
+
+    match (result, &expectations.error) {
+        (Ok(()), None) => (),
+        (Ok(()), Some(expected)) => return Err(Failure::MissingError(expected.clone())),
+        (Err(error), None) => return Err(Failure::UnexpectedError(error.to_string())),
+        (Err(error), Some(expected)) => {
+            let message = error.to_string();
+            if error_name(&error) != *expected && !message.contains(expected.as_str()) {
+                return Err(Failure::WrongError {
+                    expected: expected.clone(),
+                    actual: message,
+                });
+            }
+        }
+    }
+
+    let output = String::from_utf8_lossy(device.output());
+    let actual: Vec<_> = output.lines().map(str::to_owned).collect();
+    if actual != expectations.output {
+        return Err(Failure::Output {
+            expected: expectations.output,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Returns name of the error variant, like `StackUnderflow`.
+fn error_name(error: &impl fmt::Debug) -> String {
+    let debug = format!("{:?}", error);
+    debug
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expectations() {
+        let source = "
+            ; Adds two numbers
+            ; input: 3 4
+            ; input: 5
+            ; expect-output: 7
+            ; expect-error: Attemted to pop
+            INPUT ; input: 1
+        ";
+        assert_eq!(
+            Expectations::parse(source),
+            Expectations {
+                input: vec!["3".into(), "4".into(), "5".into()],
+                output: vec!["7".into()],
+                error: Some("Attemted to pop".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn run() {
+        let add = "INPUT\nINPUT\nADD\nOUTPUT\n; input: 3 4\n";
+        assert_eq!(
+            run_test(&format!("{}; expect-output: 7", add), None),
+            Ok(())
+        );
+        let error = run_test(&format!("{}; expect-output: 6 1", add), None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Output differs from the expected one:\n  \
+             line 1:\n  - 6\n  + 7\n  line 2:\n  - 1\n  + <no line>\n"
+        );
+
+        let underflow = "PUSH 1\nOUTPUT\nOUTPUT\n; expect-output: 1\n";
+        assert_eq!(
+            run_test(
+                &format!("{}; expect-error: StackUnderflow", underflow),
+                None
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            run_test(&format!("{}; expect-error: empty stack", underflow), None),
+            Ok(())
+        );
+        assert!(matches!(
+            run_test(underflow, None),
+            Err(Failure::UnexpectedError(_))
+        ));
+        assert!(matches!(
+            run_test("PUSH 1\nOUTPUT\n; expect-error: StackUnderflow", None),
+            Err(Failure::MissingError(_))
+        ));
+        assert!(matches!(
+            run_test(&format!("{}; expect-error: NoDevice", underflow), None),
+            Err(Failure::WrongError { .. })
+        ));
+        assert!(matches!(run_test("PUSH", None), Err(Failure::Assembly(_))));
+        assert_eq!(
+            run_test(&format!("{}; expect-error: ", underflow), None)
+                .unwrap_err()
+                .to_string(),
+            "Malformed test: expect-error without an error name or message"
+        );
+
+        for op in ["DIV", "MOD"].iter() {
+            let source = format!("PUSH 1\nPUSH 0\n{}\n; expect-error: DivisionByZero", op);
+            assert_eq!(run_test(&source, None), Ok(()));
+        }
+    }
+}
@@ -2,5 +2,12 @@
 extern crate thiserror;
 
 pub mod executor;
+pub mod format;
+pub mod golden;
+pub mod json;
+pub mod lint;
+pub mod lsp;
 pub mod machine_code;
 pub mod models;
+pub mod optimizer;
+pub mod repl;
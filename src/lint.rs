@@ -0,0 +1,402 @@
+//! Static checks of assembly programs, which are written by `smachine lint`.
+//!
+//! Programs have no jumps, so they are analysed as a single path of execution.
+//! The analysis of the stack stops at `SYSCALL` and `CALLHOST`, because the number
+//! of values used by host functions is not known.
+//!
+//! Warnings are suppressed with a comment, which lists their codes:
+//!
+//! ```text
+//!     PUSH 1  ; lint: allow L003
+//! ; lint: allow L004, L005
+//!     PUSH A
+//! ```
+//!
+//! The comment applies to its line, or to the next line if it's on its own line.
+//! Comments in included files are not read.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::Path,
+};
+
+use crate::models::{
+    split_comment, Assembly, AssemblyParseError, Location, Op, Register, Statement,
+};
+
+/// Kind of the warning, codes of the kinds never change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// Statements after `HALT`.
+    UnreachableCode,
+    /// Operation pops from the empty stack.
+    StackUnderflow,
+    /// Value is still on the stack when the program terminates.
+    UnusedValue,
+    /// Register is read before anything is written to it.
+    UninitializedRegister,
+    /// `DIV` or `MOD` by zero, pushed as a literal.
+    DivisionByZero,
+    /// Last operation is not `HALT`.
+    MissingHalt,
+}
+
+impl Code {
+    pub const ALL: [Code; 6] = [
+        Code::UnreachableCode,
+        Code::StackUnderflow,
+        Code::UnusedValue,
+        Code::UninitializedRegister,
+        Code::DivisionByZero,
+        Code::MissingHalt,
+    ];
+
+    /// Returns the stable code, like `L001`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::UnreachableCode => "L001",
+            Code::StackUnderflow => "L002",
+            Code::UnusedValue => "L003",
+            Code::UninitializedRegister => "L004",
+            Code::DivisionByZero => "L005",
+            Code::MissingHalt => "L006",
+        }
+    }
+
+    /// Returns the kind with the code, the code is case-insensitive.
+    pub fn parse(code: &str) -> Option<Code> {
+        Code::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.as_str().eq_ignore_ascii_case(code))
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub code: Code,
+
+    /// Location of the statement, `None` if the assembly was not parsed from the source.
+    pub location: Option<Location>,
+
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        write!(f, "warning[{}]: {}", self.code, self.message)
+    }
+}
+
+/// Value on the stack during the analysis.
+#[derive(Copy, Clone)]
+struct Pushed {
+    /// Index of the statement, which pushed the value.
+    statement: usize,
+
+    /// Value, if it's a literal.
+    literal: Option<i64>,
+}
+
+struct Linter<'a> {
+    assembly: &'a Assembly,
+    warnings: Vec<Warning>,
+
+    /// Values available to the current frame, `None` after a call of a host function.
+    stack: Option<Vec<Pushed>>,
+
+    /// Values of the outer frames, which are hidden by `ENTER`.
+    frames: Vec<Vec<Pushed>>,
+
+    written: HashSet<Register>,
+    reported: HashSet<Register>,
+}
+
+impl Linter<'_> {
+    fn warn(&mut self, code: Code, statement: usize, message: String) {
+        self.warnings.push(Warning {
+            code,
+            location: self.assembly.location(statement).cloned(),
+            message,
+        });
+    }
+
+    fn read(&mut self, register: Register, statement: usize) {
+        if !self.written.contains(&register) && self.reported.insert(register) {
+            let message = format!("Register {} is read before it is written", register);
+            self.warn(Code::UninitializedRegister, statement, message);
+        }
+    }
+
+    /// Pops `count` values, warns if there are not enough of them.
+    fn pop(&mut self, count: usize, op: Op, statement: usize) -> Vec<Pushed> {
+        let stack = match self.stack.as_mut() {
+            Some(stack) => stack,
+            None => return Vec::new(),
+        };
+        if stack.len() < count {
+            let message = format!(
+                "{} pops {} values, but the stack has {}",
+                op,
+                count,
+                stack.len()
+            );
+            // The machine stops here, so the rest of the stack is not analysed
+            self.stack = None;
+            self.warn(Code::StackUnderflow, statement, message);
+            return Vec::new();
+        }
+        stack.split_off(stack.len() - count)
+    }
+
+    fn push(&mut self, statement: usize, literal: Option<i64>) {
+        if let Some(stack) = self.stack.as_mut() {
+            stack.push(Pushed { statement, literal });
+        }
+    }
+
+    fn op(&mut self, op: Op, statement: usize) {
+        use Op::*;
+
+        match op {
+            PushRegister(r) | MovRegister(_, r) | Inc(r) | Dec(r) | AddValue(r, _) => {
+                self.read(r, statement)
+            }
+            _ => (),
+        }
+        match op {
+            PopRegister(r) | MovRegister(r, _) | MovValue(r, _) => {
+                self.written.insert(r);
+            }
+            _ => (),
+        }
+
+        match op {
+            Div | Mod => {
+                let values = self.pop(2, op, statement);
+                if let Some(Pushed {
+                    literal: Some(0), ..
+                }) = values.get(1)
+                {
+                    self.warn(Code::DivisionByZero, statement, format!("{} by zero", op));
+                }
+                self.push(statement, None);
+            }
+            PushValue(value) => self.push(statement, Some(value.0)),
+            Enter(_) => {
+                if let Some(stack) = self.stack.as_mut() {
+                    self.frames.push(std::mem::take(stack));
+                }
+            }
+            // Frames are pushed only while the stack is known, so they are popped the same way
+            Leave => {
+                if let Some(stack) = self.stack.as_mut() {
+                    if let Some(mut outer) = self.frames.pop() {
+                        outer.append(stack);
+                        *stack = outer;
+                    }
+                }
+            }
+            Syscall(_) | CallHost(_) => self.stack = None,
+            _ => {
+                let (pops, pushes) = op.stack_effect().expect("fixed stack effect");
+                self.pop(pops, op, statement);
+                for _ in 0..pushes {
+                    self.push(statement, None);
+                }
+            }
+        }
+    }
+
+    /// Warns about values, which are left on the stack when the program terminates.
+    fn unused_values(&mut self) {
+        let stack = match &self.stack {
+            Some(stack) => stack,
+            None => return,
+        };
+        let mut values: Vec<_> = self.frames.iter().flatten().copied().collect();
+        values.extend(stack.iter().copied());
+        let mut counts: Vec<(usize, usize)> = Vec::new();
+        for value in values {
+            match counts.last_mut() {
+                Some((statement, count)) if *statement == value.statement => *count += 1,
+                _ => counts.push((value.statement, 1)),
+            }
+        }
+        for (statement, count) in counts {
+            let message = match count {
+                1 => "Value is pushed, but never consumed".to_owned(),
+                count => format!("{} values are pushed, but never consumed", count),
+            };
+            self.warn(Code::UnusedValue, statement, message);
+        }
+    }
+}
+
+/// Returns warnings about the program, ordered by the statement.
+pub fn lint(assembly: &Assembly) -> Vec<Warning> {
+    let mut linter = Linter {
+        assembly,
+        warnings: Vec::new(),
+        stack: Some(Vec::new()),
+        frames: Vec::new(),
+        written: HashSet::new(),
+        reported: HashSet::new(),
+    };
+
+    let mut last = None;
+    let mut halted = false;
+    for (idx, statement) in assembly.statements().iter().enumerate() {
+        let is_code = matches!(
+            statement,
+            Statement::Op(_) | Statement::CallHost(_) | Statement::PushExtern { .. }
+        );
+        if !is_code {
+            continue;
+        }
+        if halted {
+            let message = "Statement is never executed, it follows HALT".to_owned();
+            linter.warn(Code::UnreachableCode, idx, message);
+            break;
+        }
+        match statement {
+            Statement::Op(op) => {
+                linter.op(*op, idx);
+                halted = *op == Op::Halt;
+            }
+            Statement::CallHost(_) => linter.op(Op::CallHost(0), idx),
+            _ => linter.push(idx, None),
+        }
+        last = Some(idx);
+    }
+
+    match last {
+        Some(_) if halted => (),
+        Some(idx) => {
+            let message = "Program doesn't end with HALT".to_owned();
+            linter.warn(Code::MissingHalt, idx, message);
+        }
+        None => (),
+    }
+    linter.unused_values();
+
+    linter
+        .warnings
+        .sort_by(|a, b| (&a.location, a.code.as_str()).cmp(&(&b.location, b.code.as_str())));
+    linter.warnings
+}
+
+/// Parses and checks the source, dropping warnings suppressed by comments.
+///
+/// Included files are resolved relative to `path`, or to the current directory.
+pub fn lint_source(source: &str, path: Option<&Path>) -> Result<Vec<Warning>, AssemblyParseError> {
+    let assembly = Assembly::parse_with(source, path, |path| fs::read_to_string(path))?;
+    let allowed = suppressions(source);
+    let file = path.map(Path::to_path_buf);
+    let mut warnings = lint(&assembly);
+    warnings.retain(|warning| match &warning.location {
+        Some(location) if location.file == file => allowed
+            .get(&location.line)
+            .is_none_or(|codes| !codes.contains(&warning.code)),
+        _ => true,
+    });
+    Ok(warnings)
+}
+
+/// Returns codes allowed by `; lint: allow` comments by line.
+fn suppressions(source: &str) -> HashMap<usize, Vec<Code>> {
+    let mut allowed: HashMap<usize, Vec<Code>> = HashMap::new();
+    let mut pending = Vec::new();
+    for (line, text) in source.lines().enumerate() {
+        let (code, comment) = split_comment(text);
+        let codes = comment
+            .and_then(|comment| comment.trim().strip_prefix("lint:"))
+            .and_then(|directive| directive.trim().strip_prefix("allow"))
+            .map(|codes| {
+                codes
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter_map(Code::parse)
+                    .collect::<Vec<_>>()
+            });
+        if code.trim().is_empty() {
+            pending.extend(codes.into_iter().flatten());
+        } else {
+            let line = allowed.entry(line).or_default();
+            line.append(&mut pending);
+            line.extend(codes.into_iter().flatten());
+        }
+    }
+    allowed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn codes(source: &str) -> Vec<(usize, &'static str)> {
+        lint_source(source, None)
+            .unwrap()
+            .iter()
+            .map(|warning| {
+                (
+                    warning.location.as_ref().unwrap().line,
+                    warning.code.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn warnings() {
+        assert_eq!(codes("PUSH 1\nOUTPUT\nHALT"), vec![]);
+        assert_eq!(
+            codes("PUSH 1\nPUSH 2\nHALT\nOUTPUT"),
+            vec![(0, "L003"), (1, "L003"), (3, "L001")]
+        );
+        assert_eq!(codes("PUSH 1\nADD\nHALT"), vec![(1, "L002")]);
+        assert_eq!(
+            codes(".equ ZERO 0\nPUSH 5\nPUSH ZERO\nMOD\nPOP B\nPUSH C\nPUSH C\nADD\nOUTPUT"),
+            vec![(3, "L005"), (5, "L004"), (8, "L006")]
+        );
+
+        // Locals are hidden from the frame, arguments are left on the stack
+        let source = "INPUT\nENTER 1\nARG 0\nLOCAL.SET 0\nLEAVE\nOUTPUT\nHALT";
+        assert_eq!(codes(source), vec![]);
+        assert_eq!(
+            codes("PUSH 1\nENTER 0\nOUTPUT\nLEAVE\nHALT"),
+            vec![(2, "L002")]
+        );
+
+        // Host functions may use any number of values
+        assert_eq!(codes("PUSH 1\nSYSCALL 0\nOUTPUT\nHALT"), vec![]);
+        let source =
+            "PUSH 1\nENTER 0\nPUSH 2\nENTER 0\nCALLHOST f\nLEAVE\nOUTPUT\nLEAVE\nOUTPUT\nHALT";
+        assert_eq!(codes(source), vec![]);
+    }
+
+    #[test]
+    fn suppression() {
+        let source = "\
+PUSH 1 ; lint: allow L003
+; lint: allow l002, L004
+PUSH A
+ADD
+PUSH 1 ; lint: allow L001
+OUTPUT";
+        assert_eq!(codes(source), vec![(3, "L003"), (5, "L006")]);
+        assert_eq!(
+            lint_source(source, None).unwrap()[1].to_string(),
+            "line 6: warning[L006]: Program doesn't end with HALT"
+        );
+    }
+}
@@ -0,0 +1,757 @@
+//! Language server for the assembly, which talks the Language Server Protocol over
+//! standard input and output, see `run`.
+//!
+//! Documents are synchronized as a whole. The server publishes parse errors and lint
+//! warnings, shows the stack effect and the encoding of the operation under the cursor,
+//! completes mnemonics, registers, constants and macros, goes to definitions of constants
+//! and macros and formats documents with `format_source`.
+//!
+//! Positions count UTF-16 code units, as the protocol requires by default.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    format::format_source,
+    json::Json,
+    lint::lint_source,
+    models::{
+        split_comment, Assembly, AssemblyParseError, Op, Register, Statement, Symbols, Width,
+        MNEMONICS,
+    },
+};
+
+/// Error code of the response to an unknown request.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Error code of the response to a message, which is not JSON.
+const PARSE_ERROR: i64 = -32700;
+
+/// Maximum length of a message body in bytes, longer messages are skipped.
+pub const MAX_MESSAGE_LEN: usize = 16 << 20;
+
+// Kinds of completion items
+const KEYWORD: usize = 14;
+const VARIABLE: usize = 6;
+const CONSTANT: usize = 21;
+const FUNCTION: usize = 3;
+
+/// Serves messages from `input` until the `exit` notification or the end of the input.
+///
+/// Returns `true` if the client asked to shut down before the exit.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<bool> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut input)? {
+        let message = body.and_then(|body| Json::parse(&body).map_err(|error| error.to_string()));
+        let replies = match message {
+            Ok(message) => server.handle(&message),
+            Err(error) => vec![Json::object(vec![
+                ("jsonrpc", "2.0".into()),
+                ("id", Json::Null),
+                ("error", response_error(PARSE_ERROR, &error)),
+            ])],
+        };
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(server.shutdown)
+}
+
+/// Reads the body of the next message, `None` at the end of the input.
+///
+/// Bodies, which are too long or are not UTF-8, are skipped and returned as errors.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<String, String>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let mut parts = header.splitn(2, ':');
+        let (name, value) = (parts.next().unwrap_or_default(), parts.next());
+        if name.eq_ignore_ascii_case("Content-Length") {
+            length = value.and_then(|value| value.trim().parse::<usize>().ok());
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "message without Content-Length")
+    })?;
+    if length > MAX_MESSAGE_LEN {
+        let skipped = io::copy(
+            &mut io::Read::take(&mut *input, length as u64),
+            &mut io::sink(),
+        )?;
+        if skipped < length as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let message = format!(
+            "Message of {} bytes is longer than {} bytes",
+            length, MAX_MESSAGE_LEN
+        );
+        return Ok(Some(Err(message)));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8(body).map_err(|error| {
+        format!("Message is not UTF-8: {}", error)
+    })))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn response_error(code: i64, message: &str) -> Json {
+    Json::object(vec![("code", code.into()), ("message", message.into())])
+}
+
+/// Open documents and the state of the session.
+#[derive(Default)]
+pub struct Server {
+    /// Texts of the open documents by their URIs.
+    documents: HashMap<String, String>,
+
+    shutdown: bool,
+    exited: bool,
+}
+
+impl Server {
+    /// Handles the request or the notification, returns the response and notifications
+    /// to send to the client.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str);
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or_default();
+
+        let result = match method.unwrap_or_default() {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            }
+            "textDocument/didOpen" => {
+                let document = params.get("textDocument");
+                let text = document.and_then(|document| document.get("text"));
+                let text = text.and_then(Json::as_str).unwrap_or_default();
+                self.documents.insert(uri.to_owned(), text.to_owned());
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didChange" => {
+                let changes = match params.get("contentChanges") {
+                    Some(Json::Array(changes)) => changes.as_slice(),
+                    _ => &[],
+                };
+                // Changes are sent as whole documents, the last one is the current text
+                let text = changes.last().and_then(|change| change.get("text"));
+                if let Some(text) = text.and_then(Json::as_str) {
+                    self.documents.insert(uri.to_owned(), text.to_owned());
+                }
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, Vec::new())];
+            }
+            "textDocument/hover" => Ok(self.hover(uri, params)),
+            "textDocument/completion" => Ok(self.completion(uri)),
+            "textDocument/definition" => Ok(self.definition(uri, params)),
+            "textDocument/formatting" => Ok(self.formatting(uri)),
+            method => Err(format!("Unknown method {}", method)),
+        };
+
+        // Notifications don't get responses
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return Vec::new(),
+        };
+        let (key, value) = match result {
+            Ok(result) => ("result", result),
+            Err(message) => ("error", response_error(METHOD_NOT_FOUND, &message)),
+        };
+        vec![Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id),
+            (key, value),
+        ])]
+    }
+
+    fn text(&self, uri: &str) -> &str {
+        self.documents.get(uri).map_or("", String::as_str)
+    }
+
+    fn parse(&self, uri: &str) -> Result<Assembly, AssemblyParseError> {
+        let path = uri_to_path(uri);
+        Assembly::parse_with(self.text(uri), path.as_deref(), |path| {
+            fs::read_to_string(path)
+        })
+    }
+
+    /// Returns notification with parse errors of the document, or its lint warnings.
+    fn diagnostics(&self, uri: &str) -> Json {
+        let text = self.text(uri);
+        let path = uri_to_path(uri);
+        let diagnostic = |line: usize, severity: usize, code: Option<&str>, message: String| {
+            let mut members = vec![
+                ("range", line_range(text, line)),
+                ("severity", severity.into()),
+                ("source", "smachine".into()),
+                ("message", message.into()),
+            ];
+            members.extend(code.map(|code| ("code", code.into())));
+            Json::object(members)
+        };
+
+        let diagnostics = match lint_source(text, path.as_deref()) {
+            Ok(warnings) => warnings
+                .iter()
+                .map(|warning| {
+                    let location = warning.location.as_ref();
+                    let line = location
+                        .filter(|location| location.file == path)
+                        .map_or(0, |location| location.line);
+                    let code = Some(warning.code.as_str());
+                    diagnostic(line, 2, code, warning.message.clone())
+                })
+                .collect(),
+            Err(error) => error
+                .errors
+                .iter()
+                .map(|error| {
+                    // Errors in macro bodies and included files are shown at the line
+                    // of the document, which led to them
+                    let line = std::iter::once((&error.file, error.line))
+                        .chain(error.expansion.iter().map(|call| (&call.file, call.line)))
+                        .find(|(file, _)| **file == path)
+                        .map_or(0, |(_, line)| line);
+                    let message = error.error.to_string().trim_end().to_owned();
+                    diagnostic(line, 1, None, message)
+                })
+                .collect(),
+        };
+        publish_diagnostics(uri, diagnostics)
+    }
+
+    fn hover(&self, uri: &str, params: &Json) -> Json {
+        let (line, _) = match position(params) {
+            Some(position) => position,
+            None => return Json::Null,
+        };
+        let text = match self.text(uri).lines().nth(line) {
+            Some(text) => text,
+            None => return Json::Null,
+        };
+
+        // Operations of the parsed document have values of the constants, otherwise
+        // the line is parsed on its own
+        let path = uri_to_path(uri);
+        let (statement, width) = match self.parse(uri) {
+            Ok(assembly) => {
+                let statement = (0..assembly.statements().len()).find(|&idx| {
+                    assembly
+                        .location(idx)
+                        .is_some_and(|location| location.file == path && location.line == line)
+                });
+                let statement = statement.map(|idx| assembly.statements()[idx].clone());
+                (statement, assembly.width())
+            }
+            Err(_) => {
+                let code = split_comment(text).0;
+                let statement = Statement::parse(code, &Symbols::new()).ok();
+                (statement, Width::default())
+            }
+        };
+        let description = match statement {
+            Some(Statement::Op(op)) => describe(&op.to_string(), op, width),
+            // Index of the import is assigned when the assembly is compiled
+            Some(statement @ Statement::CallHost(_)) => {
+                describe(&statement.to_string(), Op::CallHost(0), width)
+            }
+            _ => return Json::Null,
+        };
+        Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![
+                    ("kind", "markdown".into()),
+                    ("value", description.into()),
+                ]),
+            ),
+            ("range", line_range(self.text(uri), line)),
+        ])
+    }
+
+    fn completion(&self, uri: &str) -> Json {
+        let item = |label: String, kind: usize| {
+            Json::object(vec![("label", label.into()), ("kind", kind.into())])
+        };
+        let mut items: Vec<_> = MNEMONICS
+            .iter()
+            .map(|mnemonic| item(mnemonic.to_string(), KEYWORD))
+            .collect();
+        let registers = self
+            .parse(uri)
+            .map_or(Register::DEFAULT_COUNT, |assembly| assembly.registers());
+        items.extend(
+            (0..registers)
+                .filter_map(Register::new)
+                .map(|register| item(register.to_string(), VARIABLE)),
+        );
+        items.extend(definitions(self.text(uri)).into_iter().map(|definition| {
+            let kind = if definition.is_macro {
+                FUNCTION
+            } else {
+                CONSTANT
+            };
+            item(definition.name.to_owned(), kind)
+        }));
+        Json::Array(items)
+    }
+
+    fn definition(&self, uri: &str, params: &Json) -> Json {
+        let text = self.text(uri);
+        let (line, character) = match position(params) {
+            Some(position) => position,
+            None => return Json::Null,
+        };
+        let word = match text
+            .lines()
+            .nth(line)
+            .and_then(|text| word_at(text, character))
+        {
+            Some(word) => word,
+            None => return Json::Null,
+        };
+        // Macros are case-insensitive, constants are not
+        let definition = definitions(text).into_iter().find(|definition| {
+            definition.name == word
+                || definition.is_macro && definition.name.eq_ignore_ascii_case(word)
+        });
+        match definition {
+            Some(definition) => {
+                let line_text = text.lines().nth(definition.line).unwrap_or_default();
+                let start = utf16_len(&line_text[..definition.start]);
+                let end = start + utf16_len(definition.name);
+                Json::object(vec![
+                    ("uri", uri.into()),
+                    ("range", range(definition.line, start, definition.line, end)),
+                ])
+            }
+            None => Json::Null,
+        }
+    }
+
+    /// Returns a single edit, which replaces the document, or no edits if the document
+    /// is formatted or is not correct assembly.
+    fn formatting(&self, uri: &str) -> Json {
+        let text = self.text(uri);
+        if self.parse(uri).is_err() {
+            return Json::Array(Vec::new());
+        }
+        let formatted = format_source(text);
+        if formatted == text {
+            return Json::Array(Vec::new());
+        }
+        // The range ends after the last line, even if it has no line break
+        let end = text.lines().count() + 1;
+        Json::Array(vec![Json::object(vec![
+            ("range", range(0, 0, end, 0)),
+            ("newText", formatted.into()),
+        ])])
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                ("textDocumentSync", 1usize.into()),
+                ("hoverProvider", true.into()),
+                ("completionProvider", Json::object(vec![])),
+                ("definitionProvider", true.into()),
+                ("documentFormattingProvider", true.into()),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![("name", "smachine".into())]),
+        ),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", uri.into()),
+                ("diagnostics", diagnostics.into()),
+            ]),
+        ),
+    ])
+}
+
+/// Returns the markdown description of the operation, written as `title`.
+fn describe(title: &str, op: Op, width: Width) -> String {
+    let effect = match (op, op.stack_effect()) {
+        (_, Some((pops, pushes))) => format!("Pops {}, pushes {}.", pops, pushes),
+        (Op::Enter(locals), _) => format!(
+            "Starts a frame with {} locals, values below it can't be popped.",
+            locals
+        ),
+        (Op::Leave, _) => "Removes locals of the frame, pushed values stay.".to_owned(),
+        _ => "Stack effect depends on the host function.".to_owned(),
+    };
+    let mut encoding = Vec::new();
+    op.compile_with(width, &mut encoding)
+        .expect("writing to memory doesn't fail");
+    let bytes: Vec<_> = encoding
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!(
+        "`{}`\n\n{}\n\nEncoding: `{}` ({} byte{})",
+        title,
+        effect,
+        bytes.join(" "),
+        encoding.len(),
+        if encoding.len() == 1 { "" } else { "s" },
+    )
+}
+
+/// Definition of a constant or a macro in the document.
+struct Definition<'a> {
+    name: &'a str,
+    is_macro: bool,
+    line: usize,
+    /// Byte offset of the name in the line.
+    start: usize,
+}
+
+/// Returns constants and macros defined in the text with `.equ`, `.const` and `.macro`.
+fn definitions(text: &str) -> Vec<Definition<'_>> {
+    let mut definitions = Vec::new();
+    for (line, line_text) in text.lines().enumerate() {
+        let code = split_comment(line_text).0;
+        let mut words = code.split_whitespace();
+        let is_macro = match words.next().map(str::to_ascii_lowercase).as_deref() {
+            Some(".equ") | Some(".const") => false,
+            Some(".macro") => true,
+            _ => continue,
+        };
+        if let Some(name) = words.next() {
+            // Words are slices of the line, so the offset is known
+            let start = name.as_ptr() as usize - line_text.as_ptr() as usize;
+            definitions.push(Definition {
+                name,
+                is_macro,
+                line,
+                start,
+            });
+        }
+    }
+    definitions
+}
+
+/// Returns the identifier at the position, counted in UTF-16 code units.
+fn word_at(text: &str, character: usize) -> Option<&str> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    let mut units = 0;
+    let index = text
+        .char_indices()
+        .find(|&(_, c)| {
+            units += c.len_utf16();
+            units > character
+        })
+        .map_or(text.len(), |(index, _)| index);
+    let start = text[..index].trim_end_matches(is_word).len();
+    let end = text.len() - text[index..].trim_start_matches(is_word).len();
+    Some(&text[start..end]).filter(|word| !word.is_empty())
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+fn position(params: &Json) -> Option<(usize, usize)> {
+    let position = params.get("position")?;
+    let line = position.get("line")?.as_usize()?;
+    let character = position.get("character")?.as_usize()?;
+    Some((line, character))
+}
+
+fn range(start_line: usize, start: usize, end_line: usize, end: usize) -> Json {
+    let position = |line: usize, character: usize| {
+        Json::object(vec![("line", line.into()), ("character", character.into())])
+    };
+    Json::object(vec![
+        ("start", position(start_line, start)),
+        ("end", position(end_line, end)),
+    ])
+}
+
+/// Returns range of the whole line.
+fn line_range(text: &str, line: usize) -> Json {
+    let len = text.lines().nth(line).map_or(0, utf16_len);
+    range(line, 0, line, len)
+}
+
+/// Returns path of the `file://` URI, `None` for other schemes.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let [byte, tail @ ..] = rest {
+        let hex = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(*byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const URI: &str = "untitled:a.sasm";
+
+    fn request(method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", 1usize.into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn open(server: &mut Server, text: &str) -> Json {
+        let document = Json::object(vec![("uri", URI.into()), ("text", text.into())]);
+        let params = Json::object(vec![("textDocument", document)]);
+        let mut replies = server.handle(&Json::object(vec![
+            ("method", "textDocument/didOpen".into()),
+            ("params", params),
+        ]));
+        replies.pop().unwrap()
+    }
+
+    fn at(line: usize, character: usize) -> Json {
+        Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", URI.into())])),
+            (
+                "position",
+                Json::object(vec![("line", line.into()), ("character", character.into())]),
+            ),
+        ])
+    }
+
+    fn result(server: &mut Server, method: &str, params: Json) -> Json {
+        let reply = server.handle(&request(method, params)).pop().unwrap();
+        reply.get("result").unwrap().clone()
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut server = Server::default();
+        let notification = open(&mut server, "PUSH 1\nPUSH\nHALT");
+        assert_eq!(
+            notification.get("params").unwrap().to_string(),
+            format!(
+                "{{\"uri\":\"{}\",\"diagnostics\":[{{\"range\":{},\"severity\":1,\
+                 \"source\":\"smachine\",\"message\":\"Unknown operation PUSH with 0 arguments\"}}]}}",
+                URI,
+                range(1, 0, 1, 4)
+            )
+        );
+
+        let notification = open(&mut server, "PUSH 1\nHALT");
+        let diagnostics = notification.get("params").unwrap().get("diagnostics");
+        let diagnostic = match diagnostics {
+            Some(Json::Array(diagnostics)) => diagnostics[0].clone(),
+            _ => panic!("no diagnostics"),
+        };
+        assert_eq!(diagnostic.get("code"), Some(&"L003".into()));
+        assert_eq!(diagnostic.get("range"), Some(&range(0, 0, 0, 6)));
+    }
+
+    #[test]
+    fn hover_and_definition() {
+        let mut server = Server::default();
+        open(
+            &mut server,
+            ".equ TEN 10\nPUSH TEN ; ten\nADD\n.macro TWICE\nINPUT\n.endm\ntwice",
+        );
+
+        let hover = result(&mut server, "textDocument/hover", at(1, 2));
+        assert_eq!(
+            hover.get("contents").unwrap().get("value"),
+            Some(&"`PUSH 10`\n\nPops 0, pushes 1.\n\nEncoding: `08 00 00 00 0a` (5 bytes)".into())
+        );
+        assert_eq!(
+            result(&mut server, "textDocument/hover", at(3, 0)),
+            Json::Null
+        );
+
+        let definition = result(&mut server, "textDocument/definition", at(1, 7));
+        assert_eq!(definition.get("range"), Some(&range(0, 5, 0, 8)));
+        let definition = result(&mut server, "textDocument/definition", at(6, 5));
+        assert_eq!(definition.get("range"), Some(&range(3, 7, 3, 12)));
+        assert_eq!(
+            result(&mut server, "textDocument/definition", at(2, 1)),
+            Json::Null
+        );
+    }
+
+    #[test]
+    fn completion_and_formatting() {
+        let mut server = Server::default();
+        open(&mut server, ".registers 5\n.equ TEN 10\npush TEN");
+        let labels: Vec<_> = match result(&mut server, "textDocument/completion", at(2, 0)) {
+            Json::Array(items) => items
+                .iter()
+                .map(|item| item.get("label").unwrap().as_str().unwrap().to_owned())
+                .collect(),
+            _ => panic!("no completion items"),
+        };
+        assert_eq!(labels.len(), MNEMONICS.len() + 6);
+        assert!(labels.ends_with(&["R4".into(), "TEN".into()]));
+
+        let params = Json::object(vec![(
+            "textDocument",
+            Json::object(vec![("uri", URI.into())]),
+        )]);
+        let edits = result(&mut server, "textDocument/formatting", params);
+        assert_eq!(
+            edits.to_string(),
+            format!(
+                "[{{\"range\":{},\"newText\":\".registers 5\\n.equ TEN 10\\n    PUSH TEN\\n\"}}]",
+                range(0, 0, 4, 0)
+            )
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let mut server = Server::default();
+        let nested = format!("PUSH {}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        // Each macro calls the previous one twice
+        let mut doubling = String::from(".macro m0\nPUSH 1\n.endm\n");
+        for i in 1..=24 {
+            doubling += &format!(".macro m{}\nm{1}\nm{1}\n.endm\n", i, i - 1);
+        }
+        doubling += "m24";
+
+        for (text, message, line) in [
+            (
+                &nested,
+                "Errors occured when parsing operation PUSH",
+                0usize,
+            ),
+            // The limit is reached at the invocation of m0 in the body of m1
+            (&doubling, "Expansion of macro m0 exceeded", 4),
+        ]
+        .iter()
+        {
+            let notification = open(&mut server, text);
+            let diagnostic = match notification.get("params").unwrap().get("diagnostics") {
+                Some(Json::Array(diagnostics)) if diagnostics.len() == 1 => diagnostics[0].clone(),
+                _ => panic!("expected one diagnostic"),
+            };
+            assert_eq!(diagnostic.get("severity"), Some(&1usize.into()));
+            let start = diagnostic.get("range").unwrap().get("start").unwrap();
+            assert_eq!(start.get("line"), Some(&(*line).into()));
+            let text = diagnostic.get("message").unwrap().as_str().unwrap();
+            assert!(text.starts_with(message));
+            assert_eq!(
+                result(&mut server, "textDocument/hover", at(0, 0)),
+                Json::Null
+            );
+        }
+
+        open(&mut server, "PUSH 1");
+        assert_ne!(
+            result(&mut server, "textDocument/hover", at(0, 0)),
+            Json::Null
+        );
+    }
+
+    #[test]
+    fn messages() {
+        let input: String = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":"x","method":"foo"}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+        ]
+        .iter()
+        .map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body))
+        .collect();
+        let mut output = Vec::new();
+        assert!(run(input.as_bytes(), &mut output).unwrap());
+        let output = String::from_utf8(output).unwrap();
+        let bodies: Vec<_> = output.split("Content-Length: ").skip(1).collect();
+        assert_eq!(bodies.len(), 3);
+        assert!(bodies[0].contains("\"hoverProvider\":true"));
+        assert!(bodies[1].ends_with(
+            "{\"jsonrpc\":\"2.0\",\"id\":\"x\",\"error\":{\"code\":-32601,\"message\":\"Unknown method foo\"}}"
+        ));
+        assert!(bodies[2].ends_with("{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":null}"));
+
+        // Too long and too deeply nested messages are rejected, the next ones are served
+        let nested = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+        let long = " ".repeat(MAX_MESSAGE_LEN + 1);
+        let input: String = [nested.as_str(), long.as_str(), r#"{"method":"exit"}"#]
+            .iter()
+            .map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body))
+            .collect();
+        let mut output = Vec::new();
+        assert!(!run(input.as_bytes(), &mut output).unwrap());
+        let output = String::from_utf8(output).unwrap();
+        let errors: Vec<_> = output.split("Content-Length: ").skip(1).collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|error| error.contains("\"code\":-32700")));
+
+        assert_eq!(
+            uri_to_path("file:///home/a%20b.sasm"),
+            Some(PathBuf::from("/home/a b.sasm"))
+        );
+        assert_eq!(word_at("PUSH TEN", 6), Some("TEN"));
+        assert_eq!(word_at("PUSH TEN", 8), Some("TEN"));
+        assert_eq!(word_at("é TEN", 1), None);
+    }
+}
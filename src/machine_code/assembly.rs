@@ -1,4 +1,6 @@
-use crate::models::{Assembly, Op, Statement};
+use std::convert::TryInto;
+
+use crate::models::{Assembly, Op, Register, Statement, Width};
 
 use super::*;
 
@@ -6,12 +8,30 @@ impl Decompile for Assembly {
     type Error = AssemblyDecompileError;
 
     fn decompile(bytes: &[u8]) -> Result<DecompileResult<Self>, Self::Error> {
-        let mut idx = 0;
+        let header = Header::decompile(bytes)?;
+        let Header {
+            width,
+            registers,
+            imports,
+        } = header.value;
+        let mut idx = header.bytes_read;
         let mut result = Vec::new();
+        if width != Width::default() {
+            result.push(Statement::Width(width));
+        }
+        if registers != Register::DEFAULT_COUNT {
+            result.push(Statement::Registers(registers));
+        }
 
         while idx < bytes.len() {
-            let op = Op::decompile(&bytes[idx..])?; // todo: unwrap
-            result.push(Statement::Op(op.value));
+            let op = Op::decompile_with(&bytes[idx..], width)?; // todo: unwrap
+            result.push(match op.value {
+                Op::CallHost(import) => match imports.get(usize::from(import)) {
+                    Some(name) => Statement::CallHost(name.clone()),
+                    None => return Err(AssemblyDecompileError::WrongImport(import)),
+                },
+                op => Statement::Op(op),
+            });
             idx += op.bytes_read;
         }
 
@@ -23,15 +43,74 @@ impl Decompile for Assembly {
 }
 
 impl Compile for Assembly {
-    type Error = OutputError;
+    type Error = AssemblyCompileError;
 
+    /// Compiles assembly into an executable program.
+    ///
+    /// Fails if assembly uses external symbols, such assembly should be compiled
+    /// into an `Object` and linked.
     fn compile(&self, output: &mut impl std::io::Write) -> Result<(), Self::Error> {
+        self.compile_with_source_map(output).map(drop)
+    }
+}
+
+impl Assembly {
+    /// Compiles assembly into an executable program, like `compile`, and returns
+    /// locations of its operations in the source.
+    pub fn compile_with_source_map(
+        &self,
+        output: &mut impl std::io::Write,
+    ) -> Result<SourceMap, AssemblyCompileError> {
+        let width = self.width();
+        let mut imports: Vec<&String> = Vec::new();
         for statement in self.statements() {
+            if let Statement::CallHost(name) = statement {
+                if !imports.contains(&name) {
+                    imports.push(name);
+                }
+            }
+        }
+        if imports.len() > MAX_IMPORTS {
+            return Err(AssemblyCompileError::TooManyHostFunctions(imports.len()));
+        }
+
+        let header = Header {
+            width,
+            registers: self.registers(),
+            imports: imports.iter().map(|&name| name.clone()).collect(),
+        };
+        // Code is compiled into memory to find offsets of the operations
+        let mut code = Vec::new();
+        if header.is_required() {
+            header.compile(&mut code)?;
+        }
+
+        let mut source_map = SourceMap::default();
+        for (index, statement) in self.statements().iter().enumerate() {
+            if let (Statement::Op(_) | Statement::CallHost(_), Some(location)) =
+                (statement, self.location(index))
+            {
+                source_map.insert(code.len(), location.clone());
+            }
             match statement {
-                Statement::Op(op) => op.compile(output)?,
+                Statement::Op(op) => op.compile_with(width, &mut code)?,
+                Statement::CallHost(name) => {
+                    let import = imports.iter().position(|&import| import == name);
+                    let import = import.expect("all names are imported").try_into().unwrap();
+                    Op::CallHost(import).compile_with(width, &mut code)?
+                }
+                Statement::PushExtern { symbol, .. } => {
+                    return Err(AssemblyCompileError::ExternalSymbol(symbol.clone()))
+                }
+                Statement::Constant { .. }
+                | Statement::Extern(_)
+                | Statement::Global(_)
+                | Statement::Width(_)
+                | Statement::Registers(_) => (),
             }
         }
-        Ok(())
+        output.write_all(&code).map_err(OutputError::from)?;
+        Ok(source_map)
     }
 }
 
@@ -46,12 +125,7 @@ mod test {
         let mut machine_code = Vec::new();
         asm.compile(&mut machine_code).unwrap();
         let asm_again = Assembly::decompile(machine_code.as_ref()).unwrap().value;
-        let pairs_iter = asm.statements().iter().zip(asm_again.statements());
-        for (left, right) in pairs_iter {
-            let Statement::Op(left) = left;
-            let Statement::Op(right) = right;
-            assert_eq!(left, right);
-        }
+        assert_eq!(asm.statements(), asm_again.statements());
     }
 
     #[test]
@@ -71,4 +145,104 @@ mod test {
             Statement::Op(Op::Add),
         ]);
     }
+
+    #[test]
+    fn wide_program() {
+        test_compile_decompile(vec![
+            Statement::Width(Width::W64),
+            Statement::Op(Op::PushValue(Value(i64::MAX))),
+            Statement::Op(Op::PushValue(Value(-1))),
+            Statement::Op(Op::Add),
+        ]);
+    }
+
+    #[test]
+    fn floats() {
+        test_compile_decompile(vec![
+            Statement::Op(Op::PushFloat(Float(0.1))),
+            Statement::Op(Op::PushValue(Value(2))),
+            Statement::Op(Op::IToF),
+            Statement::Op(Op::FDiv),
+            Statement::Op(Op::FOutput),
+        ]);
+    }
+
+    #[test]
+    fn register_operands() {
+        let statements = vec![
+            Statement::Op(Op::MovValue(Register::A, Value(-5))),
+            Statement::Op(Op::MovRegister(Register::B, Register::A)),
+            Statement::Op(Op::Inc(Register::C)),
+            Statement::Op(Op::Dec(Register::D)),
+            Statement::Op(Op::AddValue(Register::B, Value(100))),
+        ];
+        test_compile_decompile(statements.clone());
+
+        let mut wide = vec![Statement::Width(Width::W64)];
+        wide.extend(statements);
+        test_compile_decompile(wide);
+    }
+
+    #[test]
+    fn register_count() {
+        let r15 = Register::new(15).unwrap();
+        test_compile_decompile(vec![
+            Statement::Registers(16),
+            Statement::Op(Op::MovValue(r15, Value(1))),
+            Statement::Op(Op::PushRegister(r15)),
+        ]);
+        test_compile_decompile(vec![
+            Statement::Width(Width::W64),
+            Statement::Registers(2),
+            Statement::Op(Op::PushRegister(Register::B)),
+        ]);
+    }
+
+    #[test]
+    fn frames() {
+        test_compile_decompile(vec![
+            Statement::Op(Op::Enter(2)),
+            Statement::Op(Op::Arg(0)),
+            Statement::Op(Op::LocalSet(1)),
+            Statement::Op(Op::LocalGet(1)),
+            Statement::Op(Op::Leave),
+        ]);
+    }
+
+    #[test]
+    fn host_calls() {
+        let statements = vec![
+            Statement::CallHost("print".into()),
+            Statement::Op(Op::Syscall(2)),
+            Statement::CallHost("read".into()),
+            Statement::CallHost("print".into()),
+        ];
+        let assembly = Assembly::new(statements.clone());
+        let mut bytes = Vec::new();
+        assembly.compile(&mut bytes).unwrap();
+        assert_eq!(&bytes[bytes.len() - 8..], [32, 0, 31, 2, 32, 1, 32, 0]);
+        test_compile_decompile(statements);
+
+        let result = Assembly::decompile(&[32, 0]);
+        assert!(matches!(
+            result,
+            Err(AssemblyDecompileError::WrongImport(0))
+        ));
+    }
+
+    #[test]
+    fn source_map() {
+        let assembly: Assembly = ".registers 8\n.equ X 1\n\nPUSH X\nPOP A\nCALLHOST f\n"
+            .parse()
+            .unwrap();
+        let mut bytes = Vec::new();
+        let source_map = assembly.compile_with_source_map(&mut bytes).unwrap();
+        let lines: Vec<_> = source_map
+            .iter()
+            .map(|(offset, location)| (offset, location.line))
+            .collect();
+        // Header is "SMX", flags, number of registers and the import of f
+        assert_eq!(lines, vec![(9, 3), (14, 4), (16, 5)]);
+        assert_eq!(bytes.len(), 18);
+    }
 }
@@ -1,6 +1,8 @@
 use std::io;
 use thiserror::Error;
 
+use crate::models::Width;
+
 #[derive(Error, Debug)]
 #[error("Can't write compiled code: {0}")]
 pub struct OutputError(#[from] io::Error);
@@ -9,13 +11,13 @@ pub struct OutputError(#[from] io::Error);
 #[error("Can't read compiled code: {0}")]
 pub struct InputError(#[from] io::Error);
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 #[error("Unexpected end of file when reading {name}")]
 pub struct EndOfInput {
     pub name: &'static str,
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum RegisterDecompileError {
     #[error(transparent)]
     EndOfInput(#[from] EndOfInput),
@@ -24,13 +26,13 @@ pub enum RegisterDecompileError {
     WrongRegister { register: u8 },
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 #[error("Incorrect op code byte: {op_code}")]
 pub struct WrongOpCode {
     pub op_code: u8,
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum OpDecompileError {
     #[error(transparent)]
     WrongOpCode(#[from] WrongOpCode),
@@ -42,8 +44,118 @@ pub enum OpDecompileError {
     EndOfInput(#[from] EndOfInput),
 }
 
+#[derive(Error, Debug)]
+pub enum HeaderDecompileError {
+    #[error(transparent)]
+    EndOfInput(#[from] EndOfInput),
+
+    #[error("Program header is corrupted")]
+    WrongMagic,
+
+    #[error("Unknown flags in the program header: {0:#010b}")]
+    WrongFlags(u8),
+
+    #[error("Program header declares {0} registers, expected 1 to 16")]
+    WrongRegisterCount(u8),
+
+    #[error("Name of the host function in the program header is not valid UTF-8")]
+    WrongImport,
+}
+
 #[derive(Error, Debug)]
 pub enum AssemblyDecompileError {
+    #[error(transparent)]
+    HeaderDecompileError(#[from] HeaderDecompileError),
+
     #[error(transparent)]
     OpDecompileError(#[from] OpDecompileError),
+
+    #[error("Host function {0} is called, but it's not in the program header")]
+    WrongImport(u8),
+}
+
+#[derive(Error, Debug)]
+pub enum AssemblyCompileError {
+    #[error(transparent)]
+    OutputError(#[from] OutputError),
+
+    #[error("Symbol {0} is external, assembly must be compiled into an object file and linked")]
+    ExternalSymbol(String),
+
+    #[error("Program calls {0} host functions, at most 255 are supported")]
+    TooManyHostFunctions(usize),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ObjectError {
+    #[error("Symbol {0} is declared global, but it's not a constant defined in this file")]
+    UndefinedGlobal(String),
+}
+
+#[derive(Error, Debug)]
+pub enum ObjectDecompileError {
+    #[error("Not an object file")]
+    WrongMagic,
+
+    #[error(transparent)]
+    EndOfInput(#[from] EndOfInput),
+
+    #[error("Symbol name is not valid UTF-8")]
+    WrongSymbolName,
+
+    #[error("Unsupported word width: {0} bits")]
+    WrongWidth(u8),
+
+    #[error("Object file declares {0} registers, expected 1 to 16")]
+    WrongRegisterCount(u8),
+}
+
+#[derive(Error, Debug)]
+pub enum StateDecompileError {
+    #[error("Not a machine state")]
+    WrongMagic,
+
+    #[error("Unsupported version of the machine state: {0}")]
+    WrongVersion(u8),
+
+    #[error(transparent)]
+    EndOfInput(#[from] EndOfInput),
+
+    #[error("Unsupported word width: {0} bits")]
+    WrongWidth(u8),
+
+    #[error("Machine state has {0} registers, expected 1 to 16")]
+    WrongRegisterCount(u8),
+
+    #[error("Incorrect halted flag: {0}")]
+    WrongHalted(u8),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    #[error("Symbol {name} is defined in both {first} and {second}")]
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("Undefined symbol {name} is used in {object}")]
+    UndefinedSymbol { name: String, object: String },
+
+    #[error("Value of symbol {name} used in {object} overflows the machine word")]
+    Overflow { name: String, object: String },
+
+    #[error("Program calls {0} host functions, at most 255 are supported")]
+    TooManyHostFunctions(usize),
+
+    #[error("Relocation at offset {offset} is outside of the code of {object}")]
+    WrongRelocation { offset: usize, object: String },
+
+    #[error("{object} uses {width}-bit words, but previous objects use {expected}-bit words")]
+    WidthMismatch {
+        object: String,
+        width: Width,
+        expected: Width,
+    },
 }
@@ -0,0 +1,234 @@
+//! Header of the compiled program.
+//!
+//! The header is optional, programs without it use 32-bit words:
+//!
+//! ```text
+//! "SMX"
+//! u8 flags, bit 0 is set for 64-bit words, bit 1 if the number of registers follows,
+//!   bit 2 if the imports follow
+//! u8 number of registers, only if bit 1 is set
+//! u8 number of imports, for each: u16 name length, UTF-8 name; only if bit 2 is set
+//! ```
+//!
+//! Imports are names of host functions, `CALLHOST` operations refer to them by index.
+//!
+//! Header can't be mistaken for code, since `S` is not an operation code.
+
+use std::{convert::TryInto, io};
+
+use crate::models::{Register, Width};
+
+use super::{object::write_name, *};
+
+const MAGIC: &[u8; 3] = b"SMX";
+
+const WIDE: u8 = 0b01;
+
+const REGISTERS: u8 = 0b10;
+
+const IMPORTS: u8 = 0b100;
+
+/// Maximum number of host functions a program can import.
+pub const MAX_IMPORTS: usize = u8::MAX as usize;
+
+/// Settings of the compiled program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub width: Width,
+
+    /// Number of general-purpose registers, from 1 to `Register::MAX_COUNT`.
+    pub registers: u8,
+
+    /// Names of host functions called by the program, at most `MAX_IMPORTS`.
+    pub imports: Vec<String>,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header {
+            width: Width::default(),
+            registers: Register::DEFAULT_COUNT,
+            imports: Vec::new(),
+        }
+    }
+}
+
+impl Header {
+    /// Returns `true` if the program has to start with the header,
+    /// programs with default settings are compiled without it.
+    pub fn is_required(&self) -> bool {
+        *self != Header::default()
+    }
+}
+
+impl Compile for Header {
+    type Error = OutputError;
+
+    fn compile(&self, output: &mut impl std::io::Write) -> Result<(), Self::Error> {
+        let mut flags = match self.width {
+            Width::W32 => 0,
+            Width::W64 => WIDE,
+        };
+        if self.registers != Register::DEFAULT_COUNT {
+            flags |= REGISTERS;
+        }
+        if !self.imports.is_empty() {
+            flags |= IMPORTS;
+        }
+        output.write_all(MAGIC)?;
+        output.write_all(&[flags])?;
+        if flags & REGISTERS != 0 {
+            output.write_all(&[self.registers])?;
+        }
+        if flags & IMPORTS != 0 {
+            let count = self.imports.len().try_into().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "too many host functions")
+            })?;
+            output.write_all(&[count])?;
+            for name in self.imports.iter() {
+                write_name(output, name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the header, returns the default one if program doesn't start with a header.
+impl Decompile for Header {
+    type Error = HeaderDecompileError;
+
+    fn decompile(bytes: &[u8]) -> Result<DecompileResult<Self>, Self::Error> {
+        if !bytes.starts_with(&MAGIC[..1]) {
+            return Ok(DecompileResult {
+                value: Header::default(),
+                bytes_read: 0,
+            });
+        }
+
+        let flags = match bytes {
+            [b'S', b'M', b'X', flags, ..] => *flags,
+            _ if MAGIC.starts_with(bytes) => Err(EndOfInput { name: "Header" })?,
+            _ => return Err(HeaderDecompileError::WrongMagic),
+        };
+        if flags & !(WIDE | REGISTERS | IMPORTS) != 0 {
+            return Err(HeaderDecompileError::WrongFlags(flags));
+        }
+        let width = match flags & WIDE {
+            0 => Width::W32,
+            _ => Width::W64,
+        };
+
+        let mut bytes_read = MAGIC.len() + 1;
+        let registers = if flags & REGISTERS == 0 {
+            Register::DEFAULT_COUNT
+        } else {
+            let count = *bytes.get(bytes_read).ok_or(EndOfInput { name: "Header" })?;
+            if count == 0 || count > Register::MAX_COUNT {
+                return Err(HeaderDecompileError::WrongRegisterCount(count));
+            }
+            bytes_read += 1;
+            count
+        };
+
+        let mut imports = Vec::new();
+        if flags & IMPORTS != 0 {
+            let mut take = |len: usize| {
+                let taken = bytes.get(bytes_read..bytes_read + len);
+                bytes_read += len;
+                taken.ok_or(EndOfInput { name: "Header" })
+            };
+            for _ in 0..take(1)?[0] {
+                let len = u16::from_be_bytes(take(2)?.try_into().unwrap());
+                let name = take(len.into())?.to_vec();
+                let name =
+                    String::from_utf8(name).map_err(|_| HeaderDecompileError::WrongImport)?;
+                imports.push(name);
+            }
+        }
+
+        Ok(DecompileResult {
+            value: Header {
+                width,
+                registers,
+                imports,
+            },
+            bytes_read,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compile_decompile() {
+        let header = Header {
+            width: Width::W64,
+            ..Header::default()
+        };
+        assert!(header.is_required());
+        let mut bytes = Vec::new();
+        header.compile(&mut bytes).unwrap();
+        assert_eq!(bytes, b"SMX\x01");
+        let decompiled = Header::decompile(&bytes).unwrap();
+        assert_eq!((decompiled.value, decompiled.bytes_read), (header, 4));
+
+        let header = Header {
+            width: Width::W32,
+            registers: 16,
+            ..Header::default()
+        };
+        let mut bytes = Vec::new();
+        header.compile(&mut bytes).unwrap();
+        assert_eq!(bytes, b"SMX\x02\x10");
+        let decompiled = Header::decompile(&bytes).unwrap();
+        assert_eq!((decompiled.value, decompiled.bytes_read), (header, 5));
+
+        let header = Header {
+            imports: vec!["log".into(), "now".into()],
+            ..Header::default()
+        };
+        let mut bytes = Vec::new();
+        header.compile(&mut bytes).unwrap();
+        assert_eq!(bytes, b"SMX\x04\x02\x00\x03log\x00\x03now");
+        let decompiled = Header::decompile(&bytes).unwrap();
+        assert_eq!((decompiled.value, decompiled.bytes_read), (header, 15));
+        assert!(matches!(
+            Header::decompile(&bytes[..10]),
+            Err(HeaderDecompileError::EndOfInput(_))
+        ));
+    }
+
+    #[test]
+    fn headerless() {
+        let decompiled = Header::decompile(&[8, 0, 0, 0, 1]).unwrap();
+        assert_eq!(decompiled.value, Header::default());
+        assert_eq!(decompiled.bytes_read, 0);
+        assert!(!decompiled.value.is_required());
+    }
+
+    #[test]
+    fn wrong_header() {
+        assert!(matches!(
+            Header::decompile(b"SMX\x80"),
+            Err(HeaderDecompileError::WrongFlags(0x80))
+        ));
+        assert!(matches!(
+            Header::decompile(b"SMX\x02\x11"),
+            Err(HeaderDecompileError::WrongRegisterCount(17))
+        ));
+        assert!(matches!(
+            Header::decompile(b"SMX\x02"),
+            Err(HeaderDecompileError::EndOfInput(_))
+        ));
+        assert!(matches!(
+            Header::decompile(b"SM"),
+            Err(HeaderDecompileError::EndOfInput(_))
+        ));
+        assert!(matches!(
+            Header::decompile(b"SMOB"),
+            Err(HeaderDecompileError::WrongMagic)
+        ));
+    }
+}
@@ -1,14 +1,22 @@
 mod assembly;
 mod error;
+mod header;
+mod object;
 mod op;
 mod op_code;
 mod register;
+mod source_map;
+mod state;
 mod value;
 
 use std::io;
 
 pub use error::*;
+pub use header::{Header, MAX_IMPORTS};
+pub use object::{link, Export, HostCall, Object, Relocation};
 pub use op_code::OpCode;
+pub use source_map::SourceMap;
+pub use state::{FrameState, MachineState};
 
 pub trait Compile {
     type Error;
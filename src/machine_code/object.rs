@@ -0,0 +1,562 @@
+//! Relocatable object files and the linker.
+//!
+//! Object file layout, all integers are big-endian:
+//!
+//! ```text
+//! "SMOB"
+//! u8 width of the machine word in bits
+//! u8 number of registers
+//! u32 code length, code
+//! u32 number of exports, for each: name, value
+//! u32 number of relocations, for each: u32 offset, name, addend
+//! u32 number of host calls, for each: u32 offset, name
+//! ```
+//!
+//! Names are stored as u16 length followed by UTF-8 bytes.
+//! Values and addends are words of the object's width.
+
+use std::{collections::HashMap, convert::TryInto, io};
+
+use crate::models::{Assembly, Op, Register, Statement, Value, Width};
+
+use super::*;
+
+const MAGIC: &[u8; 4] = b"SMOB";
+
+/// Compiled assembly, which may use symbols from other object files.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Object {
+    /// Width of the machine word, objects of different widths can't be linked.
+    pub width: Width,
+
+    /// Number of registers used by the code, linked program gets the largest one.
+    pub registers: u8,
+
+    /// Compiled code, values of external symbols are zeroed.
+    pub code: Vec<u8>,
+
+    /// Constants exported with `.global`.
+    pub exports: Vec<Export>,
+
+    /// Places in the code, which should be patched by the linker.
+    pub relocations: Vec<Relocation>,
+
+    /// `CALLHOST` operations, the linker fills in their indices in the import table.
+    pub host_calls: Vec<HostCall>,
+}
+
+/// Constant, which can be used by other object files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Export {
+    pub name: String,
+    pub value: Value,
+}
+
+/// Value in the code, which depends on an external symbol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    /// Offset of the value in the object's code.
+    pub offset: usize,
+
+    /// External symbol.
+    pub symbol: String,
+
+    /// Value, which is added to the symbol.
+    pub addend: Value,
+}
+
+/// Call of a host function by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostCall {
+    /// Offset of the import index in the object's code.
+    pub offset: usize,
+
+    /// Name of the host function.
+    pub name: String,
+}
+
+impl Object {
+    /// Compiles assembly into an object file.
+    pub fn assemble(assembly: &Assembly) -> Result<Object, ObjectError> {
+        let width = assembly.width();
+        let mut code = Vec::new();
+        let mut constants = HashMap::new();
+        let mut globals = Vec::new();
+        let mut relocations = Vec::new();
+        let mut host_calls = Vec::new();
+
+        for statement in assembly.statements() {
+            match statement {
+                Statement::Op(op) => compile_to_vec(op, width, &mut code),
+                Statement::PushExtern { symbol, addend } => {
+                    compile_to_vec(&Op::PushValue(Value(0)), width, &mut code);
+                    relocations.push(Relocation {
+                        offset: code.len() - width.bytes(),
+                        symbol: symbol.clone(),
+                        addend: *addend,
+                    });
+                }
+                Statement::CallHost(name) => {
+                    compile_to_vec(&Op::CallHost(0), width, &mut code);
+                    host_calls.push(HostCall {
+                        offset: code.len() - 1,
+                        name: name.clone(),
+                    });
+                }
+                Statement::Constant { name, value } => {
+                    constants.insert(name, *value);
+                }
+                Statement::Global(name) => globals.push(name),
+                Statement::Extern(_) | Statement::Width(_) | Statement::Registers(_) => (),
+            }
+        }
+
+        let exports = globals
+            .into_iter()
+            .map(|name| match constants.get(name) {
+                Some(&value) => Ok(Export {
+                    name: name.clone(),
+                    value,
+                }),
+                None => Err(ObjectError::UndefinedGlobal(name.clone())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Object {
+            width,
+            registers: assembly.registers(),
+            code,
+            exports,
+            relocations,
+            host_calls,
+        })
+    }
+}
+
+fn compile_to_vec(op: &Op, width: Width, code: &mut Vec<u8>) {
+    op.compile_with(width, code)
+        .expect("values are checked by the parser and writing to Vec never fails");
+}
+
+/// Links object files into an executable program.
+///
+/// Each object comes with a name, which is used in error messages.
+/// All objects must have the same word width, the program gets as many registers
+/// as the object which declares most of them.
+pub fn link(objects: &[(String, Object)]) -> Result<Vec<u8>, LinkError> {
+    let width = objects.first().map(|(_, object)| object.width);
+    let width = width.unwrap_or_default();
+    let registers = objects.iter().map(|(_, object)| object.registers).max();
+    let registers = registers.unwrap_or(Register::DEFAULT_COUNT);
+
+    let mut symbols: HashMap<&str, (&str, Value)> = HashMap::new();
+    for (object_name, object) in objects {
+        if object.width != width {
+            return Err(LinkError::WidthMismatch {
+                object: object_name.clone(),
+                width: object.width,
+                expected: width,
+            });
+        }
+        for export in object.exports.iter() {
+            if let Some((first, _)) = symbols.get(export.name.as_str()) {
+                return Err(LinkError::DuplicateSymbol {
+                    name: export.name.clone(),
+                    first: first.to_string(),
+                    second: object_name.clone(),
+                });
+            }
+            symbols.insert(&export.name, (object_name, export.value));
+        }
+    }
+
+    let mut imports: Vec<&str> = Vec::new();
+    for (_, object) in objects {
+        for call in object.host_calls.iter() {
+            if !imports.contains(&call.name.as_str()) {
+                imports.push(&call.name);
+            }
+        }
+    }
+    if imports.len() > MAX_IMPORTS {
+        return Err(LinkError::TooManyHostFunctions(imports.len()));
+    }
+
+    let mut program = Vec::new();
+    let header = Header {
+        width,
+        registers,
+        imports: imports.iter().map(|&name| name.to_owned()).collect(),
+    };
+    if header.is_required() {
+        header
+            .compile(&mut program)
+            .expect("writing to Vec never fails");
+    }
+
+    for (object_name, object) in objects {
+        let mut code = object.code.clone();
+        for relocation in object.relocations.iter() {
+            let value = match symbols.get(relocation.symbol.as_str()) {
+                Some((_, value)) => value.value(),
+                None => {
+                    return Err(LinkError::UndefinedSymbol {
+                        name: relocation.symbol.clone(),
+                        object: object_name.clone(),
+                    })
+                }
+            };
+            let value = value
+                .checked_add(relocation.addend.value())
+                .filter(|&value| width.contains(value))
+                .ok_or_else(|| LinkError::Overflow {
+                    name: relocation.symbol.clone(),
+                    object: object_name.clone(),
+                })?;
+
+            let offset = relocation.offset;
+            let mut bytes = Vec::new();
+            compile_to_vec(&Op::PushValue(Value(value)), width, &mut bytes);
+            match code.get_mut(offset..offset + width.bytes()) {
+                Some(code) => code.copy_from_slice(&bytes[1..]),
+                None => {
+                    return Err(LinkError::WrongRelocation {
+                        offset,
+                        object: object_name.clone(),
+                    })
+                }
+            }
+        }
+        for call in object.host_calls.iter() {
+            let import = imports.iter().position(|&name| name == call.name);
+            let import = import.expect("all names are imported") as u8;
+            match code.get_mut(call.offset) {
+                Some(byte) => *byte = import,
+                None => {
+                    return Err(LinkError::WrongRelocation {
+                        offset: call.offset,
+                        object: object_name.clone(),
+                    })
+                }
+            }
+        }
+        program.extend(code);
+    }
+    Ok(program)
+}
+
+impl Compile for Object {
+    type Error = OutputError;
+
+    fn compile(&self, output: &mut impl io::Write) -> Result<(), Self::Error> {
+        output.write_all(MAGIC)?;
+        output.write_all(&[self.width.bits() as u8, self.registers])?;
+        write_u32(output, self.code.len())?;
+        output.write_all(&self.code)?;
+
+        write_u32(output, self.exports.len())?;
+        for export in self.exports.iter() {
+            write_name(output, &export.name)?;
+            export.value.compile_with(self.width, output)?;
+        }
+
+        write_u32(output, self.relocations.len())?;
+        for relocation in self.relocations.iter() {
+            write_u32(output, relocation.offset)?;
+            write_name(output, &relocation.symbol)?;
+            relocation.addend.compile_with(self.width, output)?;
+        }
+
+        write_u32(output, self.host_calls.len())?;
+        for call in self.host_calls.iter() {
+            write_u32(output, call.offset)?;
+            write_name(output, &call.name)?;
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn write_u32(output: &mut impl io::Write, x: usize) -> Result<(), OutputError> {
+    let x: u32 = x
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "object file is too large"))?;
+    output.write_all(&x.to_be_bytes())?;
+    Ok(())
+}
+
+pub(super) fn write_name(output: &mut impl io::Write, name: &str) -> Result<(), OutputError> {
+    let len: u16 = name
+        .len()
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "symbol name is too long"))?;
+    output.write_all(&len.to_be_bytes())?;
+    output.write_all(name.as_bytes())?;
+    Ok(())
+}
+
+impl Decompile for Object {
+    type Error = ObjectDecompileError;
+
+    fn decompile(bytes: &[u8]) -> Result<DecompileResult<Self>, Self::Error> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(ObjectDecompileError::WrongMagic);
+        }
+        let mut reader = Reader {
+            bytes,
+            position: MAGIC.len(),
+            width: Width::W32,
+        };
+        reader.width = match reader.take(1, "Width")? {
+            [32] => Width::W32,
+            [64] => Width::W64,
+            &[bits] => return Err(ObjectDecompileError::WrongWidth(bits)),
+            _ => unreachable!(),
+        };
+        let registers = match reader.take(1, "Number of registers")? {
+            [count] if 0 < *count && *count <= Register::MAX_COUNT => *count,
+            [count] => return Err(ObjectDecompileError::WrongRegisterCount(*count)),
+            _ => unreachable!(),
+        };
+
+        let code_len = reader.u32("Code length")?;
+        let code = reader.take(code_len, "Code")?.to_vec();
+
+        let mut exports = Vec::new();
+        for _ in 0..reader.u32("Number of exports")? {
+            let name = reader.name()?;
+            let value = reader.value()?;
+            exports.push(Export { name, value });
+        }
+
+        let mut relocations = Vec::new();
+        for _ in 0..reader.u32("Number of relocations")? {
+            let offset = reader.u32("Relocation offset")?;
+            let symbol = reader.name()?;
+            let addend = reader.value()?;
+            relocations.push(Relocation {
+                offset,
+                symbol,
+                addend,
+            });
+        }
+
+        let mut host_calls = Vec::new();
+        for _ in 0..reader.u32("Number of host calls")? {
+            let offset = reader.u32("Host call offset")?;
+            let name = reader.name()?;
+            host_calls.push(HostCall { offset, name });
+        }
+
+        Ok(DecompileResult {
+            value: Object {
+                width: reader.width,
+                registers,
+                code,
+                exports,
+                relocations,
+                host_calls,
+            },
+            bytes_read: reader.position,
+        })
+    }
+}
+
+pub(super) struct Reader<'a> {
+    pub bytes: &'a [u8],
+    pub position: usize,
+    pub width: Width,
+}
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize, name: &'static str) -> Result<&'a [u8], EndOfInput> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or(EndOfInput { name })?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn u32(&mut self, name: &'static str) -> Result<usize, EndOfInput> {
+        let bytes = self.take(4, name)?.try_into().unwrap();
+        Ok(u32::from_be_bytes(bytes) as usize)
+    }
+
+    fn name(&mut self) -> Result<String, ObjectDecompileError> {
+        let len = self.take(2, "Symbol name length")?.try_into().unwrap();
+        let bytes = self.take(u16::from_be_bytes(len) as usize, "Symbol name")?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ObjectDecompileError::WrongSymbolName)
+    }
+
+    pub fn value(&mut self) -> Result<Value, EndOfInput> {
+        let value = Value::decompile_with(&self.bytes[self.position..], self.width)?;
+        self.position += value.bytes_read;
+        Ok(value.value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor::{Machine, MemoryDevice};
+
+    fn object(source: &str) -> Object {
+        let object = Object::assemble(&source.parse().unwrap()).unwrap();
+        let mut bytes = Vec::new();
+        object.compile(&mut bytes).unwrap();
+        let decompiled = Object::decompile(&bytes).unwrap();
+        assert_eq!(decompiled.bytes_read, bytes.len());
+        assert_eq!(decompiled.value, object);
+        object
+    }
+
+    fn run(program: &[u8]) -> String {
+        let mut device = MemoryDevice::default();
+        Machine::new(&mut device).execute_program(program).unwrap();
+        String::from_utf8(device.into_output()).unwrap()
+    }
+
+    #[test]
+    fn link_two_objects() {
+        let main = object(
+            "
+            .extern WIDTH
+            .extern HEIGHT
+            PUSH WIDTH
+            PUSH 2 + HEIGHT - 1
+            MUL
+            OUTPUT
+            HALT
+            ",
+        );
+        assert_eq!(main.relocations.len(), 2);
+        assert_eq!(main.relocations[1].addend, Value(1));
+
+        let sizes = object(
+            "
+            .global WIDTH
+            .global HEIGHT
+            .equ WIDTH 3
+            .equ HEIGHT WIDTH + 1
+            ",
+        );
+        assert_eq!(
+            sizes.exports,
+            vec![
+                Export {
+                    name: "WIDTH".into(),
+                    value: Value(3),
+                },
+                Export {
+                    name: "HEIGHT".into(),
+                    value: Value(4),
+                },
+            ]
+        );
+
+        let program = link(&[("main.o".into(), main), ("sizes.o".into(), sizes)]).unwrap();
+        assert_eq!(run(&program), "15\n");
+    }
+
+    #[test]
+    fn link_errors() {
+        let uses_x = || ("uses_x.o".to_string(), object(".extern X\nPUSH X + 1"));
+        let defines_x = |name: &str, value| {
+            let source = format!(".global X\n.equ X {}", value);
+            (name.to_string(), object(&source))
+        };
+
+        assert_eq!(
+            link(&[uses_x()]),
+            Err(LinkError::UndefinedSymbol {
+                name: "X".into(),
+                object: "uses_x.o".into(),
+            })
+        );
+        assert_eq!(
+            link(&[defines_x("a.o", 1), uses_x(), defines_x("b.o", 2)]),
+            Err(LinkError::DuplicateSymbol {
+                name: "X".into(),
+                first: "a.o".into(),
+                second: "b.o".into(),
+            })
+        );
+        assert_eq!(
+            link(&[uses_x(), defines_x("a.o", i32::MAX)]),
+            Err(LinkError::Overflow {
+                name: "X".into(),
+                object: "uses_x.o".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn undefined_global() {
+        let assembly = ".global X\n.extern Y".parse().unwrap();
+        assert_eq!(
+            Object::assemble(&assembly),
+            Err(ObjectError::UndefinedGlobal("X".into()))
+        );
+    }
+
+    #[test]
+    fn external_symbols_in_executable() {
+        let assembly: Assembly = ".extern X\nPUSH X".parse().unwrap();
+        let error = assembly.compile(&mut Vec::new()).unwrap_err();
+        assert!(matches!(error, AssemblyCompileError::ExternalSymbol(name) if name == "X"));
+    }
+
+    #[test]
+    fn link_wide_objects() {
+        let main = object(".width 64\n.extern BIG\nPUSH BIG + 1\nOUTPUT");
+        let big = object(".width 64\n.global BIG\n.equ BIG 0x7FFF_FFFF * 2");
+        let program = link(&[("main.o".into(), main), ("big.o".into(), big)]).unwrap();
+        assert_eq!(run(&program), "4294967295\n");
+
+        let main = object(".width 64\n.extern X\nPUSH X");
+        let x = object(".global X\n.equ X 1");
+        assert_eq!(
+            link(&[("main.o".into(), main), ("x.o".into(), x)]),
+            Err(LinkError::WidthMismatch {
+                object: "x.o".into(),
+                width: Width::W32,
+                expected: Width::W64,
+            })
+        );
+    }
+
+    #[test]
+    fn link_register_counts() {
+        let main = object(".registers 6\n.extern X\nPUSH X\nPOP R5\nPUSH R5\nOUTPUT");
+        assert_eq!(main.registers, 6);
+        let x = object(".registers 8\n.global X\n.equ X 3\nPUSH 1\nPOP R7");
+        let program = link(&[("main.o".into(), main), ("x.o".into(), x)]).unwrap();
+        assert_eq!(&program[..5], b"SMX\x02\x08");
+        assert_eq!(run(&program), "3\n");
+    }
+
+    #[test]
+    fn link_host_calls() {
+        let a = object("CALLHOST first\nCALLHOST second");
+        assert_eq!(
+            a.host_calls,
+            vec![
+                HostCall {
+                    offset: 1,
+                    name: "first".into(),
+                },
+                HostCall {
+                    offset: 3,
+                    name: "second".into(),
+                },
+            ]
+        );
+        let b = object("CALLHOST second\nCALLHOST third");
+        let program = link(&[("a.o".into(), a), ("b.o".into(), b)]).unwrap();
+        let assembly = Assembly::decompile(&program).unwrap().value;
+        assert_eq!(
+            assembly.to_string(),
+            "CALLHOST first\nCALLHOST second\nCALLHOST second\nCALLHOST third\n"
+        );
+    }
+}
@@ -1,29 +1,51 @@
 use std::convert::TryFrom;
 
-use crate::models::{Op, Register, Value};
+use crate::models::{Float, Op, Register, Value, Width};
 
 use super::*;
 
-impl Compile for Op {
-    type Error = OutputError;
-
-    fn compile(&self, output: &mut impl std::io::Write) -> Result<(), Self::Error> {
+impl Op {
+    /// Encodes operation, values are encoded as words of given width.
+    pub fn compile_with(
+        &self,
+        width: Width,
+        output: &mut impl std::io::Write,
+    ) -> Result<(), OutputError> {
         let op_code: u8 = OpCode::from(self).into();
-        output.write(&[op_code])?;
+        output.write_all(&[op_code])?;
 
         match self {
-            Op::PushValue(v) => v.compile(output)?,
-            Op::PushRegister(r) | Op::PopRegister(r) => r.compile(output)?,
+            Op::PushValue(v) => v.compile_with(width, output)?,
+            Op::PushFloat(v) => v.compile(output)?,
+            Op::PushRegister(r) | Op::PopRegister(r) | Op::Inc(r) | Op::Dec(r) => {
+                r.compile(output)?
+            }
+            Op::Enter(x)
+            | Op::LocalGet(x)
+            | Op::LocalSet(x)
+            | Op::Arg(x)
+            | Op::Syscall(x)
+            | Op::CallHost(x)
+            | Op::In(x)
+            | Op::Out(x) => output.write_all(&[*x])?,
+            Op::MovRegister(r, source) => {
+                r.compile(output)?;
+                source.compile(output)?;
+            }
+            Op::MovValue(r, v) | Op::AddValue(r, v) => {
+                r.compile(output)?;
+                v.compile_with(width, output)?;
+            }
             _ => (),
         };
         Ok(())
     }
-}
 
-impl Decompile for Op {
-    type Error = OpDecompileError;
-
-    fn decompile(bytes: &[u8]) -> Result<DecompileResult<Self>, Self::Error> {
+    /// Decodes operation, values are decoded as words of given width.
+    pub fn decompile_with(
+        bytes: &[u8],
+        width: Width,
+    ) -> Result<DecompileResult<Self>, OpDecompileError> {
         use OpCode::*;
 
         let op_code = OpCode::try_from(bytes[0])?;
@@ -34,11 +56,49 @@ impl Decompile for Op {
             (Mul, _) => Op::Mul,
             (Div, _) => Op::Div,
             (Mod, _) => Op::Mod,
+            (Shl, _) => Op::Shl,
             (Input, _) => Op::Input,
             (Output, _) => Op::Output,
             (Halt, _) => Op::Halt,
+            (FAdd, _) => Op::FAdd,
+            (FSub, _) => Op::FSub,
+            (FMul, _) => Op::FMul,
+            (FDiv, _) => Op::FDiv,
+            (FSqrt, _) => Op::FSqrt,
+            (IToF, _) => Op::IToF,
+            (FToI, _) => Op::FToI,
+            (FOutput, _) => Op::FOutput,
+            (Leave, _) => Op::Leave,
+            (Assert, _) => Op::Assert,
+            (AssertEq, _) => Op::AssertEq,
+            (Breakpoint, _) => Op::Breakpoint,
+            (Enter, bytes) => Op::Enter(index(bytes)?),
+            (LocalGet, bytes) => Op::LocalGet(index(bytes)?),
+            (LocalSet, bytes) => Op::LocalSet(index(bytes)?),
+            (Arg, bytes) => Op::Arg(index(bytes)?),
+            (Syscall, bytes) => Op::Syscall(index(bytes)?),
+            (CallHost, bytes) => Op::CallHost(index(bytes)?),
+            (In, bytes) => Op::In(index(bytes)?),
+            (Out, bytes) => Op::Out(index(bytes)?),
+            (PushFloat, bytes) => Op::PushFloat(Float::decompile(bytes)?.value),
+            (Inc, bytes) => Op::Inc(Register::decompile(bytes)?.value),
+            (Dec, bytes) => Op::Dec(Register::decompile(bytes)?.value),
+            (MovRegister, bytes) => {
+                let reg = Register::decompile(bytes)?;
+                let source = Register::decompile(&bytes[reg.bytes_read..])?;
+                Op::MovRegister(reg.value, source.value)
+            }
+            (MovValue, bytes) | (AddValue, bytes) => {
+                let reg = Register::decompile(bytes)?;
+                let v = Value::decompile_with(&bytes[reg.bytes_read..], width)?;
+                if op_code == MovValue {
+                    Op::MovValue(reg.value, v.value)
+                } else {
+                    Op::AddValue(reg.value, v.value)
+                }
+            }
             (PushValue, bytes) => {
-                let v = Value::decompile(bytes)?;
+                let v = Value::decompile_with(bytes, width)?;
                 Op::PushValue(v.value)
             }
             (PushRegister, bytes) => {
@@ -53,7 +113,30 @@ impl Decompile for Op {
 
         Ok(DecompileResult {
             value: op,
-            bytes_read: OpCode::from(&op).op_len(),
+            bytes_read: OpCode::from(&op).op_len(width),
         })
     }
 }
+
+/// Decodes the byte argument of frame operations, ports and host calls.
+fn index(bytes: &[u8]) -> Result<u8, EndOfInput> {
+    bytes.first().copied().ok_or(EndOfInput { name: "Index" })
+}
+
+/// Operations of programs with 32-bit words, see `Op::compile_with` for other widths.
+impl Compile for Op {
+    type Error = OutputError;
+
+    fn compile(&self, output: &mut impl std::io::Write) -> Result<(), Self::Error> {
+        self.compile_with(Width::W32, output)
+    }
+}
+
+/// Operations of programs with 32-bit words, see `Op::decompile_with` for other widths.
+impl Decompile for Op {
+    type Error = OpDecompileError;
+
+    fn decompile(bytes: &[u8]) -> Result<DecompileResult<Self>, Self::Error> {
+        Op::decompile_with(bytes, Width::W32)
+    }
+}
@@ -1,10 +1,10 @@
 use std::convert::TryFrom;
 
-use crate::models::Op;
+use crate::models::{Op, Width};
 
 use super::WrongOpCode;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum OpCode {
     Add = 0,
     Sub = 1,
@@ -17,14 +17,49 @@ pub enum OpCode {
     PushValue = 8,
     PushRegister = 9,
     PopRegister = 10,
+    Shl = 11,
+    PushFloat = 12,
+    FAdd = 13,
+    FSub = 14,
+    FMul = 15,
+    FDiv = 16,
+    FSqrt = 17,
+    IToF = 18,
+    FToI = 19,
+    FOutput = 20,
+    MovRegister = 21,
+    MovValue = 22,
+    Inc = 23,
+    Dec = 24,
+    AddValue = 25,
+    Enter = 26,
+    Leave = 27,
+    LocalGet = 28,
+    LocalSet = 29,
+    Arg = 30,
+    Syscall = 31,
+    CallHost = 32,
+    In = 33,
+    Out = 34,
+    Assert = 35,
+    AssertEq = 36,
+    Breakpoint = 37,
 }
 
 impl OpCode {
-    pub const fn op_len(&self) -> usize {
+    /// Length of the encoded operation in programs with given word width.
+    pub fn op_len(&self, width: Width) -> usize {
         use OpCode::*;
 
         match self {
-            PushValue => 5,
+            PushValue => 1 + width.bytes(),
+            PushFloat => 9,
+            MovValue | AddValue => 2 + width.bytes(),
+            MovRegister => 3,
+            Inc | Dec => 2,
+            Enter | LocalGet | LocalSet | Arg => 2,
+            Syscall | CallHost => 2,
+            In | Out => 2,
             PushRegister | PopRegister => 2,
             _ => 1,
         }
@@ -47,6 +82,33 @@ impl From<&Op> for OpCode {
             Op::PushValue(_) => PushValue,
             Op::PushRegister(_) => PushRegister,
             Op::PopRegister(_) => PopRegister,
+            Op::Shl => Shl,
+            Op::PushFloat(_) => PushFloat,
+            Op::FAdd => FAdd,
+            Op::FSub => FSub,
+            Op::FMul => FMul,
+            Op::FDiv => FDiv,
+            Op::FSqrt => FSqrt,
+            Op::IToF => IToF,
+            Op::FToI => FToI,
+            Op::FOutput => FOutput,
+            Op::MovRegister(..) => MovRegister,
+            Op::MovValue(..) => MovValue,
+            Op::Inc(_) => Inc,
+            Op::Dec(_) => Dec,
+            Op::AddValue(..) => AddValue,
+            Op::Enter(_) => Enter,
+            Op::Leave => Leave,
+            Op::LocalGet(_) => LocalGet,
+            Op::LocalSet(_) => LocalSet,
+            Op::Arg(_) => Arg,
+            Op::Syscall(_) => Syscall,
+            Op::CallHost(_) => CallHost,
+            Op::In(_) => In,
+            Op::Out(_) => Out,
+            Op::Assert => Assert,
+            Op::AssertEq => AssertEq,
+            Op::Breakpoint => Breakpoint,
         }
     }
 }
@@ -77,6 +139,33 @@ impl TryFrom<u8> for OpCode {
             x if x == PushValue.into() => Ok(PushValue),
             x if x == PushRegister.into() => Ok(PushRegister),
             x if x == PopRegister.into() => Ok(PopRegister),
+            x if x == Shl.into() => Ok(Shl),
+            x if x == PushFloat.into() => Ok(PushFloat),
+            x if x == FAdd.into() => Ok(FAdd),
+            x if x == FSub.into() => Ok(FSub),
+            x if x == FMul.into() => Ok(FMul),
+            x if x == FDiv.into() => Ok(FDiv),
+            x if x == FSqrt.into() => Ok(FSqrt),
+            x if x == IToF.into() => Ok(IToF),
+            x if x == FToI.into() => Ok(FToI),
+            x if x == FOutput.into() => Ok(FOutput),
+            x if x == MovRegister.into() => Ok(MovRegister),
+            x if x == MovValue.into() => Ok(MovValue),
+            x if x == Inc.into() => Ok(Inc),
+            x if x == Dec.into() => Ok(Dec),
+            x if x == AddValue.into() => Ok(AddValue),
+            x if x == Enter.into() => Ok(Enter),
+            x if x == Leave.into() => Ok(Leave),
+            x if x == LocalGet.into() => Ok(LocalGet),
+            x if x == LocalSet.into() => Ok(LocalSet),
+            x if x == Arg.into() => Ok(Arg),
+            x if x == Syscall.into() => Ok(Syscall),
+            x if x == CallHost.into() => Ok(CallHost),
+            x if x == In.into() => Ok(In),
+            x if x == Out.into() => Ok(Out),
+            x if x == Assert.into() => Ok(Assert),
+            x if x == AssertEq.into() => Ok(AssertEq),
+            x if x == Breakpoint.into() => Ok(Breakpoint),
             x => Err(WrongOpCode { op_code: x }),
         }
     }
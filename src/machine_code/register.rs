@@ -6,15 +6,11 @@ impl Decompile for Register {
     type Error = RegisterDecompileError;
 
     fn decompile(bytes: &[u8]) -> Result<super::DecompileResult<Self>, Self::Error> {
-        use Register::*;
-
-        let register = match &bytes[..] {
+        let register = match bytes {
             [] => Err(EndOfInput { name: "Register" })?,
-            [0, ..] => A,
-            [1, ..] => B,
-            [2, ..] => C,
-            [3, ..] => D,
-            [x, ..] => Err(RegisterDecompileError::WrongRegister { register: *x })?,
+            [x, ..] => {
+                Register::new(*x).ok_or(RegisterDecompileError::WrongRegister { register: *x })?
+            }
         };
 
         Ok(DecompileResult {
@@ -28,7 +24,7 @@ impl Compile for Register {
     type Error = OutputError;
 
     fn compile(&self, output: &mut impl std::io::Write) -> Result<(), Self::Error> {
-        output.write(&[*self as u8])?;
+        output.write_all(&[self.index()])?;
         Ok(())
     }
 }
@@ -0,0 +1,33 @@
+use std::collections::BTreeMap;
+
+use crate::models::Location;
+
+/// Locations of the operations of a compiled program in the assembly source.
+///
+/// Operations are identified by their offsets in the program, including the header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    locations: BTreeMap<usize, Location>,
+}
+
+impl SourceMap {
+    pub fn insert(&mut self, offset: usize, location: Location) {
+        self.locations.insert(offset, location);
+    }
+
+    /// Returns location of the operation at the offset.
+    pub fn location(&self, offset: usize) -> Option<&Location> {
+        self.locations.get(&offset)
+    }
+
+    /// Returns offsets and locations of the operations, in the order of offsets.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Location)> {
+        self.locations
+            .iter()
+            .map(|(&offset, location)| (offset, location))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+}
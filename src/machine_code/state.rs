@@ -0,0 +1,224 @@
+//! Snapshot of the machine, which executes a program.
+//!
+//! The state is encoded as:
+//!
+//! ```text
+//! "SMST"
+//! u8 version, currently 1
+//! u8 word width in bits, 32 or 64
+//! u32 program counter, offset of the next operation in the program
+//! u64 number of executed operations
+//! u8 1 if the program halted, 0 otherwise
+//! u8 number of registers, for each: word
+//! u32 length of the stack, for each value: word
+//! u32 length of the float stack, for each value: f64
+//! u32 number of frames, for each: u32 position of the first local on the stack, u8 locals
+//! ```
+//!
+//! Words have the width of the machine, all numbers are big-endian.
+
+use std::convert::TryInto;
+
+use crate::models::{Float, Register, Value, Width};
+
+use super::{
+    object::{write_u32, Reader},
+    *,
+};
+
+const MAGIC: &[u8; 4] = b"SMST";
+
+const VERSION: u8 = 1;
+
+/// Frame started with `ENTER`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrameState {
+    /// Position of the first local on the stack, arguments are below it.
+    pub base: usize,
+    pub locals: u8,
+}
+
+/// State of the machine, which can be restored to continue execution of the program.
+///
+/// Devices and host functions are not a part of the state.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MachineState {
+    pub width: Width,
+
+    /// Offset of the next operation in the program, including the header.
+    pub pc: usize,
+
+    /// Number of executed operations.
+    pub executed: u64,
+
+    /// `true` if the program executed `HALT`.
+    pub halted: bool,
+
+    /// Values of the registers available to the program.
+    pub registers: Vec<i64>,
+
+    pub stack: Vec<i64>,
+    pub floats: Vec<f64>,
+    pub frames: Vec<FrameState>,
+}
+
+impl Compile for MachineState {
+    type Error = OutputError;
+
+    fn compile(&self, output: &mut impl io::Write) -> Result<(), Self::Error> {
+        output.write_all(MAGIC)?;
+        output.write_all(&[VERSION, self.width.bits() as u8])?;
+        write_u32(output, self.pc)?;
+        output.write_all(&self.executed.to_be_bytes())?;
+        output.write_all(&[self.halted.into()])?;
+
+        let count: u8 = self
+            .registers
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many registers"))?;
+        output.write_all(&[count])?;
+        for &value in self.registers.iter() {
+            Value(value).compile_with(self.width, output)?;
+        }
+        write_u32(output, self.stack.len())?;
+        for &value in self.stack.iter() {
+            Value(value).compile_with(self.width, output)?;
+        }
+        write_u32(output, self.floats.len())?;
+        for &value in self.floats.iter() {
+            Float(value).compile(output)?;
+        }
+        write_u32(output, self.frames.len())?;
+        for frame in self.frames.iter() {
+            write_u32(output, frame.base)?;
+            output.write_all(&[frame.locals])?;
+        }
+        Ok(())
+    }
+}
+
+impl Decompile for MachineState {
+    type Error = StateDecompileError;
+
+    fn decompile(bytes: &[u8]) -> Result<DecompileResult<Self>, Self::Error> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(StateDecompileError::WrongMagic);
+        }
+        let mut reader = Reader {
+            bytes,
+            position: MAGIC.len(),
+            width: Width::W32,
+        };
+        match reader.take(1, "Version")? {
+            [VERSION] => {}
+            &[version] => return Err(StateDecompileError::WrongVersion(version)),
+            _ => unreachable!(),
+        }
+        reader.width = match reader.take(1, "Width")? {
+            [32] => Width::W32,
+            [64] => Width::W64,
+            &[bits] => return Err(StateDecompileError::WrongWidth(bits)),
+            _ => unreachable!(),
+        };
+        let pc = reader.u32("Program counter")?;
+        let executed = reader.take(8, "Number of executed operations")?;
+        let executed = u64::from_be_bytes(executed.try_into().unwrap());
+        let halted = match reader.take(1, "Halted flag")? {
+            [0] => false,
+            [1] => true,
+            &[flag] => return Err(StateDecompileError::WrongHalted(flag)),
+            _ => unreachable!(),
+        };
+
+        let count = match reader.take(1, "Number of registers")? {
+            [count] if 0 < *count && *count <= Register::MAX_COUNT => *count,
+            &[count] => return Err(StateDecompileError::WrongRegisterCount(count)),
+            _ => unreachable!(),
+        };
+        let mut registers = Vec::new();
+        for _ in 0..count {
+            registers.push(reader.value()?.0);
+        }
+
+        let mut stack = Vec::new();
+        for _ in 0..reader.u32("Stack length")? {
+            stack.push(reader.value()?.0);
+        }
+
+        let mut floats = Vec::new();
+        for _ in 0..reader.u32("Float stack length")? {
+            let bytes = reader.take(8, "Float")?;
+            floats.push(Float::decompile(bytes)?.value.0);
+        }
+
+        let mut frames = Vec::new();
+        for _ in 0..reader.u32("Number of frames")? {
+            let base = reader.u32("Frame base")?;
+            let locals = reader.take(1, "Number of locals")?[0];
+            frames.push(FrameState { base, locals });
+        }
+
+        Ok(DecompileResult {
+            value: MachineState {
+                width: reader.width,
+                pc,
+                executed,
+                halted,
+                registers,
+                stack,
+                floats,
+                frames,
+            },
+            bytes_read: reader.position,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compile_decompile() {
+        let state = MachineState {
+            width: Width::W64,
+            pc: 12,
+            executed: 7,
+            halted: false,
+            registers: vec![1, -2, i64::MAX, 0],
+            stack: vec![5, 0, 0, -1],
+            floats: vec![2.5],
+            frames: vec![FrameState { base: 1, locals: 2 }],
+        };
+        let mut bytes = Vec::new();
+        state.compile(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"SMST\x01\x40\x00\x00\x00\x0c"));
+        let decompiled = MachineState::decompile(&bytes).unwrap();
+        assert_eq!(decompiled.bytes_read, bytes.len());
+        assert_eq!(decompiled.value, state);
+
+        assert!(matches!(
+            MachineState::decompile(&bytes[..bytes.len() - 1]),
+            Err(StateDecompileError::EndOfInput(_))
+        ));
+        assert!(matches!(
+            MachineState::decompile(b"SMST\x02"),
+            Err(StateDecompileError::WrongVersion(2))
+        ));
+        assert!(matches!(
+            MachineState::decompile(b"SMX\x01"),
+            Err(StateDecompileError::WrongMagic)
+        ));
+    }
+
+    #[test]
+    fn values_fit_into_the_word() {
+        let state = MachineState {
+            registers: vec![0; 4],
+            stack: vec![i64::from(i32::MAX) + 1],
+            ..MachineState::default()
+        };
+        assert!(state.compile(&mut Vec::new()).is_err());
+    }
+}
@@ -1,35 +1,79 @@
-use std::convert::TryInto;
+use std::{convert::TryInto, io};
 
-use crate::models::Value;
+use crate::models::{Float, Value, Width};
 
 use super::{Compile, Decompile, DecompileResult, EndOfInput, OutputError};
 
+impl Value {
+    /// Encodes the value as a big-endian word of given width.
+    ///
+    /// Fails if the value doesn't fit into the word.
+    pub fn compile_with(
+        &self,
+        width: Width,
+        output: &mut impl io::Write,
+    ) -> Result<(), OutputError> {
+        if !width.contains(self.0) {
+            let message = format!("value {} doesn't fit into {}-bit word", self.0, width);
+            Err(io::Error::new(io::ErrorKind::InvalidInput, message))?
+        }
+        let bytes = self.0.to_be_bytes();
+        output.write_all(&bytes[bytes.len() - width.bytes()..])?;
+        Ok(())
+    }
+
+    /// Decodes a big-endian word of given width.
+    pub fn decompile_with(bytes: &[u8], width: Width) -> Result<DecompileResult<Self>, EndOfInput> {
+        let bytes = bytes
+            .get(..width.bytes())
+            .ok_or(EndOfInput { name: "Value" })?;
+        let value = match width {
+            Width::W32 => i32::from_be_bytes(bytes.try_into().unwrap()).into(),
+            Width::W64 => i64::from_be_bytes(bytes.try_into().unwrap()),
+        };
+        Ok(DecompileResult {
+            value: Value(value),
+            bytes_read: width.bytes(),
+        })
+    }
+}
+
+/// Values are encoded as 32-bit words, see `Value::decompile_with` for other widths.
 impl Decompile for Value {
     type Error = EndOfInput;
 
     fn decompile(bytes: &[u8]) -> Result<DecompileResult<Self>, Self::Error> {
-        if bytes.len() < 4 {
-            Err(EndOfInput { name: "Value" })?
-        }
-
-        if let Ok(bytes) = bytes[..4].try_into() {
-            let value = i32::from_be_bytes(bytes);
-            let result = DecompileResult {
-                value: Value(value),
-                bytes_read: 4,
-            };
-            Ok(result)
-        } else {
-            unreachable!()
-        }
+        Value::decompile_with(bytes, Width::W32)
     }
 }
 
+/// Values are encoded as 32-bit words, see `Value::compile_with` for other widths.
 impl Compile for Value {
     type Error = OutputError;
 
-    fn compile(&self, output: &mut impl std::io::Write) -> Result<(), Self::Error> {
-        output.write(self.0.to_be_bytes().as_ref())?;
+    fn compile(&self, output: &mut impl io::Write) -> Result<(), Self::Error> {
+        self.compile_with(Width::W32, output)
+    }
+}
+
+/// Floats are encoded as big-endian IEEE 754 double precision numbers.
+impl Decompile for Float {
+    type Error = EndOfInput;
+
+    fn decompile(bytes: &[u8]) -> Result<DecompileResult<Self>, Self::Error> {
+        let bytes = bytes.get(..8).ok_or(EndOfInput { name: "Float" })?;
+        Ok(DecompileResult {
+            value: Float(f64::from_be_bytes(bytes.try_into().unwrap())),
+            bytes_read: 8,
+        })
+    }
+}
+
+impl Compile for Float {
+    type Error = OutputError;
+
+    fn compile(&self, output: &mut impl io::Write) -> Result<(), Self::Error> {
+        output.write_all(&self.0.to_be_bytes())?;
         Ok(())
     }
 }
@@ -58,4 +102,28 @@ mod test {
         let bytes: &[u8] = &[0; 5];
         assert_eq!(4, Value::decompile(bytes).unwrap().bytes_read);
     }
+
+    #[test]
+    fn wide_values() {
+        let value = Value(-0x1_0000_0002);
+        let mut bytes = Vec::new();
+        value.compile_with(Width::W64, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xff, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xff, 0xfe]);
+        let decompiled = Value::decompile_with(&bytes, Width::W64).unwrap();
+        assert_eq!(decompiled.value, value);
+        assert_eq!(decompiled.bytes_read, 8);
+
+        assert!(value.compile(&mut Vec::new()).is_err());
+        assert!(Value::decompile_with(&bytes[..7], Width::W64).is_err());
+    }
+
+    #[test]
+    fn floats() {
+        let value = Float(-2.5);
+        let mut bytes = Vec::new();
+        value.compile(&mut bytes).unwrap();
+        assert_eq!(bytes, (-2.5f64).to_be_bytes());
+        assert_eq!(Float::decompile(&bytes).unwrap().value, value);
+        assert!(Float::decompile(&bytes[..7]).is_err());
+    }
 }
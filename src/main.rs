@@ -1,26 +1,58 @@
 use std::env;
 use std::error;
 use std::fs;
-use std::io::{self, prelude::*};
+use std::io::{self, prelude::*, IsTerminal};
 use std::path;
 use std::process;
 
 use thiserror::Error;
 
-use stack_machine::{executor::Machine, machine_code::{Compile, Decompile}, models::Assembly};
+use stack_machine::{
+    executor::{
+        read_replay, write_replay, IoDevice, LineCoverage, Machine, RecordingDevice, ReplayError,
+        ScriptedDevice, StdioDevice,
+    },
+    format::format_source,
+    golden,
+    lint::lint_source,
+    lsp,
+    machine_code::{link, Compile, Decompile, Object, SourceMap},
+    models::Assembly,
+    optimizer,
+    repl::Repl,
+};
 
 enum Config<'a> {
     Execute {
         input: Option<&'a path::Path>,
+        options: ExecuteOptions<'a>,
     },
     Compile {
         input: Option<&'a path::Path>,
         output: &'a path::Path,
+        object: bool,
+        optimize: bool,
+    },
+    Link {
+        inputs: Vec<&'a path::Path>,
+        output: &'a path::Path,
     },
     Decompile {
         input: &'a path::Path,
         output: Option<&'a path::Path>,
     },
+    Test {
+        dir: &'a path::Path,
+    },
+    Repl,
+    Format {
+        inputs: Vec<&'a path::Path>,
+        check: bool,
+    },
+    Lint {
+        inputs: Vec<&'a path::Path>,
+    },
+    Lsp,
     Help,
 }
 
@@ -35,18 +67,47 @@ impl<'a> Config<'a> {
     fn new(args: &'a [String]) -> Result<Self, UsageError> {
         let flag = args.get(1).ok_or(UsageError("Flag not specified".into()))?;
         match flag.as_str() {
-            "-c" | "--compile" => match &args[2..] {
-                [output] => Ok(Config::Compile {
-                    input: None,
-                    output: path::Path::new(output),
-                }),
-                [input, output] => Ok(Config::Compile {
-                    input: Some(path::Path::new(input)),
-                    output: path::Path::new(output),
-                }),
-                x => {
-                    let msg = format!("Expected 1 or 2 arguments after -c flag, got {}", x.len());
-                    Err(UsageError(msg))
+            "-c" | "--compile" => {
+                let mut args = &args[2..];
+                let (mut object, mut optimize) = (false, false);
+                while let [flag, rest @ ..] = args {
+                    match flag.as_str() {
+                        "--object" => object = true,
+                        "-O" => optimize = true,
+                        _ => break,
+                    }
+                    args = rest;
+                }
+                match args {
+                    [output] => Ok(Config::Compile {
+                        input: None,
+                        output: path::Path::new(output),
+                        object,
+                        optimize,
+                    }),
+                    [input, output] => Ok(Config::Compile {
+                        input: Some(path::Path::new(input)),
+                        output: path::Path::new(output),
+                        object,
+                        optimize,
+                    }),
+                    x => {
+                        let msg =
+                            format!("Expected 1 or 2 arguments after -c flag, got {}", x.len());
+                        Err(UsageError(msg))
+                    }
+                }
+            }
+            "--link" => match &args[2..] {
+                [inputs @ .., flag, output] if flag == "-o" && !inputs.is_empty() => {
+                    Ok(Config::Link {
+                        inputs: inputs.iter().map(path::Path::new).collect(),
+                        output: path::Path::new(output),
+                    })
+                }
+                _ => {
+                    let msg = "Expected object files and '-o path/to/output.s' after --link flag";
+                    Err(UsageError(msg.into()))
                 }
             },
             "-d" | "--decompile" => match &args[2..] {
@@ -63,13 +124,85 @@ impl<'a> Config<'a> {
                     Err(UsageError(msg))
                 }
             },
-            "-x" => match &args[2..] {
-                [] => Ok(Config::Execute { input: None }),
-                [input] => Ok(Config::Execute {
-                    input: Some(path::Path::new(input)),
+            "-x" => {
+                let mut args = &args[2..];
+                let mut options = ExecuteOptions::default();
+                loop {
+                    match args {
+                        [flag, rest @ ..] if flag == "--profile-pairs" => {
+                            options.profile_pairs = true;
+                            args = rest;
+                        }
+                        [flag, path, rest @ ..] if flag == "--profile" => {
+                            options.profile = Some(path::Path::new(path));
+                            args = rest;
+                        }
+                        [flag, path, rest @ ..] if flag == "--coverage" => {
+                            options.coverage = Some(path::Path::new(path));
+                            args = rest;
+                        }
+                        [flag, path, rest @ ..] if flag == "--record" => {
+                            options.record = Some(path::Path::new(path));
+                            args = rest;
+                        }
+                        [flag, path, rest @ ..] if flag == "--replay" => {
+                            options.replay = Some(path::Path::new(path));
+                            args = rest;
+                        }
+                        _ => break,
+                    }
+                }
+                if options.record.is_some() && options.replay.is_some() {
+                    let msg = "Expected either --record or --replay after -x flag, got both";
+                    return Err(UsageError(msg.into()));
+                }
+                match args {
+                    [] => Ok(Config::Execute {
+                        input: None,
+                        options,
+                    }),
+                    [input] => Ok(Config::Execute {
+                        input: Some(path::Path::new(input)),
+                        options,
+                    }),
+                    x => {
+                        let msg =
+                            format!("Expected 0 or 1 argument after -x flag, got {}", x.len());
+                        Err(UsageError(msg))
+                    }
+                }
+            }
+            "test" => match &args[2..] {
+                [dir] => Ok(Config::Test {
+                    dir: path::Path::new(dir),
                 }),
                 x => {
-                    let msg = format!("Expected 0 or 1 argument after -x flag, got {}", x.len());
+                    let msg = format!("Expected 1 argument after test, got {}", x.len());
+                    Err(UsageError(msg))
+                }
+            },
+            "repl" => match &args[2..] {
+                [] => Ok(Config::Repl),
+                x => {
+                    let msg = format!("Expected 0 arguments after repl, got {}", x.len());
+                    Err(UsageError(msg))
+                }
+            },
+            "fmt" => {
+                let (check, inputs) = match &args[2..] {
+                    [flag, inputs @ ..] if flag == "--check" => (true, inputs),
+                    inputs => (false, inputs),
+                };
+                let inputs = inputs.iter().map(path::Path::new).collect();
+                Ok(Config::Format { inputs, check })
+            }
+            "lint" => Ok(Config::Lint {
+                inputs: args[2..].iter().map(path::Path::new).collect(),
+            }),
+            "lsp" => match &args[2..] {
+                [] => Ok(Config::Lsp),
+                x => {
+                    let msg = format!("Expected 0 arguments after lsp, got {}", x.len());
                     Err(UsageError(msg))
                 }
             },
@@ -83,7 +216,7 @@ impl<'a> Config<'a> {
             }
             x => {
                 let msg = format!(
-                    "Expected one of '-x', '-d', '-h' or '-c' flags, got {} argument{}",
+                    "Expected one of '-x', '-d', '-h', '-c' or '--link' flags or 'test', 'repl', 'fmt', 'lint' or 'lsp', got {} argument{}",
                     x.len(),
                     if x.len() == 1 { "" } else { "s" },
                 );
@@ -113,56 +246,177 @@ type MyResult = Result<(), Box<dyn error::Error>>;
 fn try_main(config: &Config) -> MyResult {
     match config {
         Config::Help => help(),
-        Config::Execute { input } => execute(input)?,
-        Config::Compile { input, output } => compile(input, output)?,
+        Config::Execute { input, options } => execute(input, options)?,
+        Config::Compile {
+            input,
+            output,
+            object,
+            optimize,
+        } => compile(input, output, *object, *optimize)?,
+        Config::Link { inputs, output } => link_objects(inputs, output)?,
         Config::Decompile { input, output } => decompile(input, output)?,
+        Config::Test { dir } => test(dir)?,
+        Config::Repl => repl()?,
+        Config::Format { inputs, check } => format(inputs, *check)?,
+        Config::Lint { inputs } => lint(inputs)?,
+        Config::Lsp => language_server()?,
     };
     Ok(())
 }
 
-fn help() -> () {
+fn help() {
     println!("Usage:\n{}", USAGE);
     println!("Examples:\n{}", EXAMPLES);
 }
 
-fn execute(input: &Option<&path::Path>) -> MyResult {
-    let machine_code = if let Some(path) = input {
-        fs::read(path)?
-    } else {
-        let mut buf = Vec::new();
-        io::stdin().read_to_end(&mut buf)?;
-        let assembly: Assembly = String::from_utf8(buf)?.parse()?;
-        let mut machine_code = Vec::new();
-        assembly.compile(&mut machine_code)?;
-        machine_code
+/// Options of `smachine -x`.
+#[derive(Copy, Clone, Default)]
+struct ExecuteOptions<'a> {
+    profile_pairs: bool,
+
+    /// Profile in JSON, its text report is printed to stderr.
+    profile: Option<&'a path::Path>,
+
+    /// Line coverage in the lcov format, merged with the existing one.
+    coverage: Option<&'a path::Path>,
+
+    record: Option<&'a path::Path>,
+    replay: Option<&'a path::Path>,
+}
+
+fn execute(input: &Option<&path::Path>, options: &ExecuteOptions) -> MyResult {
+    // Assembly is compiled on the fly, its source map annotates the profile
+    // and gives lines for the coverage
+    let is_assembly = |path: &path::Path| path.extension().is_some_and(|ext| ext == "sasm");
+    let (machine_code, source_map) = match input {
+        Some(path) if !is_assembly(path) => (fs::read(path)?, None),
+        _ => {
+            let assembly = if let Some(path) = input {
+                Assembly::from_source(&fs::read_to_string(path)?, path)?
+            } else {
+                let mut buf = Vec::new();
+                io::stdin().read_to_end(&mut buf)?;
+                String::from_utf8(buf)?.parse()?
+            };
+            let mut machine_code = Vec::new();
+            let source_map = assembly.compile_with_source_map(&mut machine_code)?;
+            (machine_code, Some(source_map))
+        }
     };
 
-    let stdin = io::stdin();
-    let stdout = io::stdout();
-    let mut machine = Machine::new(stdin.lock(), stdout.lock());
-    machine.execute_program(&machine_code[..])?;
+    let source_map = source_map.as_ref();
+    if options.coverage.is_some() && source_map.is_none() {
+        Err("Coverage is only available for programs in assembly, use a .sasm file")?;
+    }
+    if let Some(path) = options.replay {
+        // Recorded input is fed to the program, which must write the recorded output
+        let mut device = ScriptedDevice::new(read_replay(&fs::read_to_string(path)?)?);
+        run(&mut device, &machine_code, options, source_map)?;
+        if let Some(&event) = device.remaining().front() {
+            Err(ReplayError::Unfinished(event))?;
+        }
+        eprintln!("Output matches the recording");
+    } else if let Some(path) = options.record {
+        let mut device = RecordingDevice::new(StdioDevice::new());
+        let result = run(&mut device, &machine_code, options, source_map);
+        // Failed runs are recorded as well, so that the failure can be reproduced
+        let mut replay = Vec::new();
+        write_replay(&mut replay, device.events())?;
+        fs::write(path, replay)?;
+        result?;
+    } else {
+        run(StdioDevice::new(), &machine_code, options, source_map)?;
+    }
     Ok(())
 }
 
-fn compile(input: &Option<&path::Path>, output: &&path::Path) -> MyResult {
-    let input = if let Some(ref path) = input {
-        fs::read_to_string(path)?
+/// Executes the program and reports its profiles, if they are enabled.
+fn run(
+    device: impl IoDevice,
+    machine_code: &[u8],
+    options: &ExecuteOptions,
+    source_map: Option<&SourceMap>,
+) -> MyResult {
+    let mut machine = Machine::new(device);
+    if options.profile_pairs {
+        machine.enable_pair_profile();
+    }
+    if options.profile.is_some() {
+        machine.enable_profile();
+    }
+    if options.coverage.is_some() {
+        machine.enable_coverage();
+    }
+    let result = machine.execute_program(machine_code);
+    if let Some(profile) = machine.pair_profile() {
+        eprint!("Executed pairs of operations:\n{}", profile);
+    }
+    if let (Some(path), Some(profile)) = (options.profile, machine.profile()) {
+        eprint!("{}", profile.report(source_map));
+        fs::write(path, profile.to_json(source_map))?;
+        eprintln!("Profile is written to {}", path.display());
+    }
+    if let (Some(path), Some(coverage), Some(source_map)) =
+        (options.coverage, machine.coverage(), source_map)
+    {
+        // Coverage of previous runs is kept, so that a test suite can be run program by program
+        let mut lines = coverage.lines(source_map);
+        if path.exists() {
+            lines.merge(&LineCoverage::from_lcov(&fs::read_to_string(path)?)?);
+        }
+        eprint!("Line coverage:\n{}", lines);
+        fs::write(path, lines.to_lcov())?;
+    }
+    result?;
+    Ok(())
+}
+
+fn compile(
+    input: &Option<&path::Path>,
+    output: &&path::Path,
+    object: bool,
+    optimize: bool,
+) -> MyResult {
+    let mut assembly = if let Some(path) = input {
+        Assembly::from_source(&fs::read_to_string(path)?, path)?
     } else {
         let mut buf = Vec::new();
         io::stdin().read_to_end(&mut buf)?;
-        String::from_utf8(buf)?
+        String::from_utf8(buf)?.parse()?
     };
 
-    let assembly: Assembly = input.parse()?;
-    let mut output = fs::File::create(output)?;
-    assembly.compile(&mut output)?;
+    if optimize {
+        let (optimized, rewrites) = optimizer::optimize(&assembly);
+        // Output file may be STDOUT of a pipe, so the report goes to STDERR
+        eprintln!("{} rewrites applied:", rewrites.len());
+        for rewrite in rewrites.iter() {
+            eprintln!("  {}", rewrite);
+        }
+        assembly = optimized;
+    }
+
+    // Compile into memory, so that no output file is left on errors
+    let mut machine_code = Vec::new();
+    if object {
+        Object::assemble(&assembly)?.compile(&mut machine_code)?;
+    } else {
+        assembly.compile(&mut machine_code)?;
+    }
+    fs::write(output, machine_code)?;
+    Ok(())
+}
+
+fn link_objects(inputs: &[&path::Path], output: &path::Path) -> MyResult {
+    let mut objects = Vec::new();
+    for path in inputs {
+        let object = Object::decompile(&fs::read(path)?)?.value;
+        objects.push((path.display().to_string(), object));
+    }
+    fs::write(output, link(&objects)?)?;
     Ok(())
 }
 
-fn decompile(
-    input: &&path::Path,
-    output: &Option<&path::Path>,
-) -> MyResult {
+fn decompile(input: &&path::Path, output: &Option<&path::Path>) -> MyResult {
     let machine_code = fs::read(input)?;
     let assembly = Assembly::decompile(machine_code.as_slice())?.value;
     if let Some(path) = output {
@@ -173,16 +427,234 @@ fn decompile(
     Ok(())
 }
 
-const USAGE: &'static str = "\
-smachine -c [path/to/input.sasm] path/to/output/s
+#[derive(Error, Debug)]
+#[error("{failed} of {total} tests failed")]
+struct TestsFailed {
+    failed: usize,
+    total: usize,
+}
+
+/// Runs golden tests from all `.sasm` files in the directory and its subdirectories.
+fn test(dir: &path::Path) -> MyResult {
+    let mut paths = Vec::new();
+    find_assembly(dir, &mut paths)?;
+    paths.sort();
+
+    let mut failed = 0;
+    for path in paths.iter() {
+        let source = fs::read_to_string(path)?;
+        match golden::run_test(&source, Some(path)) {
+            Ok(()) => println!("PASS {}", path.display()),
+            Err(failure) => {
+                failed += 1;
+                println!("FAIL {}", path.display());
+                for line in failure.to_string().lines() {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+    println!("{} passed, {} failed", paths.len() - failed, failed);
+    if failed > 0 {
+        let total = paths.len();
+        return Err(TestsFailed { failed, total }.into());
+    }
+    Ok(())
+}
+
+fn find_assembly(dir: &path::Path, paths: &mut Vec<path::PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_assembly(&path, paths)?;
+        } else if path.extension().is_some_and(|ext| ext == "sasm") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Executes operations typed by the user, until `:quit` or the end of the input.
+fn repl() -> MyResult {
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        println!("Type operations to execute them, :help for commands, :quit to exit");
+    }
+    let mut repl = Repl::new(Machine::new(StdioDevice::new()));
+    loop {
+        if interactive {
+            print!("> ");
+            io::stdout().flush()?;
+        }
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 || line.trim() == ":quit" {
+            break;
+        }
+        match repl.eval(&line) {
+            Ok(reply) if reply.is_empty() => {}
+            Ok(reply) => println!("{}", reply),
+            Err(error) => eprintln!("Error: {}", error),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+#[error("{0} file(s) are not formatted")]
+struct NotFormatted(usize);
+
+#[derive(Error, Debug)]
+#[error("{0} file(s) can't be formatted")]
+struct NotFormattable(usize);
+
+/// Rewrites the files in the canonical layout, or checks that they already are in it.
+///
+/// Without files formats the standard input to the standard output. Files, which can't
+/// be read or are not correct assembly, are reported and left untouched, the rest of
+/// the files are formatted all the same.
+fn format(inputs: &[&path::Path], check: bool) -> MyResult {
+    if inputs.is_empty() {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        source.parse::<Assembly>()?;
+        let formatted = format_source(&source);
+        if check {
+            if formatted != source {
+                return Err(NotFormatted(1).into());
+            }
+        } else {
+            print!("{}", formatted);
+        }
+        return Ok(());
+    }
+
+    let mut unformatted = 0;
+    let mut failed = 0;
+    for path in inputs {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Can't read {}: {}", path.display(), error);
+                failed += 1;
+                continue;
+            }
+        };
+        if let Err(error) = Assembly::from_source(&source, path) {
+            eprint!("{}", error);
+            failed += 1;
+            continue;
+        }
+        let formatted = format_source(&source);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path.display());
+            unformatted += 1;
+        } else {
+            fs::write(path, formatted)?;
+        }
+    }
+    if failed > 0 {
+        return Err(NotFormattable(failed).into());
+    }
+    if unformatted > 0 {
+        return Err(NotFormatted(unformatted).into());
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+#[error("{0} warning(s)")]
+struct LintWarnings(usize);
+
+/// Prints warnings about the files, or about the standard input without files.
+fn lint(inputs: &[&path::Path]) -> MyResult {
+    let mut warnings = Vec::new();
+    if inputs.is_empty() {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        warnings = lint_source(&source, None)?;
+    }
+    for path in inputs {
+        let source = fs::read_to_string(path)?;
+        warnings.extend(lint_source(&source, Some(path))?);
+    }
+    for warning in warnings.iter() {
+        println!("{}", warning);
+    }
+    if !warnings.is_empty() {
+        return Err(LintWarnings(warnings.len()).into());
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+#[error("Client exited without a shutdown request")]
+struct ExitWithoutShutdown;
+
+/// Serves the Language Server Protocol on the standard input and output.
+fn language_server() -> MyResult {
+    if !lsp::run(io::stdin().lock(), io::stdout().lock())? {
+        return Err(ExitWithoutShutdown.into());
+    }
+    Ok(())
+}
+
+const USAGE: &str = "\
+smachine -c [--object] [-O] [path/to/input.sasm] path/to/output.s
+smachine --link path/to/a.o [path/to/b.o ...] -o path/to/output.s
 smachine -d [path/to/input.s] path/to/output.sasm
-smachine -x [path/to/input.s]
+smachine -x [--profile-pairs] [--profile path] [--coverage path]
+           [--record path | --replay path] [path/to/input.s]
+smachine test path/to/tests/
+smachine repl
+smachine fmt [--check] [path/to/a.sasm ...]
+smachine lint [path/to/a.sasm ...]
+smachine lsp
 ";
 
-const EXAMPLES: &'static str = "\
+const EXAMPLES: &str = "\
 Execute compiled binary 'a.s':
 smachine -x a.s
 
+Execute 'a.s' and print the most frequently executed pairs of operations:
+smachine -x --profile-pairs a.s
+
+Execute 'a.sasm', print the operations executed most often with their source lines
+and write the same profile in JSON to 'profile.json':
+smachine -x --profile profile.json a.sasm
+
+Execute 'a.sasm' and 'b.sasm', print the share of their executed lines and write
+line coverage of both runs to 'coverage.info' in the lcov format:
+smachine -x --coverage coverage.info a.sasm
+smachine -x --coverage coverage.info b.sasm
+
+Execute 'a.s', saving its input and output to 'a.replay', then check that it
+still writes the same output for the same input:
+smachine -x --record a.replay a.s
+smachine -x --replay a.replay a.s
+
+Run every '.sasm' file in 'tests/' with the input from its '; input:' comments
+and compare the output with its '; expect-output:' and '; expect-error:' comments:
+smachine test tests/
+
+Execute operations as they are typed, showing the stack and registers after each one,
+':help' lists commands like ':load a.sasm' and ':save session.sasm':
+smachine repl
+
+Rewrite 'a.sasm' and 'b.sasm' with uppercase mnemonics and aligned operands and comments:
+smachine fmt a.sasm b.sasm
+Use 'smachine fmt --check a.sasm b.sasm' in CI, it fails if any of them would change
+
+Warn about mistakes in 'a.sasm', like stack underflow or missing HALT,
+'; lint: allow L003' comment suppresses the warning with this code on its line:
+smachine lint a.sasm
+
+Serve diagnostics, hover, completion, go to definition and formatting to an editor
+over the Language Server Protocol on the standard input and output:
+smachine lsp
+
 Compile 'a.sasm' and execute:
 smachine -c a.sasm a.s && smachine -x a.s
 Can do 'smachine -x < a.sasm' in Bash (or other shells)
@@ -191,6 +663,13 @@ Compile a.sasm and write resulting binary to 'a.s':
 smachine -c a.sasm a.s
 Also try 'cat a.sasm | smachine -c a.s' in Bash (or other shells)
 
+Optimize a.sasm, print applied rewrites and write resulting binary to 'a.s':
+smachine -c -O a.sasm a.s
+
+Compile a.sasm and b.sasm separately, then link them into 'prog.s':
+smachine -c --object a.sasm a.o && smachine -c --object b.sasm b.o
+smachine --link a.o b.o -o prog.s
+
 Decompile b.s and write resulting assembly to b.sasm
 smachine -d b.s b.sasm
 Can write to STDOUT instead: `smachine -d b.s | cat > b.sasm'
@@ -1,39 +1,132 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, fs, io, path::Path, str::FromStr};
 
-use super::*;
+use super::{macros::expand_macros, source::read_source, *};
 
-pub struct Assembly(Vec<Statement>);
+#[derive(Debug)]
+pub struct Assembly {
+    statements: Vec<Statement>,
+
+    /// Locations of the statements in the source, empty if assembly was not parsed.
+    locations: Vec<Location>,
+}
 
 impl Assembly {
     pub fn statements(&self) -> &Vec<Statement> {
-        &self.0
+        &self.statements
     }
 
     pub fn new(statements: Vec<Statement>) -> Assembly {
-        Assembly(statements)
+        Assembly {
+            statements,
+            locations: Vec::new(),
+        }
+    }
+
+    /// Returns location of the statement with given index in the source, if it's known.
+    pub fn location(&self, index: usize) -> Option<&Location> {
+        self.locations.get(index)
+    }
+
+    /// Returns the width of the machine word, declared with `.width`.
+    pub fn width(&self) -> Width {
+        self.statements
+            .iter()
+            .find_map(|statement| match statement {
+                Statement::Width(width) => Some(*width),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of registers, declared with `.registers`.
+    pub fn registers(&self) -> u8 {
+        self.statements
+            .iter()
+            .find_map(|statement| match statement {
+                Statement::Registers(count) => Some(*count),
+                _ => None,
+            })
+            .unwrap_or(Register::DEFAULT_COUNT)
     }
 }
 
-impl FromStr for Assembly {
-    type Err = AssemblyParseError;
+impl Assembly {
+    /// Parses assembly, which was read from the file at `path`.
+    ///
+    /// Included files are resolved relative to the including file.
+    pub fn from_source(source: &str, path: &Path) -> Result<Self, AssemblyParseError> {
+        Assembly::parse_with(source, Some(path), |path| fs::read_to_string(path))
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let statements = s
-            .lines()
-            .enumerate()
-            .filter(|&(_, s)| !s.is_empty())
-            .map(|(line, s)| s.parse().map_err(|error| LineWithError { line, error }));
+    /// Parses assembly, reading included files with `read`.
+    ///
+    /// Includes, then macros are expanded and the result is parsed line by line.
+    /// Macros and constants must be defined before they are used.
+    pub fn parse_with(
+        source: &str,
+        path: Option<&Path>,
+        mut read: impl FnMut(&Path) -> io::Result<String>,
+    ) -> Result<Self, AssemblyParseError> {
+        let lines = expand_macros(read_source(source, path, &mut read));
+
+        let mut symbols = Symbols::new();
+        let mut first = true;
+        let mut registers = None;
+        let mut has_code = false;
+        let statements = lines.into_iter().map(|line| {
+            let line = line?;
+            let mut statement = Statement::parse(&line.text, &symbols);
+            match &statement {
+                Ok(Statement::Constant { name, value }) => symbols.insert(name.clone(), *value),
+                Ok(Statement::Extern(name)) => symbols.insert_extern(name.clone()),
+                Ok(Statement::Width(_)) if !first => {
+                    statement = Err(StatementParseError::MisplacedWidth)
+                }
+                Ok(Statement::Width(width)) => symbols.set_width(*width),
+                Ok(Statement::Registers(_)) if has_code || registers.is_some() => {
+                    statement = Err(StatementParseError::MisplacedRegisters)
+                }
+                Ok(Statement::Registers(count)) => registers = Some(*count),
+                Ok(Statement::Op(op)) => {
+                    has_code = true;
+                    let count = registers.unwrap_or(Register::DEFAULT_COUNT);
+                    if let Some(&register) = op.registers().iter().find(|r| r.index() >= count) {
+                        statement = Err(StatementParseError::MissingRegister { register, count });
+                    }
+                }
+                Ok(Statement::PushExtern { .. }) | Ok(Statement::CallHost(_)) => has_code = true,
+                _ => (),
+            }
+            first = false;
+            match statement {
+                Ok(statement) => Ok((statement, line.location())),
+                Err(error) => Err(line.error(error)),
+            }
+        });
 
         let (assembly, errors) = partition_results(statements);
 
         if errors.is_empty() {
-            Ok(Assembly(assembly))
+            let (statements, locations) = assembly.into_iter().unzip();
+            Ok(Assembly {
+                statements,
+                locations,
+            })
         } else {
             Err(AssemblyParseError { errors })
         }
     }
 }
 
+impl FromStr for Assembly {
+    type Err = AssemblyParseError;
+
+    /// Parses assembly, included files are resolved relative to the current directory.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Assembly::parse_with(s, None, |path| fs::read_to_string(path))
+    }
+}
+
 fn partition_results<T, U>(results: impl Iterator<Item = Result<T, U>>) -> (Vec<T>, Vec<U>) {
     let mut oks = Vec::new();
     let mut errors = Vec::new();
@@ -52,9 +145,257 @@ fn partition_results<T, U>(results: impl Iterator<Item = Result<T, U>>) -> (Vec<
 
 impl Display for Assembly {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for line in &self.0 {
+        for line in &self.statements {
             writeln!(f, "{}", line)?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn constants() {
+        let source = "
+            .equ SIZE 4
+            .CONST total SIZE * 4 + 1
+            PUSH total
+            PUSH 'a' - SIZE
+        ";
+        let assembly: Assembly = source.parse().unwrap();
+        assert_eq!(
+            assembly.statements()[2..],
+            [
+                Statement::Op(Op::PushValue(Value(17))),
+                Statement::Op(Op::PushValue(Value(93))),
+            ]
+        );
+        assert_eq!(assembly.to_string().lines().next(), Some(".equ SIZE 4"));
+        assert_eq!(assembly.location(3).map(|l| l.line), Some(4));
+    }
+
+    #[test]
+    fn macros() {
+        let source = "
+.macro swap
+    POP A
+    POP B
+    PUSH A
+    PUSH B
+.endm
+.macro push_sum x, y
+    .equ sum\\@ \\x + \\y
+    PUSH sum\\@
+.endm
+push_sum 1, 2
+push_sum 'a', -1
+swap
+";
+        let assembly: Assembly = source.parse().unwrap();
+        let expected = ".equ sum0 3\nPUSH 3\n.equ sum1 96\nPUSH 96\nPOP A\nPOP B\nPUSH A\nPUSH B\n";
+        assert_eq!(assembly.to_string(), expected);
+    }
+
+    #[test]
+    fn macro_errors() {
+        let source = "
+.macro push_twice x
+    PUSH \\x
+    PUSH \\x
+.endm
+.macro outer
+    push_twice UNKNOWN
+.endm
+outer
+";
+        let error = source.parse::<Assembly>().unwrap_err();
+        assert_eq!(error.errors.len(), 2);
+        assert_eq!(error.errors[0].line, 2);
+        assert_eq!(
+            error.errors[0].expansion,
+            vec![
+                MacroCall {
+                    name: "outer".into(),
+                    file: None,
+                    line: 8,
+                },
+                MacroCall {
+                    name: "push_twice".into(),
+                    file: None,
+                    line: 6,
+                },
+            ]
+        );
+        assert_eq!(
+            error.to_string().lines().take(5).collect::<Vec<_>>(),
+            vec![
+                "2 errors occured when parsing assembly:",
+                "Line 3: Errors occured when parsing operation PUSH:",
+                "  1st argument: Undefined constant: UNKNOWN",
+                "  in expansion of macro push_twice at line 7",
+                "  in expansion of macro outer at line 9",
+            ]
+        );
+    }
+
+    #[test]
+    fn constant_errors() {
+        let source = "
+.equ SIZE 4
+.equ SIZE 5
+.equ B 1
+.equ TOO_BIG SIZE * 0x7FFF_FFFF
+.equ EMPTY
+PUSH UNKNOWN
+.org 10
+";
+        let errors: Vec<_> = source
+            .parse::<Assembly>()
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|LineWithError { line, error, .. }| (line, error))
+            .collect();
+
+        assert_eq!(
+            errors,
+            vec![
+                (2, StatementParseError::DuplicateConstant("SIZE".into())),
+                (3, StatementParseError::WrongConstantName("B".into())),
+                (
+                    4,
+                    StatementParseError::WrongConstantValue {
+                        name: "TOO_BIG".into(),
+                        error: ArgumentParseError::Overflow("(SIZE * 2147483647)".into()),
+                    }
+                ),
+                (
+                    5,
+                    StatementParseError::WrongConstant {
+                        directive: ".equ",
+                        num_args: 1,
+                    }
+                ),
+                (
+                    6,
+                    StatementParseError::OpError(OpParseError::WrongArguments {
+                        op: "PUSH",
+                        errors: vec![(0, ArgumentParseError::UndefinedSymbol("UNKNOWN".into()))],
+                    })
+                ),
+                (7, StatementParseError::UnknownDirective(".org".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn width() {
+        let source = "
+            .width 64
+            .equ BIG 0x7FFF_FFFF * 4
+            PUSH BIG + 1
+        ";
+        let assembly: Assembly = source.parse().unwrap();
+        assert_eq!(assembly.width(), Width::W64);
+        assert_eq!(
+            assembly.statements()[2],
+            Statement::Op(Op::PushValue(Value(0x1_FFFF_FFFD)))
+        );
+        assert_eq!("PUSH 1".parse::<Assembly>().unwrap().width(), Width::W32);
+
+        let errors: Vec<_> = "PUSH 0x1_0000_0000\n.width 64\n.width 16"
+            .parse::<Assembly>()
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|error| error.error)
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                StatementParseError::OpError(OpParseError::WrongArguments {
+                    op: "PUSH",
+                    errors: vec![(
+                        0,
+                        ArgumentParseError::ValueOutOfRange("0x1_0000_0000".into())
+                    )],
+                }),
+                StatementParseError::MisplacedWidth,
+                StatementParseError::WrongWidth("16".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn registers() {
+        let assembly: Assembly = ".width 64\n.registers 16\nMOV R15, r0".parse().unwrap();
+        assert_eq!(assembly.registers(), 16);
+        assert_eq!(
+            assembly.to_string(),
+            ".width 64\n.registers 16\nMOV R15, A\n"
+        );
+        assert_eq!("PUSH D".parse::<Assembly>().unwrap().registers(), 4);
+
+        let errors: Vec<_> = ".registers 2\nPUSH A\nMOV A, C\n.registers 4\n.registers 17\nPOP R16"
+            .parse::<Assembly>()
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|error| (error.line, error.error))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    2,
+                    StatementParseError::MissingRegister {
+                        register: Register::C,
+                        count: 2,
+                    }
+                ),
+                (3, StatementParseError::MisplacedRegisters),
+                (4, StatementParseError::WrongRegisterCount("17".into())),
+                (
+                    5,
+                    StatementParseError::OpError(OpParseError::WrongArguments {
+                        op: "POP",
+                        errors: vec![(0, ArgumentParseError::WrongRegister("R16".into()))],
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn includes() {
+        let read = |path: &Path| match path.to_str() {
+            Some("lib/macros.sasm") => {
+                Ok(".macro twice x\n    PUSH \\x\n    PUSH \\x\n.endm".into())
+            }
+            Some("lib/values.sasm") => Ok(".equ TWO 2\nPUSH UNKNOWN".into()),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+        };
+        let source = ".include \"macros.sasm\"\n.include \"values.sasm\"\ntwice TWO\nADD";
+
+        let error =
+            Assembly::parse_with(source, Some(Path::new("lib/main.sasm")), read).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1 errors occured when parsing assembly:\n\
+             lib/values.sasm, line 2: Errors occured when parsing operation PUSH:\n  \
+             1st argument: Undefined constant: UNKNOWN\n"
+        );
+
+        let source = source.replace("values", "macros");
+        let error =
+            Assembly::parse_with(&source, Some(Path::new("lib/main.sasm")), read).unwrap_err();
+        assert_eq!(
+            error.errors[0].error,
+            MacroError::Duplicate("twice".into()).into()
+        );
+        assert_eq!(error.errors[0].file, Some("lib/macros.sasm".into()));
+        assert_eq!(error.errors[0].line, 0);
+    }
+}
@@ -1,11 +1,16 @@
-use std::{self, error, fmt};
+use std::{self, error, fmt, path::PathBuf};
 use thiserror::Error;
 
+use super::Register;
+
 /// Statement parse error and line number, where this error occured.
 ///
 /// Part of the `AssemblyParseError` struct.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct LineWithError {
+    /// File where error occured, `None` if assembly was not read from a file.
+    pub file: Option<PathBuf>,
+
     /// Line where error occured.
     ///
     /// Starts from 0.
@@ -13,6 +18,26 @@ pub struct LineWithError {
 
     /// Inner error
     pub error: StatementParseError,
+
+    /// Macro invocations, which produced the line, outermost first.
+    ///
+    /// Empty if the error is not inside of a macro expansion.
+    pub expansion: Vec<MacroCall>,
+}
+
+/// Location of a macro invocation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacroCall {
+    /// Name of the invoked macro.
+    pub name: String,
+
+    /// File of the invocation, `None` if assembly was not read from a file.
+    pub file: Option<PathBuf>,
+
+    /// Line of the invocation.
+    ///
+    /// Starts from 0.
+    pub line: usize,
 }
 
 /// Collection of assembly parse errors.
@@ -30,7 +55,18 @@ impl fmt::Display for AssemblyParseError {
             self.errors.len()
         )?;
         for error in self.errors.iter() {
-            writeln!(f, "Line {}: {}", error.line + 1, error.error)?;
+            // Operation errors span several lines and end with a line break
+            let message = error.error.to_string();
+            writeln!(
+                f,
+                "{:#}: {}",
+                Location(&error.file, error.line),
+                message.trim_end()
+            )?;
+            for call in error.expansion.iter().rev() {
+                let location = Location(&call.file, call.line);
+                writeln!(f, "  in expansion of macro {} at {}", call.name, location)?;
+            }
         }
         Ok(())
     }
@@ -38,15 +74,157 @@ impl fmt::Display for AssemblyParseError {
 
 impl error::Error for AssemblyParseError {}
 
+/// Formats file and line as `path, line N` or just `line N`.
+///
+/// Alternate form capitalizes the latter: `Line N`.
+struct Location<'a>(&'a Option<PathBuf>, usize);
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(path) => write!(f, "{}, line {}", path.display(), self.1 + 1),
+            None if f.alternate() => write!(f, "Line {}", self.1 + 1),
+            None => write!(f, "line {}", self.1 + 1),
+        }
+    }
+}
+
 /// An error that may occur when parsing assembly's statement.
-#[derive(Error, Debug, PartialEq, Eq)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum StatementParseError {
     #[error("{0}")]
     OpError(#[from] OpParseError),
+
+    #[error("{0}")]
+    MacroError(#[from] MacroError),
+
+    #[error("{0}")]
+    IncludeError(#[from] IncludeError),
+
+    /// Directive is not known to the assembler.
+    #[error("Unknown directive {0}")]
+    UnknownDirective(String),
+
+    /// Constant directive without a name or a value.
+    #[error("Expected name and value after {directive}, got {num_args} arguments")]
+    WrongConstant {
+        directive: &'static str,
+        num_args: usize,
+    },
+
+    /// Symbol declaration without a name or with several names.
+    #[error("Expected one name after {directive}, got {num_args} arguments")]
+    WrongSymbol {
+        directive: &'static str,
+        num_args: usize,
+    },
+
+    /// Constant name is not an identifier or is reserved.
+    #[error("Wrong constant name: {0}")]
+    WrongConstantName(String),
+
+    /// Constant with the same name was defined earlier.
+    #[error("Constant {0} is already defined")]
+    DuplicateConstant(String),
+
+    /// Constant value can't be evaluated.
+    #[error("Wrong value of constant {name}: {error}")]
+    WrongConstantValue {
+        name: String,
+        error: ArgumentParseError,
+    },
+
+    /// Word width is not 32 or 64.
+    #[error("Expected 32 or 64 after .width, got: {0}")]
+    WrongWidth(String),
+
+    /// `.width` is not the first statement of the assembly.
+    #[error(".width must be the first statement of the program")]
+    MisplacedWidth,
+
+    /// Number of registers is not a number from 1 to `Register::MAX_COUNT`.
+    #[error("Expected number of registers from 1 to 16 after .registers, got: {0}")]
+    WrongRegisterCount(String),
+
+    /// `.registers` follows an operation or another `.registers`.
+    #[error(".registers must be declared once, before operations")]
+    MisplacedRegisters,
+
+    /// Operation uses a register, which the program doesn't have.
+    #[error("Register {register} is used, but the program has only {count} registers")]
+    MissingRegister { register: Register, count: u8 },
+}
+
+/// An error that may occur when defining or expanding a macro.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MacroError {
+    /// Macro name is not an identifier.
+    #[error("Wrong macro name: {0}")]
+    WrongName(String),
+
+    /// Macro name is the mnemonic of an operation or a directive, which it would replace.
+    #[error("Macro name {0} is reserved for an operation or a directive")]
+    ReservedName(String),
+
+    /// Macro parameter name is not an identifier.
+    #[error("Wrong macro parameter name: {0}")]
+    WrongParameter(String),
+
+    /// Macro with the same name was defined earlier.
+    #[error("Macro {0} is already defined")]
+    Duplicate(String),
+
+    /// Macro definition without `.endm`.
+    #[error("Macro {0} is not terminated with .endm")]
+    Unterminated(String),
+
+    /// `.endm` outside of macro definition.
+    #[error(".endm without .macro")]
+    UnexpectedEnd,
+
+    /// Macro defined inside of another macro definition.
+    #[error("Macro definitions can't be nested")]
+    Nested,
+
+    /// Macro body refers to a parameter, which is not declared.
+    #[error("Unknown macro parameter: \\{0}")]
+    UndefinedParameter(String),
+
+    /// Macro invoked with wrong number of arguments.
+    #[error("Macro {name} expects {expected} arguments, got {got}")]
+    WrongArguments {
+        name: String,
+        expected: usize,
+        got: usize,
+    },
+
+    /// Macro expansions are nested too deep, most likely macro is recursive.
+    #[error("Expansion of macro {name} exceeded the maximum depth of {depth}")]
+    Recursion { name: String, depth: usize },
+
+    /// Macro expansions produced too many lines.
+    #[error("Expansion of macro {name} exceeded the maximum of {lines} lines")]
+    TooLarge { name: String, lines: usize },
+}
+
+/// An error that may occur when including a file.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IncludeError {
+    /// Path is not a string in double quotes.
+    #[error("Expected path in double quotes after .include, got: {0}")]
+    WrongPath(String),
+
+    /// Included file can't be read.
+    #[error("Can't read included file {}: {message}", path.display())]
+    Read { path: PathBuf, message: String },
+
+    /// File includes itself, directly or through other files.
+    #[error("File {} is included recursively", .0.display())]
+    Cycle(PathBuf),
 }
 
 /// An error that may occur when parsing operation and it's arguments.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OpParseError {
     /// Wrong arguments for the operation.
     WrongArguments {
@@ -83,17 +261,53 @@ impl fmt::Display for OpParseError {
 impl error::Error for OpParseError {}
 
 /// An error that may occur when parsing arguments to the operation.
-#[derive(Debug, Error, PartialEq, Eq)]
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum ArgumentParseError {
     /// Expected register, but found something else.
-    #[error("Expected register A, B, C or D, got: {0}")]
+    #[error("Expected register A, B, C, D or R0 to R15, got: {0}")]
     WrongRegister(String),
 
     /// Expected value, but found something else.
-    #[error("Expected integer with radix 10, got: {0}")]
+    #[error("Expected integer or character literal, got: {0}")]
     WrongValue(String),
 
+    /// Value literal is well-formed, but doesn't fit into the machine word.
+    #[error("Integer literal doesn't fit into the machine word: {0}")]
+    ValueOutOfRange(String),
+
+    /// Expected floating-point value, but found something else.
+    #[error("Expected floating-point number, got: {0}")]
+    WrongFloat(String),
+
+    /// Argument of a frame operation is not a number from 0 to 255.
+    #[error("Expected index from 0 to 255, got: {0}")]
+    WrongIndex(String),
+
+    /// Name of the host function is not an identifier.
+    #[error("Expected name of the host function, got: {0}")]
+    WrongHostFunction(String),
+
     /// Expected register or value, but found something else.
     #[error("Expected integer or register, got: {0}")]
     WrongRegisterOrValue(String),
+
+    /// Expression is not well-formed.
+    #[error("Wrong expression: {0}")]
+    WrongExpression(String),
+
+    /// Expression refers to a constant which is not defined.
+    #[error("Undefined constant: {0}")]
+    UndefinedSymbol(String),
+
+    /// Expression result doesn't fit into the machine word.
+    #[error("Expression overflows the machine word: {0}")]
+    Overflow(String),
+
+    /// Expression divides by zero.
+    #[error("Expression divides by zero: {0}")]
+    DivisionByZero(String),
+
+    /// Expression uses external symbol in a way the linker can't resolve.
+    #[error("External symbols can only be added to the rest of expression: {0}")]
+    NotRelocatable(String),
 }
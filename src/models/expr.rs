@@ -0,0 +1,519 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+use super::{ArgumentParseError, Value, Width};
+
+/// Maximum depth of an expression: parentheses, unary and binary operators in a row.
+pub const MAX_EXPR_DEPTH: usize = 256;
+
+/// Named constants known to the assembler.
+///
+/// Besides the constants with known values, there are external symbols,
+/// declared with `.extern`, which are resolved by the linker.
+/// Values of the constants and expressions are limited by the word width.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    constants: HashMap<String, Value>,
+    externs: HashSet<String>,
+    width: Width,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    pub fn set_width(&mut self, width: Width) {
+        self.width = width;
+    }
+
+    /// Returns value of the constant, `None` for external and undefined symbols.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.constants.get(name).copied()
+    }
+
+    pub fn insert(&mut self, name: String, value: Value) {
+        self.constants.insert(name, value);
+    }
+
+    /// Declares a symbol, which is defined in another object file.
+    pub fn insert_extern(&mut self, name: String) {
+        self.externs.insert(name);
+    }
+
+    pub fn is_extern(&self, name: &str) -> bool {
+        self.externs.contains(name)
+    }
+
+    /// Returns true if the name is taken by a constant or an external symbol.
+    pub fn contains(&self, name: &str) -> bool {
+        self.constants.contains_key(name) || self.externs.contains(name)
+    }
+}
+
+/// Constant expression, evaluated by the assembler.
+///
+/// Supports integer and character literals, named constants, parentheses,
+/// unary minus and `+ - * / %` operators with the usual precedence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Value(Value),
+    Symbol(String),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl Expr {
+    /// Calculates value of the expression.
+    ///
+    /// Fails if expression uses a constant, which is not in `symbols`,
+    /// or when the result doesn't fit into the machine word.
+    pub fn evaluate(&self, symbols: &Symbols) -> Result<Value, ArgumentParseError> {
+        let width = symbols.width();
+        let value = match self {
+            Expr::Value(value) if width.contains(value.value()) => *value,
+            Expr::Value(value) => {
+                return Err(ArgumentParseError::ValueOutOfRange(value.to_string()))
+            }
+            Expr::Symbol(name) => match symbols.get(name) {
+                Some(value) => value,
+                None => return Err(ArgumentParseError::UndefinedSymbol(name.clone())),
+            },
+            Expr::Neg(expr) => {
+                let x = expr.evaluate(symbols)?.value();
+                let result = x.checked_neg().filter(|&x| width.contains(x));
+                Value(result.ok_or_else(|| ArgumentParseError::Overflow(self.to_string()))?)
+            }
+            Expr::Binary(op, left, right) => {
+                let a = left.evaluate(symbols)?.value();
+                let b = right.evaluate(symbols)?.value();
+                Value(op.apply(a, b, width, self)?)
+            }
+        };
+        Ok(value)
+    }
+
+    /// Calculates value of the expression, which may refer to one external symbol.
+    ///
+    /// Returns the external symbol, if any, and the value to add to it.
+    /// The symbol can only be added to or subtracted from the rest of the expression,
+    /// so that the linker doesn't have to evaluate expressions.
+    pub fn evaluate_relocatable(
+        &self,
+        symbols: &Symbols,
+    ) -> Result<(Option<String>, Value), ArgumentParseError> {
+        let not_relocatable = || ArgumentParseError::NotRelocatable(self.to_string());
+
+        match self {
+            Expr::Symbol(name) if symbols.is_extern(name) => Ok((Some(name.clone()), Value(0))),
+            Expr::Binary(op @ BinaryOp::Add, left, right)
+            | Expr::Binary(op @ BinaryOp::Sub, left, right) => {
+                let (left_symbol, a) = left.evaluate_relocatable(symbols)?;
+                let (right_symbol, b) = right.evaluate_relocatable(symbols)?;
+                let symbol = match (left_symbol, right_symbol, op) {
+                    (symbol, None, _) => symbol,
+                    (None, symbol, BinaryOp::Add) => symbol,
+                    _ => return Err(not_relocatable()),
+                };
+                let value = op.apply(a.value(), b.value(), symbols.width(), self)?;
+                Ok((symbol, Value(value)))
+            }
+            _ => match self.evaluate(symbols) {
+                Ok(value) => Ok((None, value)),
+                Err(ArgumentParseError::UndefinedSymbol(name)) if symbols.is_extern(&name) => {
+                    Err(not_relocatable())
+                }
+                Err(error) => Err(error),
+            },
+        }
+    }
+}
+
+impl BinaryOp {
+    /// Applies operation with overflow checks, `expr` is used in error messages.
+    fn apply(self, a: i64, b: i64, width: Width, expr: &Expr) -> Result<i64, ArgumentParseError> {
+        if b == 0 && (self == BinaryOp::Div || self == BinaryOp::Mod) {
+            return Err(ArgumentParseError::DivisionByZero(expr.to_string()));
+        }
+        let result = match self {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            BinaryOp::Mul => a.checked_mul(b),
+            BinaryOp::Div => a.checked_div(b),
+            BinaryOp::Mod => a.checked_rem(b),
+        };
+        result
+            .filter(|&x| width.contains(x))
+            .ok_or_else(|| ArgumentParseError::Overflow(expr.to_string()))
+    }
+}
+
+/// Returns true if `s` can be used as a name of a constant.
+pub fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(is_identifier_char),
+        _ => false,
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Value(&'a str),
+    Symbol(&'a str),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token<'_>>, ArgumentParseError> {
+    let wrong_expression = || ArgumentParseError::WrongExpression(s.trim().to_owned());
+
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '+' | '-' | '*' | '/' | '%' => Token::Op(c),
+            '\'' => {
+                let mut escaped = false;
+                let end = loop {
+                    match chars.next() {
+                        Some((_, _)) if escaped => escaped = false,
+                        Some((_, '\\')) => escaped = true,
+                        Some((idx, '\'')) => break idx + 1,
+                        Some(_) => (),
+                        None => return Err(wrong_expression()),
+                    }
+                };
+                Token::Value(&s[start..end])
+            }
+            _ if is_identifier_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(idx, c)) = chars.peek() {
+                    if !is_identifier_char(c) {
+                        break;
+                    }
+                    end = idx + c.len_utf8();
+                    chars.next();
+                }
+                let word = &s[start..end];
+                if c.is_ascii_digit() {
+                    Token::Value(word)
+                } else if is_identifier(word) {
+                    Token::Symbol(word)
+                } else {
+                    return Err(wrong_expression());
+                }
+            }
+            _ => return Err(wrong_expression()),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the expression tokens.
+struct Parser<'a> {
+    source: &'a str,
+    width: Width,
+    tokens: Vec<Token<'a>>,
+    position: usize,
+
+    /// Depth of the expression being parsed, limited by `MAX_EXPR_DEPTH`,
+    /// so that neither parsing nor evaluation overflows the stack.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn error(&self) -> ArgumentParseError {
+        ArgumentParseError::WrongExpression(self.source.trim().to_owned())
+    }
+
+    /// Goes one level deeper, the caller restores the depth when it's done.
+    fn deeper(&mut self) -> Result<(), ArgumentParseError> {
+        if self.depth == MAX_EXPR_DEPTH {
+            return Err(self.error());
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Expr, ArgumentParseError> {
+        let depth = self.depth;
+        let mut left = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op('+')) => BinaryOp::Add,
+                Some(Token::Op('-')) => BinaryOp::Sub,
+                _ => break,
+            };
+            self.next();
+            self.deeper()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Expr, ArgumentParseError> {
+        let depth = self.depth;
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op('*')) => BinaryOp::Mul,
+                Some(Token::Op('/')) => BinaryOp::Div,
+                Some(Token::Op('%')) => BinaryOp::Mod,
+                _ => break,
+            };
+            self.next();
+            self.deeper()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ArgumentParseError> {
+        match self.peek() {
+            Some(Token::Op('+')) => {
+                self.next();
+                self.deeper()?;
+                let expr = self.unary();
+                self.depth -= 1;
+                expr
+            }
+            Some(Token::Op('-')) => {
+                self.next();
+                // Negative literals are parsed as a whole,
+                // otherwise the smallest integer would overflow.
+                if let Some(&Token::Value(literal)) = self.peek() {
+                    if !literal.starts_with('\'') {
+                        self.next();
+                        let literal = format!("-{}", literal);
+                        return Ok(Expr::Value(Value::parse(&literal, self.width)?));
+                    }
+                }
+                self.deeper()?;
+                let expr = self.unary()?;
+                self.depth -= 1;
+                Ok(Expr::Neg(Box::new(expr)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ArgumentParseError> {
+        match self.next() {
+            Some(Token::Value(literal)) => Ok(Expr::Value(Value::parse(literal, self.width)?)),
+            Some(Token::Symbol(name)) => Ok(Expr::Symbol(name.to_owned())),
+            Some(Token::Open) => {
+                self.deeper()?;
+                let expr = self.expr()?;
+                self.depth -= 1;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(self.error()),
+                }
+            }
+            _ => Err(self.error()),
+        }
+    }
+}
+
+impl Expr {
+    /// Parses expression, literals must fit into the word of given width.
+    pub fn parse(s: &str, width: Width) -> Result<Self, ArgumentParseError> {
+        let mut parser = Parser {
+            source: s,
+            width,
+            tokens: tokenize(s)?,
+            position: 0,
+            depth: 0,
+        };
+        let expr = parser.expr()?;
+        if parser.position == parser.tokens.len() {
+            Ok(expr)
+        } else {
+            Err(parser.error())
+        }
+    }
+}
+
+/// Parses expression with 32-bit literals, see `Expr::parse` for other widths.
+impl FromStr for Expr {
+    type Err = ArgumentParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Expr::parse(s, Width::W32)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Value(value) => write!(f, "{}", value),
+            Expr::Symbol(name) => write!(f, "{}", name),
+            Expr::Neg(expr) => write!(f, "-{}", expr),
+            Expr::Binary(op, left, right) => write!(f, "({} {} {})", left, op, right),
+        }
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn evaluate(s: &str) -> Result<i64, ArgumentParseError> {
+        let mut symbols = Symbols::new();
+        symbols.insert("SIZE".into(), Value(10));
+        symbols.insert("max_len".into(), i32::MAX.into());
+        s.parse::<Expr>()?.evaluate(&symbols).map(Value::value)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("SIZE*4+1"), Ok(41));
+        assert_eq!(evaluate("1 + SIZE * 4"), Ok(41));
+        assert_eq!(evaluate("(1 + SIZE) * 4"), Ok(44));
+        assert_eq!(evaluate("10 - 3 - 2"), Ok(5));
+        assert_eq!(evaluate("100 / 10 / 5 % 3"), Ok(2));
+        assert_eq!(evaluate("-SIZE + -(2 - 5)"), Ok(-7));
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(evaluate("'a' - 1"), Ok(96));
+        assert_eq!(evaluate("' ' + '\\n'"), Ok(42));
+        assert_eq!(evaluate("0b11 * 0o10"), Ok(24));
+        assert_eq!(evaluate("-2147483648"), Ok(i32::MIN.into()));
+    }
+
+    #[test]
+    fn undefined_symbol() {
+        assert_eq!(
+            evaluate("SIZE + size"),
+            Err(ArgumentParseError::UndefinedSymbol("size".into()))
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            evaluate("max_len + 1"),
+            Err(ArgumentParseError::Overflow("(max_len + 1)".into()))
+        );
+        assert_eq!(
+            evaluate("-(-2147483648)"),
+            Err(ArgumentParseError::Overflow("--2147483648".into()))
+        );
+        assert_eq!(
+            evaluate("SIZE % (SIZE - 10)"),
+            Err(ArgumentParseError::DivisionByZero(
+                "(SIZE % (SIZE - 10))".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn relocatable() {
+        let mut symbols = Symbols::new();
+        symbols.insert("SIZE".into(), Value(10));
+        symbols.insert_extern("BASE".into());
+
+        let evaluate = |s: &str| s.parse::<Expr>().unwrap().evaluate_relocatable(&symbols);
+        assert_eq!(evaluate("SIZE * 2"), Ok((None, Value(20))));
+        assert_eq!(evaluate("BASE"), Ok((Some("BASE".into()), Value(0))));
+        assert_eq!(
+            evaluate("SIZE + BASE - 1"),
+            Ok((Some("BASE".into()), Value(9)))
+        );
+        assert_eq!(
+            evaluate("BASE - (SIZE - 1)"),
+            Ok((Some("BASE".into()), Value(-9)))
+        );
+        for s in ["-BASE", "2 * BASE", "SIZE - BASE", "BASE + BASE"].iter() {
+            let error = ArgumentParseError::NotRelocatable(s.parse::<Expr>().unwrap().to_string());
+            assert_eq!(evaluate(s), Err(error));
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for s in [
+            "", "1 +", "(1", "1)", "1 2", "* 2", "'a", "1 $ 2", "0x10 | 1",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                evaluate(s),
+                Err(ArgumentParseError::WrongExpression(s.trim().to_string()))
+            );
+        }
+        assert_eq!(
+            evaluate("12a"),
+            Err(ArgumentParseError::WrongValue("12a".into()))
+        );
+    }
+
+    #[test]
+    fn depth() {
+        let parentheses = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        // Minus right before a literal is a part of the literal
+        let unary = |depth| format!("{}1", &"-+".repeat(depth)[depth..]);
+        let binary = |depth| format!("1{}", " * 1".repeat(depth));
+        for nested in [parentheses, unary, binary].iter() {
+            assert_eq!(evaluate(&nested(MAX_EXPR_DEPTH)), Ok(1));
+            let s = nested(MAX_EXPR_DEPTH + 1);
+            assert_eq!(
+                evaluate(&s),
+                Err(ArgumentParseError::WrongExpression(s.clone()))
+            );
+        }
+    }
+}
@@ -0,0 +1,473 @@
+//! Macro expansion, which happens before statements are parsed.
+//!
+//! Macros are defined with
+//!
+//! ```text
+//! .macro NAME param1, param2
+//!     ...
+//! .endm
+//! ```
+//!
+//! and invoked as `NAME arg1, arg2`. Inside of the body `\param` is replaced with
+//! the argument text and `\@` with a number, which is unique for each expansion,
+//! so that a macro can define its own local constants, e.g. `.equ tmp\@ \param`.
+
+use std::{collections::HashMap, rc::Rc};
+
+use super::{
+    is_identifier,
+    source::{split_operands, split_outside_quotes, SourceLine},
+    LineWithError, MacroCall, MacroError, DIRECTIVES, MNEMONICS,
+};
+
+/// Maximum depth of nested macro expansions.
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+/// Maximum number of lines produced by all macro expansions of the source.
+pub const MAX_EXPANDED_LINES: usize = 1 << 16;
+
+struct Macro {
+    name: String,
+    params: Vec<String>,
+    body: Vec<SourceLine>,
+}
+
+#[derive(Default)]
+struct Expander {
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    expanded_lines: usize,
+    output: Vec<Result<SourceLine, LineWithError>>,
+}
+
+/// Expands all macros in the source and removes empty lines.
+///
+/// Errors from the previous stages are passed through in place.
+/// Macros must be defined before they are used.
+pub(crate) fn expand_macros(
+    lines: Vec<Result<SourceLine, LineWithError>>,
+) -> Vec<Result<SourceLine, LineWithError>> {
+    let mut expander = Expander::default();
+    let mut lines = lines.into_iter();
+
+    while let Some(line) = lines.next() {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                expander.output.push(Err(error));
+                continue;
+            }
+        };
+
+        let result = match first_word(&line.text) {
+            Some(word) if word.eq_ignore_ascii_case(".macro") => expander.define(&line, &mut lines),
+            Some(word) if word.eq_ignore_ascii_case(".endm") => Err(MacroError::UnexpectedEnd),
+            Some(_) => {
+                if let Err(error) = expander.expand(line) {
+                    expander.output.push(Err(error));
+                }
+                continue;
+            }
+            None => Ok(()),
+        };
+
+        if let Err(error) = result {
+            expander.output.push(Err(line.error(error)));
+        }
+    }
+
+    expander.output
+}
+
+impl Expander {
+    /// Reads macro definition, which starts with the `.macro` line `header`.
+    fn define(
+        &mut self,
+        header: &SourceLine,
+        lines: &mut impl Iterator<Item = Result<SourceLine, LineWithError>>,
+    ) -> Result<(), MacroError> {
+        let mut words = split_outside_quotes(header.text.trim(), |c| c.is_whitespace() || c == ',')
+            .into_iter()
+            .filter(|word| !word.is_empty())
+            .skip(1);
+
+        let name = words.next().unwrap_or_default().to_owned();
+        let params: Vec<String> = words.map(str::to_owned).collect();
+
+        let mut body = Vec::new();
+        let mut terminated = false;
+        for line in lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => {
+                    self.output.push(Err(error));
+                    continue;
+                }
+            };
+            match first_word(&line.text) {
+                Some(word) if word.eq_ignore_ascii_case(".endm") => {
+                    terminated = true;
+                    break;
+                }
+                Some(word) if word.eq_ignore_ascii_case(".macro") => {
+                    self.output.push(Err(line.error(MacroError::Nested)));
+                }
+                Some(_) => body.push(line),
+                None => (),
+            }
+        }
+
+        let reserved = MNEMONICS.contains(&name.to_ascii_uppercase().as_str())
+            || DIRECTIVES.contains(&name.to_ascii_lowercase().as_str());
+        if reserved {
+            return Err(MacroError::ReservedName(name));
+        }
+        if !is_identifier(&name) {
+            return Err(MacroError::WrongName(name));
+        }
+        if let Some(param) = params.iter().find(|param| !is_identifier(param)) {
+            return Err(MacroError::WrongParameter(param.clone()));
+        }
+        if !terminated {
+            return Err(MacroError::Unterminated(name));
+        }
+        if self.macros.contains_key(&name.to_ascii_uppercase()) {
+            return Err(MacroError::Duplicate(name));
+        }
+
+        // Substitution errors are easier to understand at the definition,
+        // than at each of the call sites.
+        for line in body.iter() {
+            let args = vec![String::new(); params.len()];
+            if let Err(error) = substitute(&line.text, &params, &args, 0) {
+                self.output.push(Err(line.error(error)));
+            }
+        }
+
+        let key = name.to_ascii_uppercase();
+        self.macros.insert(key, Macro { name, params, body });
+        Ok(())
+    }
+
+    /// Writes a line to the output, expanding it if it's a macro invocation.
+    ///
+    /// Expansion stops at the first error, so that a runaway recursion
+    /// produces only one diagnostic.
+    fn expand(&mut self, line: SourceLine) -> Result<(), LineWithError> {
+        let word = first_word(&line.text).unwrap_or_default();
+        let mac = match self.macros.get(&word.to_ascii_uppercase()) {
+            Some(mac) => mac,
+            None => {
+                self.output.push(Ok(line));
+                return Ok(());
+            }
+        };
+
+        if line.expansion.len() >= MAX_MACRO_DEPTH {
+            return Err(line.error(MacroError::Recursion {
+                name: mac.name.clone(),
+                depth: MAX_MACRO_DEPTH,
+            }));
+        }
+
+        let rest = line.text.trim_start()[word.len()..].trim();
+        let args: Vec<String> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            split_operands(rest)
+                .into_iter()
+                .map(str::to_owned)
+                .collect()
+        };
+        if args.len() != mac.params.len() {
+            return Err(line.error(MacroError::WrongArguments {
+                name: mac.name.clone(),
+                expected: mac.params.len(),
+                got: args.len(),
+            }));
+        }
+
+        if self.expanded_lines + mac.body.len() > MAX_EXPANDED_LINES {
+            return Err(line.error(MacroError::TooLarge {
+                name: mac.name.clone(),
+                lines: MAX_EXPANDED_LINES,
+            }));
+        }
+        self.expanded_lines += mac.body.len();
+
+        let unique = self.expansions;
+        self.expansions += 1;
+
+        let mut expansion = line.expansion.to_vec();
+        expansion.push(MacroCall {
+            name: mac.name.clone(),
+            file: line.file.clone(),
+            line: line.line,
+        });
+        let expansion: Rc<[MacroCall]> = expansion.into();
+
+        let mut body = Vec::new();
+        for body_line in mac.body.iter() {
+            let text = substitute(&body_line.text, &mac.params, &args, unique)
+                .map_err(|error| line.error(error))?;
+            body.push(SourceLine {
+                text,
+                file: body_line.file.clone(),
+                line: body_line.line,
+                expansion: Rc::clone(&expansion),
+            });
+        }
+
+        for body_line in body {
+            self.expand(body_line)?;
+        }
+        Ok(())
+    }
+}
+
+fn first_word(text: &str) -> Option<&str> {
+    text.split_whitespace().next()
+}
+
+/// Replaces `\param` with the corresponding argument and `\@` with `unique`.
+fn substitute(
+    text: &str,
+    params: &[String],
+    args: &[String],
+    unique: usize,
+) -> Result<String, MacroError> {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '\'' => in_quotes = false,
+                _ => (),
+            }
+            result.push(c);
+        } else if c == '\\' {
+            if chars.peek() == Some(&'@') {
+                chars.next();
+                result.push_str(&unique.to_string());
+                continue;
+            }
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            match params.iter().position(|param| *param == name) {
+                Some(idx) => result.push_str(&args[idx]),
+                None => return Err(MacroError::UndefinedParameter(name)),
+            }
+        } else {
+            in_quotes = c == '\'';
+            result.push(c);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::StatementParseError;
+
+    type Expanded = Result<(usize, String), (usize, MacroError, Vec<MacroCall>)>;
+
+    fn expand(source: &str) -> Vec<Expanded> {
+        let lines = source
+            .lines()
+            .enumerate()
+            .map(|(line, text)| Ok(SourceLine::new(text, None, line)))
+            .collect();
+        expand_macros(lines)
+            .into_iter()
+            .map(|line| match line {
+                Ok(SourceLine { text, line, .. }) => Ok((line, text.trim().to_owned())),
+                Err(LineWithError {
+                    line,
+                    error: StatementParseError::MacroError(error),
+                    expansion,
+                    ..
+                }) => Err((line, error, expansion)),
+                Err(error) => panic!("unexpected error {:?}", error),
+            })
+            .collect()
+    }
+
+    fn call(name: &str, line: usize) -> MacroCall {
+        MacroCall {
+            name: name.to_owned(),
+            file: None,
+            line,
+        }
+    }
+
+    #[test]
+    fn substitution() {
+        let source = "
+.macro square x
+    PUSH \\x
+    PUSH \\x
+    MUL
+.endm
+.macro twice op, x
+    \\op \\x
+    \\op '\\x'
+.endm
+SQUARE SIZE + 1
+twice PUSH, 2
+";
+        assert_eq!(
+            expand(source),
+            vec![
+                Ok((2, "PUSH SIZE + 1".into())),
+                Ok((3, "PUSH SIZE + 1".into())),
+                Ok((4, "MUL".into())),
+                Ok((7, "PUSH 2".into())),
+                Ok((8, "PUSH '\\x'".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn unique_locals() {
+        let source = "
+.macro local value
+    .equ tmp\\@ \\value
+.endm
+.macro outer
+    local 1
+    local 2
+.endm
+outer
+local 3
+";
+        let texts: Vec<_> = expand(source).into_iter().map(|x| x.unwrap().1).collect();
+        assert_eq!(texts, vec![".equ tmp1 1", ".equ tmp2 2", ".equ tmp3 3"]);
+    }
+
+    #[test]
+    fn nested_expansion_errors() {
+        let source = "
+.macro inner
+    PUSH 1
+.endm
+.macro outer
+    inner 1
+.endm
+outer
+";
+        assert_eq!(
+            expand(source),
+            vec![Err((
+                5,
+                MacroError::WrongArguments {
+                    name: "inner".into(),
+                    expected: 0,
+                    got: 1,
+                },
+                vec![call("outer", 7)],
+            ))]
+        );
+    }
+
+    #[test]
+    fn recursion() {
+        let source = "
+.macro forever
+    PUSH 1
+    forever
+.endm
+forever
+";
+        let result = expand(source);
+        assert_eq!(result.len(), MAX_MACRO_DEPTH + 1);
+        let (line, error, expansion) = result.last().unwrap().clone().unwrap_err();
+        assert_eq!(line, 3);
+        assert_eq!(
+            error,
+            MacroError::Recursion {
+                name: "forever".into(),
+                depth: MAX_MACRO_DEPTH,
+            }
+        );
+        assert_eq!(expansion.len(), MAX_MACRO_DEPTH);
+        assert_eq!(expansion[0], call("forever", 5));
+        assert_eq!(expansion[1], call("forever", 3));
+    }
+
+    #[test]
+    fn too_large() {
+        // Each macro calls the previous one twice, the last one expands to 2^17 lines
+        let mut source = String::from(".macro m0\n    PUSH 1\n.endm\n");
+        for i in 1..=17 {
+            source += &format!(".macro m{}\n    m{1}\n    m{1}\n.endm\n", i, i - 1);
+        }
+        source += "m17\n";
+
+        let result = expand(&source);
+        assert!(result.len() <= MAX_EXPANDED_LINES);
+        let (errors, lines): (Vec<_>, Vec<_>) = result.into_iter().partition(Result::is_err);
+        assert!(lines.len() > MAX_EXPANDED_LINES / 4);
+        assert_eq!(errors.len(), 1);
+        let (line, error, _) = errors[0].clone().unwrap_err();
+        assert!(matches!(
+            error,
+            MacroError::TooLarge {
+                lines: MAX_EXPANDED_LINES,
+                ..
+            }
+        ));
+        assert!(line < 3 * 18);
+    }
+
+    #[test]
+    fn definition_errors() {
+        let source = "
+.endm
+.macro bad-name
+.endm
+.macro twice x
+    PUSH \\y
+.endm
+.macro twice
+.endm
+.macro add
+    PUSH 9
+.endm
+.macro .equ
+.endm
+.macro unterminated
+    PUSH 1
+";
+        let errors: Vec<_> = expand(source)
+            .into_iter()
+            .map(|x| {
+                let (line, error, _) = x.unwrap_err();
+                (line, error)
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (1, MacroError::UnexpectedEnd),
+                (2, MacroError::WrongName("bad-name".into())),
+                (5, MacroError::UndefinedParameter("y".into())),
+                (7, MacroError::Duplicate("twice".into())),
+                (9, MacroError::ReservedName("add".into())),
+                (12, MacroError::ReservedName(".equ".into())),
+                (14, MacroError::Unterminated("unterminated".into())),
+            ]
+        );
+    }
+}
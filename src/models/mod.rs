@@ -1,15 +1,24 @@
-//!
+//! Textual representation of the stack machine programs.
 
 mod assembly;
 mod error;
+mod expr;
+mod macros;
 mod op;
 mod register;
+mod source;
 mod statement;
 mod value;
+mod width;
 
 pub use assembly::Assembly;
 pub use error::*;
-pub use op::Op;
+pub use expr::{is_identifier, BinaryOp, Expr, Symbols, MAX_EXPR_DEPTH};
+pub use macros::{MAX_EXPANDED_LINES, MAX_MACRO_DEPTH};
+pub use op::{Op, MNEMONICS};
 pub use register::Register;
-pub use statement::Statement;
-pub use value::Value;
+pub use source::Location;
+pub(crate) use source::{split_comment, split_operands, strip_comment};
+pub use statement::{Statement, DIRECTIVES};
+pub use value::{Float, Value};
+pub use width::Width;
@@ -1,9 +1,10 @@
-use std::{fmt::Display, str::FromStr};
+use std::{convert::TryInto, fmt::Display, str::FromStr};
 
 use super::{
-    ArgumentParseError,
+    source::{split_operands, split_outside_quotes},
+    ArgumentParseError, Expr, Float,
     OpParseError::{self, *},
-    Register, Value,
+    Register, Symbols, Value,
 };
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -13,33 +14,206 @@ pub enum Op {
     Mul,
     Div,
     Mod,
+    /// Shifts the value left, shift amount is taken modulo the word width.
+    Shl,
     Input,
     Output,
     Halt,
     PushValue(Value),
     PushRegister(Register),
     PopRegister(Register),
+
+    /// Copies the second register into the first one: `MOV A, B`.
+    MovRegister(Register, Register),
+    /// Sets the register: `MOV A, 10`.
+    MovValue(Register, Value),
+    Inc(Register),
+    Dec(Register),
+    /// Adds the value to the register: `ADD A, 10`.
+    AddValue(Register, Value),
+
+    /// Pushes the value onto the float stack, which is separate from the integer stack.
+    PushFloat(Float),
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FSqrt,
+    /// Moves integer from the stack to the float stack.
+    IToF,
+    /// Moves float to the integer stack, rounding it towards zero.
+    ///
+    /// Values outside of the word range are saturated, `NaN` becomes 0.
+    FToI,
+    FOutput,
+
+    /// Starts a frame with given number of locals, which are initialized with zeros.
+    ///
+    /// Values below the frame are its arguments, they can be read with `ARG`, but not popped.
+    Enter(u8),
+    /// Removes locals of the current frame, values pushed after them stay on the stack.
+    Leave,
+    /// Pushes the local of the current frame: `LOCAL.GET 0`.
+    LocalGet(u8),
+    /// Pops the value into the local of the current frame: `LOCAL.SET 0`.
+    LocalSet(u8),
+    /// Pushes the argument of the current frame, `ARG 0` is the value pushed last before `ENTER`.
+    Arg(u8),
+
+    /// Reads an integer from the device attached to the port: `IN 1`, `INPUT` is `IN 0`.
+    In(u8),
+    /// Writes an integer to the device attached to the port: `OUT 1`, `OUTPUT` is `OUT 0`.
+    Out(u8),
+
+    /// Calls the host function with given number, numbers are assigned in order of registration.
+    Syscall(u8),
+    /// Calls the host function from the import table of the program.
+    ///
+    /// Assembly refers to host functions by name with `CALLHOST name`, which is
+    /// `Statement::CallHost`, the import table is built when the assembly is compiled.
+    CallHost(u8),
+
+    /// Pops the value and fails with `ExecutionError::AssertionFailed` if it is zero.
+    Assert,
+    /// Pops two values and fails with `ExecutionError::AssertionFailed` if they differ.
+    AssertEq,
+    /// Pauses `Machine::run` if breakpoints are enabled, otherwise does nothing.
+    Breakpoint,
 }
 
-impl FromStr for Op {
-    type Err = OpParseError;
+/// Mnemonics of the operations and of `CALLHOST`, as they are written by `Display`.
+pub const MNEMONICS: &[&str] = &[
+    "ADD",
+    "SUB",
+    "MUL",
+    "DIV",
+    "MOD",
+    "SHL",
+    "INPUT",
+    "OUTPUT",
+    "HALT",
+    "PUSH",
+    "POP",
+    "MOV",
+    "INC",
+    "DEC",
+    "PUSHF",
+    "FADD",
+    "FSUB",
+    "FMUL",
+    "FDIV",
+    "FSQRT",
+    "ITOF",
+    "FTOI",
+    "FOUTPUT",
+    "ENTER",
+    "LEAVE",
+    "LOCAL.GET",
+    "LOCAL.SET",
+    "ARG",
+    "IN",
+    "OUT",
+    "SYSCALL",
+    "CALLHOST",
+    "ASSERT",
+    "ASSERTEQ",
+    "BREAKPOINT",
+];
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Op {
+    /// Returns registers, which are read or written by the operation.
+    pub fn registers(&self) -> Vec<Register> {
+        use Op::*;
+
+        match *self {
+            PushRegister(r) | PopRegister(r) | MovValue(r, _) | Inc(r) | Dec(r) => vec![r],
+            AddValue(r, _) => vec![r],
+            MovRegister(r, source) => vec![r, source],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns numbers of values, which the operation pops from the integer stack and
+    /// pushes onto it, `None` if the operation changes frames or calls a host function.
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        use Op::*;
+
+        Some(match *self {
+            Add | Sub | Mul | Div | Mod | Shl => (2, 1),
+            Input | In(_) | PushValue(_) | PushRegister(_) | FToI | LocalGet(_) | Arg(_) => (0, 1),
+            Output | Out(_) | PopRegister(_) | IToF | LocalSet(_) | Assert => (1, 0),
+            AssertEq => (2, 0),
+            Halt | MovRegister(..) | MovValue(..) | Inc(_) | Dec(_) | AddValue(..) => (0, 0),
+            PushFloat(_) | FAdd | FSub | FMul | FDiv | FSqrt | FOutput | Breakpoint => (0, 0),
+            Enter(_) | Leave | Syscall(_) | CallHost(_) => return None,
+        })
+    }
+
+    /// Parses operation, using `symbols` to evaluate constant expressions in its arguments.
+    pub fn parse(s: &str, symbols: &Symbols) -> Result<Self, OpParseError> {
         use Op::*;
 
-        let s = s.to_uppercase();
-        let words = s.trim().split_whitespace().collect::<Vec<&str>>();
+        // Only the mnemonic is case-insensitive as a whole, arguments may contain
+        // character literals and constant names, which must keep their case.
+        let words = split_words(s);
+        let (op, args) = match words.split_first() {
+            Some((op, args)) => (op.to_uppercase(), args),
+            None => (String::new(), &[][..]),
+        };
 
-        match (words[0], &words[1..]) {
+        match (op.as_str(), args) {
             ("ADD", []) => Ok(Add),
             ("SUB", []) => Ok(Sub),
             ("MUL", []) => Ok(Mul),
             ("DIV", []) => Ok(Div),
             ("MOD", []) => Ok(Mod),
+            ("SHL", []) => Ok(Shl),
             ("INPUT", []) => Ok(Input),
             ("OUTPUT", []) => Ok(Output),
             ("HALT", []) => Ok(Halt),
-            ("PUSH", [arg]) => parse_push(arg),
+            ("FADD", []) => Ok(FAdd),
+            ("FSUB", []) => Ok(FSub),
+            ("FMUL", []) => Ok(FMul),
+            ("FDIV", []) => Ok(FDiv),
+            ("FSQRT", []) => Ok(FSqrt),
+            ("ITOF", []) => Ok(IToF),
+            ("FTOI", []) => Ok(FToI),
+            ("FOUTPUT", []) => Ok(FOutput),
+            ("LEAVE", []) => Ok(Leave),
+            ("ASSERT", []) => Ok(Assert),
+            ("ASSERTEQ", []) => Ok(AssertEq),
+            ("BREAKPOINT", []) => Ok(Breakpoint),
+            ("ENTER", [_, ..]) => {
+                parse_index("ENTER", rest_of_line(s, words[0]), symbols).map(Enter)
+            }
+            ("LOCAL.GET", [_, ..]) => {
+                parse_index("LOCAL.GET", rest_of_line(s, words[0]), symbols).map(LocalGet)
+            }
+            ("LOCAL.SET", [_, ..]) => {
+                parse_index("LOCAL.SET", rest_of_line(s, words[0]), symbols).map(LocalSet)
+            }
+            ("ARG", [_, ..]) => parse_index("ARG", rest_of_line(s, words[0]), symbols).map(Arg),
+            ("IN", [_, ..]) => parse_index("IN", rest_of_line(s, words[0]), symbols).map(In),
+            ("OUT", [_, ..]) => parse_index("OUT", rest_of_line(s, words[0]), symbols).map(Out),
+            ("SYSCALL", [_, ..]) => {
+                parse_index("SYSCALL", rest_of_line(s, words[0]), symbols).map(Syscall)
+            }
+            ("PUSHF", [arg]) => match arg.parse() {
+                Ok(value) => Ok(PushFloat(value)),
+                Err(err) => Err(WrongArguments {
+                    op: "PUSHF",
+                    errors: vec![(0, err)],
+                }),
+            },
+            ("PUSH", [arg]) => parse_push(arg, symbols),
+            ("PUSH", [_, _, ..])
+                if Expr::parse(rest_of_line(s, words[0]), symbols.width()).is_ok() =>
+            {
+                parse_push(rest_of_line(s, words[0]), symbols)
+            }
+            ("MOV", [_, ..]) | ("ADD", [_, ..]) | ("INC", [_, ..]) | ("DEC", [_, ..]) => {
+                parse_register_op(op, rest_of_line(s, words[0]), symbols)
+            }
             ("POP", [register]) => match register.parse() {
                 Ok(register) => Ok(PopRegister(register)),
                 Err(err) => Err(WrongArguments {
@@ -47,24 +221,126 @@ impl FromStr for Op {
                     errors: vec![(0, err)],
                 }),
             },
-            (op, args) => Err(WrongOp {
-                op: op.to_owned(),
+            (_, args) => Err(WrongOp {
+                op,
                 num_args: args.len(),
             }),
         }
     }
 }
 
-fn parse_push<'a>(arg: &'a str) -> Result<Op, OpParseError> {
-    if let Ok(value) = arg.parse() {
-        Ok(Op::PushValue(value))
-    } else if let Ok(register) = arg.parse() {
-        Ok(Op::PushRegister(register))
-    } else {
-        Err(WrongArguments {
+impl FromStr for Op {
+    type Err = OpParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Op::parse(s, &Symbols::new())
+    }
+}
+
+/// Returns everything after the first word of the statement.
+fn rest_of_line<'a>(s: &'a str, first_word: &str) -> &'a str {
+    s.trim_start()[first_word.len()..].trim()
+}
+
+/// Splits a statement into whitespace-separated words.
+///
+/// Whitespace inside of a character literal (`' '`) doesn't split words.
+fn split_words(s: &str) -> Vec<&str> {
+    split_outside_quotes(s, char::is_whitespace)
+        .into_iter()
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Register or value operand.
+enum Operand {
+    Register(Register),
+    Value(Value),
+}
+
+fn parse_operand(arg: &str, symbols: &Symbols) -> Result<Operand, ArgumentParseError> {
+    if let Ok(register) = arg.parse() {
+        return Ok(Operand::Register(register));
+    }
+
+    let expr = Expr::parse(arg, symbols.width());
+    match expr.and_then(|expr| expr.evaluate(symbols)) {
+        Ok(value) => Ok(Operand::Value(value)),
+        Err(ArgumentParseError::WrongValue(_)) | Err(ArgumentParseError::WrongExpression(_)) => {
+            Err(ArgumentParseError::WrongRegisterOrValue(arg.to_uppercase()))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_push(arg: &str, symbols: &Symbols) -> Result<Op, OpParseError> {
+    match parse_operand(arg, symbols) {
+        Ok(Operand::Register(register)) => Ok(Op::PushRegister(register)),
+        Ok(Operand::Value(value)) => Ok(Op::PushValue(value)),
+        Err(error) => Err(WrongArguments {
             op: "PUSH",
-            errors: vec![(0, ArgumentParseError::WrongRegisterOrValue(arg.to_owned()))],
-        })?
+            errors: vec![(0, error)],
+        }),
+    }
+}
+
+/// Parses the byte argument of frame operations, ports and `SYSCALL`,
+/// a constant expression from 0 to 255.
+fn parse_index(op: &'static str, arg: &str, symbols: &Symbols) -> Result<u8, OpParseError> {
+    let expr = Expr::parse(arg, symbols.width());
+    let index = expr
+        .and_then(|expr| expr.evaluate(symbols))
+        .and_then(|value| {
+            value
+                .value()
+                .try_into()
+                .map_err(|_| ArgumentParseError::WrongIndex(arg.to_owned()))
+        });
+    index.map_err(|error| WrongArguments {
+        op,
+        errors: vec![(0, error)],
+    })
+}
+
+/// Parses operations on a register with comma-separated operands:
+/// `MOV A, B`, `MOV A, 1`, `INC A`, `DEC A` and `ADD A, 1`.
+fn parse_register_op(op: String, operands: &str, symbols: &Symbols) -> Result<Op, OpParseError> {
+    let operands = split_operands(operands);
+    let (name, register, source): (&'static str, _, _) = match (op.as_str(), &operands[..]) {
+        ("MOV", [register, source]) => ("MOV", register, Some(source)),
+        ("ADD", [register, source]) => ("ADD", register, Some(source)),
+        ("INC", [register]) => ("INC", register, None),
+        ("DEC", [register]) => ("DEC", register, None),
+        _ => {
+            return Err(WrongOp {
+                op,
+                num_args: operands.len(),
+            })
+        }
+    };
+
+    let register = register.parse::<Register>();
+    let source = source.map(|source| match parse_operand(source, symbols) {
+        Ok(Operand::Register(_)) if name == "ADD" => {
+            Err(ArgumentParseError::WrongValue(source.to_string()))
+        }
+        result => result,
+    });
+
+    match (register, source) {
+        (Ok(r), None) if name == "INC" => Ok(Op::Inc(r)),
+        (Ok(r), None) => Ok(Op::Dec(r)),
+        (Ok(r), Some(Ok(Operand::Register(source)))) => Ok(Op::MovRegister(r, source)),
+        (Ok(r), Some(Ok(Operand::Value(value)))) if name == "MOV" => Ok(Op::MovValue(r, value)),
+        (Ok(r), Some(Ok(Operand::Value(value)))) => Ok(Op::AddValue(r, value)),
+        (register, source) => {
+            let register = register.err().map(|error| (0, error));
+            let source = source.and_then(Result::err).map(|error| (1, error));
+            Err(WrongArguments {
+                op: name,
+                errors: register.into_iter().chain(source).collect(),
+            })
+        }
     }
 }
 
@@ -78,12 +354,39 @@ impl Display for Op {
             Mul => w("MUL"),
             Div => w("DIV"),
             Mod => w("MOD"),
+            Shl => w("SHL"),
             Input => w("INPUT"),
             Output => w("OUTPUT"),
             Halt => w("HALT"),
             PushValue(v) => w(&format!("PUSH {}", v)),
             PushRegister(r) => w(&format!("PUSH {}", r)),
             PopRegister(r) => w(&format!("POP {}", r)),
+            MovRegister(r, source) => w(&format!("MOV {}, {}", r, source)),
+            MovValue(r, v) => w(&format!("MOV {}, {}", r, v)),
+            Inc(r) => w(&format!("INC {}", r)),
+            Dec(r) => w(&format!("DEC {}", r)),
+            AddValue(r, v) => w(&format!("ADD {}, {}", r, v)),
+            PushFloat(v) => w(&format!("PUSHF {}", v)),
+            FAdd => w("FADD"),
+            FSub => w("FSUB"),
+            FMul => w("FMUL"),
+            FDiv => w("FDIV"),
+            FSqrt => w("FSQRT"),
+            IToF => w("ITOF"),
+            FToI => w("FTOI"),
+            FOutput => w("FOUTPUT"),
+            Enter(n) => w(&format!("ENTER {}", n)),
+            Leave => w("LEAVE"),
+            LocalGet(i) => w(&format!("LOCAL.GET {}", i)),
+            LocalSet(i) => w(&format!("LOCAL.SET {}", i)),
+            Arg(i) => w(&format!("ARG {}", i)),
+            In(port) => w(&format!("IN {}", port)),
+            Out(port) => w(&format!("OUT {}", port)),
+            Syscall(n) => w(&format!("SYSCALL {}", n)),
+            CallHost(i) => w(&format!("CALLHOST #{}", i)),
+            Assert => w("ASSERT"),
+            AssertEq => w("ASSERTEQ"),
+            Breakpoint => w("BREAKPOINT"),
         }
     }
 }
@@ -100,14 +403,44 @@ mod test {
             Op::Mul,
             Op::Div,
             Op::Mod,
+            Op::Shl,
             Op::PushValue(Value::from(42)),
             Op::PushRegister(Register::A),
             Op::PopRegister(Register::C),
+            Op::MovRegister(Register::A, Register::D),
+            Op::MovValue(Register::B, Value(-7)),
+            Op::Inc(Register::C),
+            Op::Dec(Register::D),
+            Op::AddValue(Register::A, Value(3)),
+            Op::PushFloat(Float(-0.5)),
+            Op::FAdd,
+            Op::FSub,
+            Op::FMul,
+            Op::FDiv,
+            Op::FSqrt,
+            Op::IToF,
+            Op::FToI,
+            Op::FOutput,
+            Op::Enter(3),
+            Op::Leave,
+            Op::LocalGet(0),
+            Op::LocalSet(2),
+            Op::Arg(255),
+            Op::In(2),
+            Op::Out(3),
+            Op::Syscall(1),
+            Op::Assert,
+            Op::AssertEq,
+            Op::Breakpoint,
         ];
 
         for op in ops.iter() {
             let restored_op: Op = op.to_string().parse().unwrap();
             assert_eq!(*op, restored_op);
+
+            let mnemonic = op.to_string();
+            let mnemonic = mnemonic.split_whitespace().next().unwrap();
+            assert!(MNEMONICS.contains(&mnemonic), "{}", mnemonic);
         }
     }
 
@@ -159,11 +492,27 @@ mod test {
 
     #[test]
     fn failure_wrong_argument() {
+        assert_eq!(
+            Op::from_str("PUSH 1hello").unwrap_err(),
+            OpParseError::WrongArguments {
+                op: "PUSH",
+                errors: vec![(0, ArgumentParseError::WrongRegisterOrValue("1HELLO".into()))],
+            }
+        );
+
         assert_eq!(
             Op::from_str("PUSH hello").unwrap_err(),
             OpParseError::WrongArguments {
                 op: "PUSH",
-                errors: vec![(0, ArgumentParseError::WrongRegisterOrValue("HELLO".into()))],
+                errors: vec![(0, ArgumentParseError::UndefinedSymbol("hello".into()))],
+            }
+        );
+
+        assert_eq!(
+            Op::from_str("PUSHF A").unwrap_err(),
+            OpParseError::WrongArguments {
+                op: "PUSHF",
+                errors: vec![(0, ArgumentParseError::WrongFloat("A".into()))],
             }
         );
 
@@ -174,5 +523,146 @@ mod test {
                 errors: vec![(0, ArgumentParseError::WrongRegister("X".into()))],
             }
         );
+
+        assert_eq!(
+            Op::from_str("PUSH 0x1_0000_0000").unwrap_err(),
+            OpParseError::WrongArguments {
+                op: "PUSH",
+                errors: vec![(
+                    0,
+                    ArgumentParseError::ValueOutOfRange("0x1_0000_0000".into())
+                )],
+            }
+        );
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(Op::from_str("push 0x2A"), Ok(Op::PushValue(Value(42))));
+        assert_eq!(Op::from_str("PUSH 'a'"), Ok(Op::PushValue(Value(97))));
+        assert_eq!(Op::from_str("PUSH ' '"), Ok(Op::PushValue(Value(32))));
+        assert_eq!(
+            Op::from_str("  PUSH   '\\n'  "),
+            Ok(Op::PushValue(Value(10)))
+        );
+    }
+
+    #[test]
+    fn expressions() {
+        let mut symbols = Symbols::new();
+        symbols.insert("SIZE".into(), Value(10));
+
+        assert_eq!(
+            Op::parse("PUSH SIZE*4+1", &symbols),
+            Ok(Op::PushValue(Value(41)))
+        );
+        assert_eq!(
+            Op::parse("PUSH 'a' - 1", &symbols),
+            Ok(Op::PushValue(Value(96)))
+        );
+        assert_eq!(
+            Op::parse("PUSH ( SIZE )", &symbols),
+            Ok(Op::PushValue(Value(10)))
+        );
+        assert_eq!(
+            Op::parse("PUSH b", &symbols),
+            Ok(Op::PushRegister(Register::B))
+        );
+        assert_eq!(
+            Op::parse("PUSH SIZE * 0x7FFF_FFFF", &symbols),
+            Err(OpParseError::WrongArguments {
+                op: "PUSH",
+                errors: vec![(
+                    0,
+                    ArgumentParseError::Overflow("(SIZE * 2147483647)".into())
+                )],
+            })
+        );
+    }
+
+    #[test]
+    fn register_operands() {
+        let mut symbols = Symbols::new();
+        symbols.insert("STEP".into(), Value(2));
+        let parse = |s| Op::parse(s, &symbols);
+
+        assert_eq!(
+            parse("mov a,b"),
+            Ok(Op::MovRegister(Register::A, Register::B))
+        );
+        assert_eq!(
+            parse("MOV C, ','"),
+            Ok(Op::MovValue(Register::C, Value(44)))
+        );
+        assert_eq!(
+            parse("ADD D , STEP * 2"),
+            Ok(Op::AddValue(Register::D, Value(4)))
+        );
+        assert_eq!(parse("INC B"), Ok(Op::Inc(Register::B)));
+        assert_eq!(parse("ADD"), Ok(Op::Add));
+
+        assert_eq!(
+            parse("MOV A B"),
+            Err(WrongOp {
+                op: "MOV".into(),
+                num_args: 1,
+            })
+        );
+        assert_eq!(
+            parse("INC A, 1"),
+            Err(WrongOp {
+                op: "INC".into(),
+                num_args: 2,
+            })
+        );
+        assert_eq!(
+            parse("MOV E, x"),
+            Err(WrongArguments {
+                op: "MOV",
+                errors: vec![
+                    (0, ArgumentParseError::WrongRegister("E".into())),
+                    (1, ArgumentParseError::UndefinedSymbol("x".into())),
+                ],
+            })
+        );
+        assert_eq!(
+            parse("ADD A, B"),
+            Err(WrongArguments {
+                op: "ADD",
+                errors: vec![(1, ArgumentParseError::WrongValue("B".into()))],
+            })
+        );
+    }
+
+    #[test]
+    fn frame_operands() {
+        let mut symbols = Symbols::new();
+        symbols.insert("LOCALS".into(), Value(4));
+        let parse = |s| Op::parse(s, &symbols);
+
+        assert_eq!(parse("enter LOCALS + 1"), Ok(Op::Enter(5)));
+        assert_eq!(parse("local.get 1"), Ok(Op::LocalGet(1)));
+        assert_eq!(parse("LOCAL.SET LOCALS - 1"), Ok(Op::LocalSet(3)));
+        assert_eq!(
+            parse("ARG 256"),
+            Err(WrongArguments {
+                op: "ARG",
+                errors: vec![(0, ArgumentParseError::WrongIndex("256".into()))],
+            })
+        );
+        assert_eq!(
+            parse("ENTER -1"),
+            Err(WrongArguments {
+                op: "ENTER",
+                errors: vec![(0, ArgumentParseError::WrongIndex("-1".into()))],
+            })
+        );
+        assert_eq!(
+            parse("LEAVE 1"),
+            Err(WrongOp {
+                op: "LEAVE".into(),
+                num_args: 1,
+            })
+        );
     }
 }
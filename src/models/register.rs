@@ -1,40 +1,85 @@
 use super::ArgumentParseError;
 use std::{fmt, str};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum Register {
-    A = 0,
-    B = 1,
-    C = 2,
-    D = 3,
+/// General-purpose register, `R0`–`R15`.
+///
+/// `A`, `B`, `C` and `D` are aliases of the first four registers. Programs have
+/// `DEFAULT_COUNT` registers unless they declare another number with `.registers`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Register(u8);
+
+impl Register {
+    pub const A: Register = Register(0);
+    pub const B: Register = Register(1);
+    pub const C: Register = Register(2);
+    pub const D: Register = Register(3);
+
+    /// Maximum number of registers a program can declare.
+    pub const MAX_COUNT: u8 = 16;
+
+    /// Number of registers of programs, which don't declare it.
+    pub const DEFAULT_COUNT: u8 = 4;
+
+    /// Returns register with given index, `None` if it's not less than `MAX_COUNT`.
+    pub fn new(index: u8) -> Option<Register> {
+        Some(Register(index)).filter(|_| index < Register::MAX_COUNT)
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
 }
 
 impl str::FromStr for Register {
     type Err = ArgumentParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        use Register::*;
-
-        match s.trim().to_ascii_uppercase().as_str() {
-            "A" => Ok(A),
-            "B" => Ok(B),
-            "C" => Ok(C),
-            "D" => Ok(D),
-            x => Err(ArgumentParseError::WrongRegister(x.to_owned())),
-        }
+        let s = s.trim().to_ascii_uppercase();
+        let index = match s.as_str() {
+            "A" => Some(0),
+            "B" => Some(1),
+            "C" => Some(2),
+            "D" => Some(3),
+            // Leading zeros and signs are not allowed, so that each register has one name
+            x => x
+                .strip_prefix('R')
+                .filter(|x| x == &"0" || !x.starts_with('0'))
+                .filter(|x| x.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|x| x.parse().ok()),
+        };
+        index
+            .and_then(Register::new)
+            .ok_or(ArgumentParseError::WrongRegister(s))
     }
 }
 
 impl fmt::Display for Register {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use Register::*;
+        match self.0 {
+            0 => write!(f, "A"),
+            1 => write!(f, "B"),
+            2 => write!(f, "C"),
+            3 => write!(f, "D"),
+            x => write!(f, "R{}", x),
+        }
+    }
+}
 
-        let result = match self {
-            A => "A",
-            B => "B",
-            C => "C",
-            D => "D",
-        };
-        write!(f, "{}", result)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!("r0".parse(), Ok(Register::A));
+        assert_eq!("R3".parse(), Ok(Register::D));
+        assert_eq!("R15".parse::<Register>().unwrap().to_string(), "R15");
+        assert_eq!(Register::new(4).unwrap().to_string(), "R4");
+        for name in ["R16", "R01", "R+1", "R", "E"].iter() {
+            assert_eq!(
+                name.parse::<Register>(),
+                Err(ArgumentParseError::WrongRegister(name.to_string()))
+            );
+        }
     }
 }
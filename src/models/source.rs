@@ -0,0 +1,372 @@
+//! Reading of the assembly source and its `.include` directives.
+//!
+//! Comments start with `;` and last until the end of the line, they are removed here.
+//!
+//! `.include "path"` is replaced with the lines of the included file. Relative paths
+//! are resolved against the directory of the including file, or against the current
+//! directory for the source which was not read from a file.
+
+use std::{
+    fmt, io,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{IncludeError, LineWithError, MacroCall, StatementParseError};
+
+/// Location of a statement in the assembly source.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Location {
+    /// File which contains the statement, `None` for the source not read from a file.
+    pub file: Option<PathBuf>,
+
+    /// Line of the statement, for macro expansions it points into the macro body.
+    ///
+    /// Starts from 0.
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file.display(), self.line + 1),
+            None => write!(f, "line {}", self.line + 1),
+        }
+    }
+}
+
+/// Line of the assembly source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SourceLine {
+    /// Text of the line, macro parameters are already substituted.
+    pub text: String,
+
+    /// File which contains the line, `None` for the source not read from a file.
+    pub file: Option<PathBuf>,
+
+    /// Line number in the file, for macro expansions it points into the macro body.
+    pub line: usize,
+
+    /// Macro invocations which produced this line, outermost first.
+    ///
+    /// Shared by all lines of the same expansion.
+    pub expansion: Rc<[MacroCall]>,
+}
+
+impl SourceLine {
+    pub fn new(text: &str, file: Option<&Path>, line: usize) -> Self {
+        SourceLine {
+            text: text.to_owned(),
+            file: file.map(Path::to_path_buf),
+            line,
+            expansion: Rc::new([]),
+        }
+    }
+
+    pub fn location(&self) -> Location {
+        Location {
+            file: self.file.clone(),
+            line: self.line,
+        }
+    }
+
+    /// Returns an error located at this line.
+    pub fn error(&self, error: impl Into<StatementParseError>) -> LineWithError {
+        LineWithError {
+            file: self.file.clone(),
+            line: self.line,
+            error: error.into(),
+            expansion: self.expansion.to_vec(),
+        }
+    }
+}
+
+struct Includer<'a> {
+    read: &'a mut dyn FnMut(&Path) -> io::Result<String>,
+
+    /// Files which are being included, used to detect cycles.
+    stack: Vec<PathBuf>,
+
+    output: Vec<Result<SourceLine, LineWithError>>,
+}
+
+/// Splits source into lines, replacing `.include` directives with the included files.
+///
+/// Files are read with `read`, `path` is the path of the `source` itself, if any.
+pub(crate) fn read_source(
+    source: &str,
+    path: Option<&Path>,
+    read: &mut dyn FnMut(&Path) -> io::Result<String>,
+) -> Vec<Result<SourceLine, LineWithError>> {
+    let mut includer = Includer {
+        read,
+        stack: path.map(normalize).into_iter().collect(),
+        output: Vec::new(),
+    };
+    includer.include(source, path);
+    includer.output
+}
+
+impl Includer<'_> {
+    fn include(&mut self, source: &str, path: Option<&Path>) {
+        for (line, text) in source.lines().enumerate() {
+            let text = strip_comment(text);
+            let line = SourceLine::new(text, path, line);
+            match text.split_whitespace().next() {
+                Some(word) if word.eq_ignore_ascii_case(".include") => {
+                    let argument = text.trim_start()[word.len()..].trim();
+                    if let Err(error) = self.include_file(argument, path) {
+                        self.output.push(Err(line.error(error)));
+                    }
+                }
+                _ => self.output.push(Ok(line)),
+            }
+        }
+    }
+
+    fn include_file(
+        &mut self,
+        argument: &str,
+        including: Option<&Path>,
+    ) -> Result<(), IncludeError> {
+        let relative = argument
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| IncludeError::WrongPath(argument.to_owned()))?;
+
+        let directory = including
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new(""));
+        let path = normalize(&directory.join(relative));
+        if self.stack.contains(&path) {
+            return Err(IncludeError::Cycle(path));
+        }
+
+        let source = (self.read)(&path).map_err(|error| IncludeError::Read {
+            path: path.clone(),
+            message: error.to_string(),
+        })?;
+
+        self.stack.push(path.clone());
+        self.include(&source, Some(&path));
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+/// Removes the comment from the line.
+pub(crate) fn strip_comment(text: &str) -> &str {
+    split_comment(text).0
+}
+
+/// Splits the line into the code and the text of the comment after `;`, if any.
+///
+/// `;` inside of quotes doesn't start a comment.
+pub(crate) fn split_comment(text: &str) -> (&str, Option<&str>) {
+    match outside_quotes(text).find(|&(_, c)| c == ';') {
+        Some((idx, _)) => (&text[..idx], Some(&text[idx + 1..])),
+        None => (text, None),
+    }
+}
+
+/// Splits the text at separators, which are not inside of quotes.
+pub(crate) fn split_outside_quotes(text: &str, is_separator: impl Fn(char) -> bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (idx, c) in outside_quotes(text).filter(|&(_, c)| is_separator(c)) {
+        parts.push(&text[start..idx]);
+        start = idx + c.len_utf8();
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Splits operands of an operation or arguments of a macro at commas, which are not
+/// inside of quotes, and trims them.
+pub(crate) fn split_operands(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    split_outside_quotes(text, |c| c == ',')
+        .into_iter()
+        .map(str::trim)
+        .collect()
+}
+
+/// Returns characters, which are not inside of quotes, with their offsets.
+///
+/// Character literals are in `'` and paths are in `"`, `\\` escapes the next character
+/// inside of both. Quotes themselves are not returned.
+fn outside_quotes(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut quote = None;
+    let mut escaped = false;
+    text.char_indices().filter(move |&(_, c)| {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None => return true,
+        }
+        false
+    })
+}
+
+/// Removes `.` and `..` components from the path without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match result.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    result.pop();
+                }
+                _ => result.push(".."),
+            },
+            component => result.push(component),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn read_files(main: &str, files: &[(&str, &str)]) -> Vec<Result<SourceLine, LineWithError>> {
+        let files: HashMap<PathBuf, String> = files
+            .iter()
+            .map(|(path, text)| (PathBuf::from(path), text.to_string()))
+            .collect();
+        let mut read = |path: &Path| match files.get(path) {
+            Some(text) => Ok(text.clone()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+        };
+        read_source(main, Some(Path::new("src/main.sasm")), &mut read)
+    }
+
+    fn location(line: &Result<SourceLine, LineWithError>) -> (String, usize) {
+        let (file, line) = match line {
+            Ok(line) => (&line.file, line.line),
+            Err(error) => (&error.file, error.line),
+        };
+        (file.as_ref().unwrap().display().to_string(), line)
+    }
+
+    #[test]
+    fn nested_includes() {
+        let lines = read_files(
+            "PUSH 1\n.include \"lib/math.sasm\"\nOUTPUT",
+            &[
+                ("src/lib/math.sasm", ".include \"../common.sasm\"\nADD"),
+                ("src/common.sasm", "PUSH 2"),
+            ],
+        );
+        let texts: Vec<_> = lines
+            .iter()
+            .map(|line| line.as_ref().unwrap().text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["PUSH 1", "PUSH 2", "ADD", "OUTPUT"]);
+
+        let locations: Vec<_> = lines.iter().map(location).collect();
+        assert_eq!(
+            locations,
+            vec![
+                ("src/main.sasm".into(), 0),
+                ("src/common.sasm".into(), 0),
+                ("src/lib/math.sasm".into(), 1),
+                ("src/main.sasm".into(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let lines = read_files(
+            "; program\nPUSH ';' ; char\n.include \"a;b.sasm\"\nPUSH '\\''; quote",
+            &[("src/a;b.sasm", "ADD;")],
+        );
+        let texts: Vec<_> = lines
+            .iter()
+            .map(|line| line.as_ref().unwrap().text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["", "PUSH ';' ", "ADD", "PUSH '\\''"]);
+    }
+
+    #[test]
+    fn splitting() {
+        assert_eq!(split_operands(" "), Vec::<&str>::new());
+        assert_eq!(split_operands("A ,  1"), vec!["A", "1"]);
+        assert_eq!(split_operands("',', \"a,b\""), vec!["','", "\"a,b\""]);
+        assert_eq!(split_operands("'\\'', B,"), vec!["'\\''", "B", ""]);
+        assert_eq!(
+            split_outside_quotes("PUSH  ' '", char::is_whitespace),
+            vec!["PUSH", "", "' '"]
+        );
+        assert_eq!(
+            split_comment("PUSH '\\'' ; ';'"),
+            ("PUSH '\\'' ", Some(" ';'"))
+        );
+    }
+
+    #[test]
+    fn include_errors() {
+        let lines = read_files(
+            ".include lib.sasm\n.include \"missing.sasm\"\n.include \"a.sasm\"",
+            &[
+                ("src/a.sasm", "PUSH 1\n.include \"b.sasm\""),
+                (
+                    "src/b.sasm",
+                    ".include \"./a.sasm\"\n.include \"main.sasm\"",
+                ),
+            ],
+        );
+
+        let errors: Vec<_> = lines
+            .iter()
+            .map(|line| (location(line), line.as_ref().err().map(|e| e.error.clone())))
+            .collect();
+
+        let include_error = |error| Some(StatementParseError::IncludeError(error));
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    ("src/main.sasm".into(), 0),
+                    include_error(IncludeError::WrongPath("lib.sasm".into()))
+                ),
+                (
+                    ("src/main.sasm".into(), 1),
+                    include_error(IncludeError::Read {
+                        path: "src/missing.sasm".into(),
+                        message: "not found".into(),
+                    })
+                ),
+                (("src/a.sasm".into(), 0), None),
+                (
+                    ("src/b.sasm".into(), 0),
+                    include_error(IncludeError::Cycle("src/a.sasm".into()))
+                ),
+                (
+                    ("src/b.sasm".into(), 1),
+                    include_error(IncludeError::Cycle("src/main.sasm".into()))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(normalize(Path::new("a/./b/../c")), PathBuf::from("a/c"));
+        assert_eq!(
+            normalize(Path::new("../a/../../b")),
+            PathBuf::from("../../b")
+        );
+        assert_eq!(normalize(Path::new("/a/b/../../c")), PathBuf::from("/c"));
+    }
+}
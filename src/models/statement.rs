@@ -1,17 +1,208 @@
 use std::{fmt::Display, str::FromStr};
 
-use super::{Op, StatementParseError};
+use super::{
+    is_identifier, ArgumentParseError, Expr, Op, OpParseError, Register, StatementParseError,
+    Symbols, Value, Width,
+};
 
+/// Directives of the assembler, including the ones handled before statements are parsed.
+pub const DIRECTIVES: &[&str] = &[
+    ".equ",
+    ".const",
+    ".width",
+    ".registers",
+    ".extern",
+    ".global",
+    ".include",
+    ".macro",
+    ".endm",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Statement {
     Op(Op),
+
+    /// Named constant, defined with `.equ NAME value` or `.const NAME value`.
+    Constant {
+        name: String,
+        value: Value,
+    },
+
+    /// Declaration of a symbol from another object file: `.extern NAME`.
+    Extern(String),
+
+    /// Constant exported to other object files: `.global NAME`.
+    Global(String),
+
+    /// `PUSH` of an expression with external symbol, which is resolved by the linker.
+    PushExtern {
+        symbol: String,
+        addend: Value,
+    },
+
+    /// Call of the host function by name: `CALLHOST name`.
+    ///
+    /// Names are resolved when the program is loaded into the machine.
+    CallHost(String),
+
+    /// Width of the machine word: `.width 32` or `.width 64`.
+    ///
+    /// Must be the first statement, programs without it use 32-bit words.
+    Width(Width),
+
+    /// Number of general-purpose registers: `.registers 16`.
+    ///
+    /// Must precede operations, programs without it have `Register::DEFAULT_COUNT` registers.
+    Registers(u8),
+}
+
+impl Statement {
+    /// Parses statement, using `symbols` to evaluate constant expressions.
+    ///
+    /// Constant definitions are not added to `symbols`, that's up to the caller.
+    pub fn parse(s: &str, symbols: &Symbols) -> Result<Self, StatementParseError> {
+        let s = s.trim();
+        if s.starts_with('.') {
+            parse_directive(s, symbols)
+        } else if let Some(statement) = parse_push_extern(s, symbols) {
+            statement
+        } else if let Some(statement) = parse_call_host(s) {
+            statement
+        } else {
+            Ok(Statement::Op(Op::parse(s, symbols)?))
+        }
+    }
+}
+
+/// Parses `PUSH` with an expression, which refers to external symbols.
+///
+/// Returns `None` for other statements.
+fn parse_push_extern(s: &str, symbols: &Symbols) -> Option<Result<Statement, StatementParseError>> {
+    let mut words = s.splitn(2, char::is_whitespace);
+    let op = words.next().unwrap_or_default();
+    let expr = Expr::parse(words.next()?, symbols.width()).ok()?;
+    if !op.eq_ignore_ascii_case("PUSH") {
+        return None;
+    }
+
+    match expr.evaluate_relocatable(symbols) {
+        Ok((Some(symbol), addend)) => Some(Ok(Statement::PushExtern { symbol, addend })),
+        Err(error @ ArgumentParseError::NotRelocatable(_)) => {
+            Some(Err(OpParseError::WrongArguments {
+                op: "PUSH",
+                errors: vec![(0, error)],
+            }
+            .into()))
+        }
+        _ => None,
+    }
+}
+
+/// Parses `CALLHOST name`, returns `None` for other statements.
+fn parse_call_host(s: &str) -> Option<Result<Statement, StatementParseError>> {
+    let words: Vec<_> = s.split_whitespace().collect();
+    match words[..] {
+        [op, name] if op.eq_ignore_ascii_case("CALLHOST") => Some(if is_identifier(name) {
+            Ok(Statement::CallHost(name.to_owned()))
+        } else {
+            Err(OpParseError::WrongArguments {
+                op: "CALLHOST",
+                errors: vec![(0, ArgumentParseError::WrongHostFunction(name.to_owned()))],
+            }
+            .into())
+        }),
+        _ => None,
+    }
+}
+
+fn parse_directive(s: &str, symbols: &Symbols) -> Result<Statement, StatementParseError> {
+    let mut words = s.splitn(2, char::is_whitespace);
+    let directive = words.next().unwrap_or_default();
+    let args = words.next().unwrap_or_default().trim();
+
+    let directive = match directive.to_ascii_lowercase().as_str() {
+        ".equ" => ".equ",
+        ".const" => ".const",
+        ".width" => return Ok(Statement::Width(args.parse()?)),
+        ".registers" => return parse_register_count(args),
+        ".extern" => return parse_symbol_declaration(".extern", args, symbols),
+        ".global" => return parse_symbol_declaration(".global", args, symbols),
+        _ => return Err(StatementParseError::UnknownDirective(directive.to_owned())),
+    };
+
+    let mut args = args.splitn(2, char::is_whitespace);
+    let (name, value) = match (args.next(), args.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => (name, value),
+        (name, _) => {
+            let num_args = name.filter(|name| !name.is_empty()).map_or(0, |_| 1);
+            return Err(StatementParseError::WrongConstant {
+                directive,
+                num_args,
+            });
+        }
+    };
+
+    if !is_identifier(name) || name.parse::<Register>().is_ok() {
+        return Err(StatementParseError::WrongConstantName(name.to_owned()));
+    }
+    if symbols.contains(name) {
+        return Err(StatementParseError::DuplicateConstant(name.to_owned()));
+    }
+
+    let wrong_value = |error| StatementParseError::WrongConstantValue {
+        name: name.to_owned(),
+        error,
+    };
+    let value = Expr::parse(value, symbols.width()).map_err(wrong_value)?;
+    let value = value.evaluate(symbols).map_err(wrong_value)?;
+
+    Ok(Statement::Constant {
+        name: name.to_owned(),
+        value,
+    })
+}
+
+fn parse_register_count(args: &str) -> Result<Statement, StatementParseError> {
+    match args.parse() {
+        Ok(count) if 0 < count && count <= Register::MAX_COUNT => Ok(Statement::Registers(count)),
+        _ => Err(StatementParseError::WrongRegisterCount(args.to_owned())),
+    }
+}
+
+fn parse_symbol_declaration(
+    directive: &'static str,
+    args: &str,
+    symbols: &Symbols,
+) -> Result<Statement, StatementParseError> {
+    let name = match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [name] => name,
+        ref args => {
+            return Err(StatementParseError::WrongSymbol {
+                directive,
+                num_args: args.len(),
+            })
+        }
+    };
+
+    if !is_identifier(name) || name.parse::<Register>().is_ok() {
+        return Err(StatementParseError::WrongConstantName(name.to_owned()));
+    }
+
+    if directive == ".extern" {
+        if symbols.contains(name) {
+            return Err(StatementParseError::DuplicateConstant(name.to_owned()));
+        }
+        Ok(Statement::Extern(name.to_owned()))
+    } else {
+        Ok(Statement::Global(name.to_owned()))
+    }
 }
 
 impl FromStr for Statement {
     type Err = StatementParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let op = s.parse()?;
-        Ok(Statement::Op(op))
+        Statement::parse(s, &Symbols::new())
     }
 }
 
@@ -21,6 +212,17 @@ impl Display for Statement {
 
         match self {
             Op(op) => write!(f, "{}", op),
+            Constant { name, value } => write!(f, ".equ {} {}", name, value),
+            Extern(name) => write!(f, ".extern {}", name),
+            Global(name) => write!(f, ".global {}", name),
+            CallHost(name) => write!(f, "CALLHOST {}", name),
+            Width(width) => write!(f, ".width {}", width),
+            Registers(count) => write!(f, ".registers {}", count),
+            PushExtern { symbol, addend } => match addend.value() {
+                0 => write!(f, "PUSH {}", symbol),
+                x if x < 0 => write!(f, "PUSH {} - {}", symbol, x.unsigned_abs()),
+                x => write!(f, "PUSH {} + {}", symbol, x),
+            },
         }
     }
 }
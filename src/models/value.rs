@@ -1,36 +1,267 @@
-use std::{fmt::Display, str::FromStr};
+use std::{convert::TryFrom, fmt::Display, str::FromStr};
 
-use super::ArgumentParseError;
+use super::{ArgumentParseError, Width};
 
+/// Machine word, its range is limited by the `Width` of the program.
 #[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
-pub struct Value(pub i32);
+pub struct Value(pub i64);
 
 impl From<i32> for Value {
     fn from(x: i32) -> Self {
+        Value(x.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(x: i64) -> Self {
         Value(x)
     }
 }
 
 impl Value {
-    pub fn value(self) -> i32 {
+    pub fn value(self) -> i64 {
         self.0
     }
+
+    /// Parses integer or character literal, which must fit into the word of given width.
+    pub fn parse(s: &str, width: Width) -> Result<Self, ArgumentParseError> {
+        let s = s.trim();
+        if s.starts_with('\'') {
+            parse_char(s).map(Value)
+        } else {
+            parse_integer(s, width).map(Value)
+        }
+    }
 }
 
+/// Parses integer and character literals of 32-bit words.
+///
+/// Integers may have a sign, a `0x`, `0b` or `0o` radix prefix and underscores
+/// between digits: `-42`, `0x1F`, `0b1010`, `0o17`, `1_000_000`.
+/// Character literals are written in single quotes: `'A'`, `'\n'`.
 impl FromStr for Value {
     type Err = ArgumentParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Ok(number) = s.parse() {
-            Ok(Value(number))
-        } else {
-            Err(ArgumentParseError::WrongValue(s.to_owned()))
-        }
+        Value::parse(s, Width::W32)
     }
 }
 
+fn parse_integer(s: &str, width: Width) -> Result<i64, ArgumentParseError> {
+    let wrong_value = || ArgumentParseError::WrongValue(s.to_owned());
+
+    let (negative, unsigned) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+
+    let lowercase = unsigned.to_ascii_lowercase();
+    let (radix, digits) = match lowercase.get(..2) {
+        Some("0x") => (16, &unsigned[2..]),
+        Some("0b") => (2, &unsigned[2..]),
+        Some("0o") => (8, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+
+    // Underscores are allowed anywhere between the digits, but the number itself
+    // must start with a digit, so that `_1` is not mistaken for a value.
+    if unsigned.starts_with('_') || !digits.chars().any(|c| c.is_digit(radix)) {
+        return Err(wrong_value());
+    }
+    if !digits.chars().all(|c| c == '_' || c.is_digit(radix)) {
+        return Err(wrong_value());
+    }
+
+    let digits: String = digits.chars().filter(|&c| c != '_').collect();
+    let out_of_range = || ArgumentParseError::ValueOutOfRange(s.to_owned());
+    let magnitude = i128::from_str_radix(&digits, radix).map_err(|_| out_of_range())?;
+    let number = if negative { -magnitude } else { magnitude };
+    i64::try_from(number)
+        .ok()
+        .filter(|&number| width.contains(number))
+        .ok_or_else(out_of_range)
+}
+
+fn parse_char(s: &str) -> Result<i64, ArgumentParseError> {
+    let wrong_value = || ArgumentParseError::WrongValue(s.to_owned());
+
+    let inner = s
+        .strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .ok_or_else(wrong_value)?;
+
+    let mut chars = inner.chars();
+    let c = match (chars.next(), chars.next(), chars.next()) {
+        (Some('\\'), Some(escaped), None) => match escaped {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '\'' => '\'',
+            '"' => '"',
+            _ => return Err(wrong_value()),
+        },
+        (Some('\''), _, _) | (Some('\\'), _, _) => return Err(wrong_value()),
+        (Some(c), None, None) => c,
+        _ => return Err(wrong_value()),
+    };
+    Ok(c as i64)
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
+
+/// Floating-point value, pushed with `PUSHF`.
+///
+/// Values are compared bitwise, so that `NaN` is equal to itself and operations can be compared.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Float {}
+
+impl From<f64> for Float {
+    fn from(x: f64) -> Self {
+        Float(x)
+    }
+}
+
+impl Float {
+    pub fn value(self) -> f64 {
+        self.0
+    }
+}
+
+/// Parses decimal floating-point literals: `3.14`, `-2`, `1e-3`, `inf`, `nan`.
+impl FromStr for Float {
+    type Err = ArgumentParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.parse()
+            .map(Float)
+            .map_err(|_| ArgumentParseError::WrongFloat(s.to_owned()))
+    }
+}
+
+/// Formats the value so that it's parsed back exactly, integers keep the fraction: `2.0`.
+impl Display for Float {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> Result<i64, ArgumentParseError> {
+        s.parse::<Value>().map(Value::value)
+    }
+
+    #[test]
+    fn decimal() {
+        assert_eq!(parse("42"), Ok(42));
+        assert_eq!(parse("-42"), Ok(-42));
+        assert_eq!(parse("+7"), Ok(7));
+        assert_eq!(parse("1_000_000"), Ok(1_000_000));
+    }
+
+    #[test]
+    fn other_radixes() {
+        assert_eq!(parse("0x1F"), Ok(0x1F));
+        assert_eq!(parse("0X1f"), Ok(0x1F));
+        assert_eq!(parse("-0x10"), Ok(-16));
+        assert_eq!(parse("0b1010"), Ok(10));
+        assert_eq!(parse("0b_1111_0000"), Ok(0xF0));
+        assert_eq!(parse("0o17"), Ok(15));
+    }
+
+    #[test]
+    fn characters() {
+        assert_eq!(parse("'A'"), Ok(65));
+        assert_eq!(parse("' '"), Ok(32));
+        assert_eq!(parse("'\\n'"), Ok(10));
+        assert_eq!(parse("'\\''"), Ok(39));
+        assert_eq!(parse("'\\\\'"), Ok(92));
+    }
+
+    #[test]
+    fn bounds() {
+        assert_eq!(parse("2147483647"), Ok(i32::MAX.into()));
+        assert_eq!(parse("-2147483648"), Ok(i32::MIN.into()));
+        assert_eq!(parse("0x7FFF_FFFF"), Ok(i32::MAX.into()));
+    }
+
+    #[test]
+    fn wide_values() {
+        let parse = |s| Value::parse(s, Width::W64).map(Value::value);
+        assert_eq!(parse("2147483648"), Ok(1 << 31));
+        assert_eq!(parse("0x7FFF_FFFF_FFFF_FFFF"), Ok(i64::MAX));
+        assert_eq!(parse("-9223372036854775808"), Ok(i64::MIN));
+        assert_eq!(
+            parse("9223372036854775808"),
+            Err(ArgumentParseError::ValueOutOfRange(
+                "9223372036854775808".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        for s in [
+            "2147483648",
+            "-2147483649",
+            "0x1_0000_0000",
+            "99999999999999999999999",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                parse(s),
+                Err(ArgumentParseError::ValueOutOfRange(s.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_values() {
+        for s in [
+            "", "-", "0x", "_1", "12a", "0b102", "0o8", "A", "''", "'AB'", "'\\q'", "'A",
+        ]
+        .iter()
+        {
+            assert_eq!(parse(s), Err(ArgumentParseError::WrongValue(s.to_string())));
+        }
+    }
+
+    #[test]
+    fn floats() {
+        let parse = |s: &str| s.parse::<Float>().map(Float::value);
+        assert_eq!(parse("2.75"), Ok(2.75));
+        assert_eq!(parse("-2"), Ok(-2.0));
+        assert_eq!(parse("1e-3"), Ok(0.001));
+        assert_eq!(parse("inf"), Ok(f64::INFINITY));
+        assert!(parse("nan").unwrap().is_nan());
+        assert_eq!(
+            parse("1.5x"),
+            Err(ArgumentParseError::WrongFloat("1.5x".into()))
+        );
+
+        for x in [0.1, 2.0, -1e300, f64::NAN, f64::NEG_INFINITY].iter() {
+            let float = Float(*x);
+            assert_eq!(float.to_string().parse(), Ok(float));
+        }
+        assert_eq!(Float(2.0).to_string(), "2.0");
+    }
+}
@@ -0,0 +1,86 @@
+use std::{fmt::Display, str::FromStr};
+
+use super::StatementParseError;
+
+/// Width of the machine word.
+///
+/// Values are stored as `i64` regardless of the width, the width limits their range.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Width {
+    #[default]
+    W32,
+    W64,
+}
+
+impl Width {
+    pub fn bits(self) -> u32 {
+        match self {
+            Width::W32 => 32,
+            Width::W64 => 64,
+        }
+    }
+
+    /// Number of bytes in the encoded value.
+    pub fn bytes(self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    pub fn min(self) -> i64 {
+        match self {
+            Width::W32 => i32::MIN.into(),
+            Width::W64 => i64::MIN,
+        }
+    }
+
+    pub fn max(self) -> i64 {
+        match self {
+            Width::W32 => i32::MAX.into(),
+            Width::W64 => i64::MAX,
+        }
+    }
+
+    /// Returns true if the value fits into the word.
+    pub fn contains(self, x: i64) -> bool {
+        self.min() <= x && x <= self.max()
+    }
+
+    /// Truncates the value to the word, wrapping it around like `as` casts do.
+    pub fn wrap(self, x: i64) -> i64 {
+        match self {
+            Width::W32 => (x as i32).into(),
+            Width::W64 => x,
+        }
+    }
+}
+
+impl FromStr for Width {
+    type Err = StatementParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "32" => Ok(Width::W32),
+            "64" => Ok(Width::W64),
+            s => Err(StatementParseError::WrongWidth(s.to_owned())),
+        }
+    }
+}
+
+impl Display for Width {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.bits())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wrap() {
+        assert_eq!(Width::W32.wrap(i64::from(i32::MAX) + 1), i32::MIN.into());
+        assert_eq!(Width::W32.wrap(-1), -1);
+        assert_eq!(Width::W64.wrap(i64::MAX), i64::MAX);
+        assert!(Width::W64.contains(i64::from(i32::MAX) + 1));
+        assert!(!Width::W32.contains(i64::from(i32::MAX) + 1));
+    }
+}
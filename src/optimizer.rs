@@ -0,0 +1,422 @@
+//! Peephole optimizer, which rewrites `Assembly` before it is compiled.
+//!
+//! Rewrites are applied until none of them matches, in a single pass over the statements:
+//!
+//! - constant folding: `PUSH 2; PUSH 2; ADD` becomes `PUSH 4`,
+//! - redundant moves: `PUSH A; POP A` is removed,
+//! - dead code: instructions after `HALT` are removed,
+//! - strength reduction: `PUSH 8; MUL` becomes `PUSH 3; SHL`, `PUSH 1; MUL` is removed.
+//!
+//! Only consecutive instructions are rewritten, directives between them prevent rewrites.
+//! Arithmetic which would overflow or divide by zero is left for the machine to execute.
+//! `PUSH 1; MUL` is removed only if the stack is known to have a value to multiply,
+//! otherwise the machine must still fail with the stack underflow.
+
+use std::fmt;
+
+use crate::models::{Assembly, Op, Statement, Value, Width};
+
+/// Kind of the applied rewrite.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RewriteKind {
+    ConstantFolding,
+    RedundantMove,
+    DeadCode,
+    StrengthReduction,
+}
+
+/// Description of a single rewrite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rewrite {
+    pub kind: RewriteKind,
+
+    /// Index of the first rewritten statement in the original assembly.
+    ///
+    /// Starts from 0.
+    pub statement: usize,
+
+    /// Rewritten instructions.
+    pub before: Vec<Op>,
+
+    /// Instructions which replaced them.
+    pub after: Vec<Op>,
+}
+
+/// Statement and its index in the original assembly.
+type Item = (usize, Statement);
+
+/// Optimizes assembly, returning the optimized assembly and the list of rewrites.
+pub fn optimize(assembly: &Assembly) -> (Assembly, Vec<Rewrite>) {
+    let mut optimizer = Optimizer {
+        width: assembly.width(),
+        items: Vec::with_capacity(assembly.statements().len()),
+        depths: vec![Some(0)],
+        rewrites: Vec::new(),
+        halted: false,
+        dead_code: None,
+    };
+    for item in assembly.statements().iter().cloned().enumerate() {
+        optimizer.push(item);
+    }
+
+    let Optimizer {
+        items,
+        mut rewrites,
+        dead_code,
+        ..
+    } = optimizer;
+    rewrites.extend(dead_code);
+    let statements = items.into_iter().map(|(_, statement)| statement).collect();
+    (Assembly::new(statements), rewrites)
+}
+
+/// Optimizes statements in a single pass: each statement is appended to the output and
+/// the rewrites are tried on the instructions at its end, so that every statement is
+/// visited a constant number of times.
+struct Optimizer {
+    width: Width,
+
+    /// Optimized statements.
+    items: Vec<Item>,
+
+    /// Number of values on the stack before each of the items and after the last one,
+    /// `None` where it's not known: after frame changes, host calls and a stack underflow.
+    depths: Vec<Option<usize>>,
+
+    rewrites: Vec<Rewrite>,
+
+    /// Whether `HALT` was appended, code after it is never executed.
+    halted: bool,
+
+    /// Removed instructions after `HALT`.
+    dead_code: Option<Rewrite>,
+}
+
+impl Optimizer {
+    fn push(&mut self, (idx, statement): Item) {
+        if self.halted && is_code(&statement) {
+            let rewrite = self.dead_code.get_or_insert_with(|| Rewrite {
+                kind: RewriteKind::DeadCode,
+                statement: idx,
+                before: Vec::new(),
+                after: Vec::new(),
+            });
+            // External pushes and host calls are not operations yet, but they are dead all the same
+            if let Statement::Op(op) = statement {
+                rewrite.before.push(op);
+            }
+            return;
+        }
+
+        self.halted |= statement == Statement::Op(Op::Halt);
+        self.append((idx, statement));
+        while self.rewrite_end() {}
+    }
+
+    fn append(&mut self, item: Item) {
+        let depth = *self.depths.last().unwrap();
+        let depth = match &item.1 {
+            Statement::Op(op) => depth.and_then(|depth| {
+                let (pops, pushes) = op.stack_effect()?;
+                Some(depth.checked_sub(pops)? + pushes)
+            }),
+            Statement::PushExtern { .. } => depth.map(|depth| depth + 1),
+            Statement::CallHost(_) => None,
+            _ => depth,
+        };
+        self.items.push(item);
+        self.depths.push(depth);
+    }
+
+    /// Applies a rewrite, which ends at the last item, returns false if none matches.
+    fn rewrite_end(&mut self) -> bool {
+        // Up to three instructions at the end, directives between them prevent rewrites
+        let mut window = [Op::Halt; 3];
+        let mut len = 0;
+        for (_, statement) in self.items.iter().rev().take(window.len()) {
+            match statement {
+                Statement::Op(op) => {
+                    len += 1;
+                    window[window.len() - len] = *op;
+                }
+                _ => break,
+            }
+        }
+        let window = &window[window.len() - len..];
+
+        for skip in 0..window.len() {
+            let start = self.items.len() - window.len() + skip;
+            let (kind, _, after) =
+                match match_window(&window[skip..], self.depths[start], self.width) {
+                    Some(rewrite) if rewrite.1 == window.len() - skip => rewrite,
+                    _ => continue,
+                };
+
+            let statement = self.items[start].0;
+            self.items.truncate(start);
+            self.depths.truncate(start + 1);
+            for &op in after.iter() {
+                self.append((statement, Statement::Op(op)));
+            }
+            self.rewrites.push(Rewrite {
+                kind,
+                statement,
+                before: window[skip..].to_vec(),
+                after,
+            });
+            return true;
+        }
+        false
+    }
+}
+
+fn is_code(statement: &Statement) -> bool {
+    match statement {
+        Statement::Op(_) | Statement::PushExtern { .. } | Statement::CallHost(_) => true,
+        Statement::Constant { .. }
+        | Statement::Extern(_)
+        | Statement::Global(_)
+        | Statement::Width(_)
+        | Statement::Registers(_) => false,
+    }
+}
+
+/// Matches the beginning of the window, which starts with `depth` values on the stack,
+/// returns kind of the rewrite, number of rewritten instructions and the replacement.
+fn match_window(
+    window: &[Op],
+    depth: Option<usize>,
+    width: Width,
+) -> Option<(RewriteKind, usize, Vec<Op>)> {
+    use Op::*;
+
+    match *window {
+        [PushValue(Value(a)), PushValue(Value(b)), op, ..] => {
+            let result = match op {
+                Add => a.checked_add(b),
+                Sub => a.checked_sub(b),
+                Mul => a.checked_mul(b),
+                Div if b != 0 => a.checked_div(b),
+                Mod if b != 0 => a.checked_rem(b),
+                Shl => Some(width.wrap(a.wrapping_shl(b as u32 % width.bits()))),
+                _ => None,
+            }
+            .filter(|&result| width.contains(result))?;
+            Some((
+                RewriteKind::ConstantFolding,
+                3,
+                vec![PushValue(Value(result))],
+            ))
+        }
+        [PushRegister(a), PopRegister(b), ..] if a == b => {
+            Some((RewriteKind::RedundantMove, 2, Vec::new()))
+        }
+        [PushValue(Value(1)), Mul, ..] if depth.is_some_and(|depth| depth > 0) => {
+            Some((RewriteKind::StrengthReduction, 2, Vec::new()))
+        }
+        [PushValue(Value(1)), Mul, ..] => None,
+        [PushValue(Value(x)), Mul, ..] if x > 0 && x.count_ones() == 1 => {
+            let shift = Value(x.trailing_zeros().into());
+            Some((
+                RewriteKind::StrengthReduction,
+                2,
+                vec![PushValue(shift), Shl],
+            ))
+        }
+        _ => None,
+    }
+}
+
+impl fmt::Display for RewriteKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            RewriteKind::ConstantFolding => "constant folding",
+            RewriteKind::RedundantMove => "redundant move",
+            RewriteKind::DeadCode => "dead code",
+            RewriteKind::StrengthReduction => "strength reduction",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |ops: &[Op]| {
+            let ops: Vec<String> = ops.iter().map(Op::to_string).collect();
+            if ops.is_empty() {
+                "nothing".to_owned()
+            } else {
+                ops.join("; ")
+            }
+        };
+        write!(
+            f,
+            "statement {}: {}: {} -> {}",
+            self.statement + 1,
+            self.kind,
+            join(&self.before),
+            join(&self.after)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        executor::{ExecutionError, Machine, MemoryDevice},
+        machine_code::Compile,
+    };
+
+    fn run(assembly: &Assembly) -> String {
+        let mut program = Vec::new();
+        assembly.compile(&mut program).unwrap();
+        let mut device = MemoryDevice::default();
+        Machine::new(&mut device).execute_program(&program).unwrap();
+        String::from_utf8(device.into_output()).unwrap()
+    }
+
+    /// Optimizes the program and checks that its output didn't change.
+    fn optimize_source(source: &str) -> (String, Vec<RewriteKind>) {
+        let assembly: Assembly = source.parse().unwrap();
+        let (optimized, rewrites) = optimize(&assembly);
+        assert_eq!(run(&assembly), run(&optimized));
+        let kinds = rewrites.iter().map(|rewrite| rewrite.kind).collect();
+        (optimized.to_string(), kinds)
+    }
+
+    #[test]
+    fn constant_folding() {
+        let (optimized, kinds) = optimize_source(
+            "
+            PUSH 2
+            PUSH 3
+            PUSH 4
+            MUL
+            ADD
+            PUSH A
+            PUSH 10
+            PUSH 3
+            MOD
+            SUB
+            OUTPUT
+            OUTPUT
+            ",
+        );
+        assert_eq!(optimized, "PUSH 14\nPUSH A\nPUSH 1\nSUB\nOUTPUT\nOUTPUT\n");
+        assert_eq!(kinds, vec![RewriteKind::ConstantFolding; 3]);
+    }
+
+    #[test]
+    fn no_folding_on_errors() {
+        for source in &[
+            "PUSH 2147483647\nPUSH 1\nADD\n",
+            "PUSH 10\nPUSH 0\nDIV\n",
+            ".width 64\nPUSH 9223372036854775807\nPUSH 1\nADD\n",
+        ] {
+            let assembly: Assembly = source.parse().unwrap();
+            let (optimized, rewrites) = optimize(&assembly);
+            assert_eq!(&optimized.to_string(), source);
+            assert!(rewrites.is_empty());
+        }
+    }
+
+    #[test]
+    fn redundant_moves_and_dead_code() {
+        let (optimized, kinds) = optimize_source(
+            "
+            PUSH 7
+            POP B
+            PUSH B
+            PUSH A
+            POP A
+            OUTPUT
+            HALT
+            .equ AFTER 1
+            PUSH B
+            OUTPUT
+            ",
+        );
+        assert_eq!(
+            optimized,
+            "PUSH 7\nPOP B\nPUSH B\nOUTPUT\nHALT\n.equ AFTER 1\n"
+        );
+        assert_eq!(
+            kinds,
+            vec![RewriteKind::RedundantMove, RewriteKind::DeadCode]
+        );
+    }
+
+    #[test]
+    fn strength_reduction() {
+        let (optimized, kinds) = optimize_source(
+            "
+            PUSH 5
+            POP A
+            PUSH A
+            PUSH 8
+            MUL
+            PUSH A
+            PUSH 1
+            MUL
+            ADD
+            OUTPUT
+            ",
+        );
+        assert_eq!(
+            optimized,
+            "PUSH 5\nPOP A\nPUSH A\nPUSH 3\nSHL\nPUSH A\nADD\nOUTPUT\n"
+        );
+        assert_eq!(kinds, vec![RewriteKind::StrengthReduction; 2]);
+    }
+
+    #[test]
+    fn keeps_errors() {
+        // The stack is empty or unknown, so MUL must still fail
+        for source in &[
+            "PUSH 1\nMUL\nOUTPUT\n",
+            "PUSH 2\nOUTPUT\nPUSH 1\nMUL\n",
+            "PUSH 2\nSYSCALL 0\nPUSH 1\nMUL\n",
+            "PUSH 2\nENTER 0\nPUSH 1\nMUL\nLEAVE\n",
+        ] {
+            let assembly: Assembly = source.parse().unwrap();
+            let (optimized, rewrites) = optimize(&assembly);
+            assert_eq!(&optimized.to_string(), source);
+            assert!(rewrites.is_empty());
+        }
+
+        for source in &["PUSH 1\nMUL\nHALT", "PUSH 8\nMUL\nHALT"] {
+            let assembly: Assembly = source.parse().unwrap();
+            let (optimized, _) = optimize(&assembly);
+            let mut program = Vec::new();
+            optimized.compile(&mut program).unwrap();
+            let mut machine = Machine::new(MemoryDevice::default());
+            assert!(matches!(
+                machine.execute_program(&program),
+                Err(ExecutionError::StackUnderflow)
+            ));
+        }
+    }
+
+    #[test]
+    fn linear() {
+        // A quadratic pass takes tens of seconds here even in release builds
+        let source = "PUSH 2\nPUSH 3\nADD\nOUTPUT\n".repeat(20_000);
+        let assembly: Assembly = source.parse().unwrap();
+        let start = std::time::Instant::now();
+        let (optimized, rewrites) = optimize(&assembly);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(rewrites.len(), 20_000);
+        assert_eq!(optimized.statements().len(), 40_000);
+    }
+
+    #[test]
+    fn report() {
+        let assembly = "PUSH 2\nPUSH 2\nADD\nOUTPUT".parse().unwrap();
+        let (_, rewrites) = optimize(&assembly);
+        assert_eq!(
+            rewrites[0].to_string(),
+            "statement 1: constant folding: PUSH 2; PUSH 2; ADD -> PUSH 4"
+        );
+    }
+}
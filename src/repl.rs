@@ -0,0 +1,292 @@
+//! Interactive session, which executes operations one by one on a persistent machine.
+//!
+//! Each line is an operation, like `PUSH 2`, or a command:
+//!
+//! - `:stack` shows the stacks and registers,
+//! - `:reset` clears the machine and forgets executed operations,
+//! - `:load path/to/file.sasm` executes operations of the file until `HALT`,
+//! - `:save path/to/session.sasm` writes executed operations to the file,
+//! - `:help` lists the commands.
+//!
+//! Comments and empty lines are ignored.
+
+use std::{
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    executor::{ExecutionError, Machine},
+    models::{strip_comment, Assembly, AssemblyParseError, Op, OpParseError, Register, Statement},
+};
+
+/// Commands of the session, shown by `:help`.
+pub const COMMANDS: &str = "\
+:stack            show the stacks and registers
+:reset            clear the machine and forget executed operations
+:load file.sasm   execute operations of the file until HALT
+:save file.sasm   write executed operations to the file
+:help             show this list
+";
+
+#[derive(Error, Debug)]
+pub enum ReplError {
+    #[error("{0}")]
+    Parse(#[from] OpParseError),
+
+    #[error("{0}")]
+    Execution(#[from] ExecutionError),
+
+    #[error("{0}")]
+    Assembly(#[from] AssemblyParseError),
+
+    #[error("Only operations can be executed, got: {0}")]
+    NotAnOperation(String),
+
+    #[error("Can't access {}: {inner}", path.display())]
+    Io { path: PathBuf, inner: io::Error },
+
+    #[error("Unknown command :{0}, try :help")]
+    UnknownCommand(String),
+
+    #[error("Expected a path after :{0}")]
+    MissingPath(String),
+}
+
+/// Machine with operations executed in the session.
+pub struct Repl<'a> {
+    machine: Machine<'a>,
+
+    /// Successfully executed operations, which are written by `:save`.
+    session: Vec<Op>,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(machine: Machine<'a>) -> Self {
+        Repl {
+            machine,
+            session: Vec::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<'a> {
+        &self.machine
+    }
+
+    /// Returns operations executed since the start or the last `:reset`.
+    pub fn session(&self) -> &[Op] {
+        &self.session
+    }
+
+    /// Executes an operation or a command, returns text to show to the user.
+    ///
+    /// Failed operation is not added to the session and leaves the machine unchanged.
+    pub fn eval(&mut self, line: &str) -> Result<String, ReplError> {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            return Ok(String::new());
+        }
+        if let Some(command) = line.strip_prefix(':') {
+            return self.command(command);
+        }
+        self.execute(line.parse()?)?;
+        Ok(self.state())
+    }
+
+    fn command(&mut self, command: &str) -> Result<String, ReplError> {
+        let mut words = command.splitn(2, char::is_whitespace);
+        let name = words.next().unwrap_or_default();
+        let path = words.next().map(str::trim).filter(|path| !path.is_empty());
+        let path = || {
+            path.map(Path::new)
+                .ok_or(ReplError::MissingPath(name.into()))
+        };
+        match name {
+            "stack" => Ok(self.state()),
+            "reset" => {
+                self.machine.reset();
+                self.session.clear();
+                Ok(self.state())
+            }
+            "load" => self.load(path()?),
+            "save" => {
+                let path = path()?;
+                let source: String = self.session.iter().map(|op| format!("{}\n", op)).collect();
+                fs::write(path, source).map_err(|inner| ReplError::Io {
+                    path: path.into(),
+                    inner,
+                })?;
+                let count = self.session.len();
+                Ok(format!("Saved {} operations to {}", count, path.display()))
+            }
+            "help" => Ok(COMMANDS.trim_end().into()),
+            _ => Err(ReplError::UnknownCommand(name.into())),
+        }
+    }
+
+    /// Executes operations of the file, statements other than operations are rejected
+    /// before anything is executed.
+    fn load(&mut self, path: &Path) -> Result<String, ReplError> {
+        let source = fs::read_to_string(path).map_err(|inner| ReplError::Io {
+            path: path.into(),
+            inner,
+        })?;
+        let assembly = Assembly::from_source(&source, path)?;
+        let ops = assembly
+            .statements()
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Op(op) => Some(Ok(*op)),
+                // Constants are already substituted into operations
+                Statement::Constant { .. } => None,
+                statement => Some(Err(ReplError::NotAnOperation(statement.to_string()))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for op in ops {
+            if !self.execute(op)? {
+                break;
+            }
+        }
+        Ok(self.state())
+    }
+
+    /// Executes the operation, returns `false` if it was `HALT`.
+    fn execute(&mut self, op: Op) -> Result<bool, ReplError> {
+        let state = self.machine.snapshot();
+        let result = self.machine.execute(op);
+        self.machine.flush()?;
+        if let Err(error) = result {
+            // Values popped by the failed operation are pushed back
+            self.machine
+                .restore(&state)
+                .expect("state of the same machine");
+            return Err(error.into());
+        }
+        self.session.push(op);
+        Ok(op != Op::Halt)
+    }
+
+    /// Returns the stacks, from the bottom, and values of the registers.
+    pub fn state(&self) -> String {
+        let mut state = format!("stack: {:?}", self.machine.stack().values());
+        let floats = self.machine.floats().values();
+        if !floats.is_empty() {
+            write!(state, "\nfloats: {:?}", floats).unwrap();
+        }
+        state.push_str("\nregisters:");
+        for (index, value) in self.machine.registers().values().iter().enumerate() {
+            let register = Register::new(index as u8).expect("registers of the machine");
+            write!(state, " {}={}", register, value).unwrap();
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{executor::MemoryDevice, models::Value};
+
+    #[test]
+    fn eval() {
+        let mut device = MemoryDevice::default();
+        let mut repl = Repl::new(Machine::new(&mut device));
+        assert_eq!(repl.eval("  ; comment").unwrap(), "");
+        assert_eq!(
+            repl.eval("push 2").unwrap(),
+            "stack: [2]\nregisters: A=0 B=0 C=0 D=0"
+        );
+        repl.eval("PUSH 3").unwrap();
+        assert!(matches!(
+            repl.eval("PUSH"),
+            Err(ReplError::Parse(OpParseError::WrongOp { .. }))
+        ));
+        assert_eq!(
+            repl.eval("MUL ; 6").unwrap(),
+            "stack: [6]\nregisters: A=0 B=0 C=0 D=0"
+        );
+        repl.eval("POP B").unwrap();
+        repl.eval("PUSHF 0.5").unwrap();
+        assert_eq!(
+            repl.eval(":stack").unwrap(),
+            "stack: []\nfloats: [0.5]\nregisters: A=0 B=6 C=0 D=0"
+        );
+        assert!(matches!(
+            repl.eval("OUTPUT"),
+            Err(ReplError::Execution(ExecutionError::StackUnderflow))
+        ));
+        assert_eq!(repl.session().len(), 5);
+
+        // Failed operations keep the stack
+        repl.eval("PUSH 5").unwrap();
+        assert!(matches!(
+            repl.eval("DIV"),
+            Err(ReplError::Execution(ExecutionError::StackUnderflow))
+        ));
+        repl.eval("PUSH 0").unwrap();
+        assert!(matches!(
+            repl.eval("MOD"),
+            Err(ReplError::Execution(ExecutionError::DivisionByZero { .. }))
+        ));
+        assert_eq!(
+            repl.eval(":stack").unwrap(),
+            "stack: [5, 0]\nfloats: [0.5]\nregisters: A=0 B=6 C=0 D=0"
+        );
+        assert_eq!(repl.session().len(), 7);
+
+        assert!(matches!(
+            repl.eval(":frobnicate"),
+            Err(ReplError::UnknownCommand(_))
+        ));
+        assert!(matches!(repl.eval(":save"), Err(ReplError::MissingPath(_))));
+        assert_eq!(
+            repl.eval(":reset").unwrap(),
+            "stack: []\nregisters: A=0 B=0 C=0 D=0"
+        );
+        assert!(repl.session().is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("smachine-repl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.sasm");
+
+        let mut device = MemoryDevice::default();
+        let mut repl = Repl::new(Machine::new(&mut device));
+        for line in ["PUSH 2", "PUSH 3", "ADD", "OUTPUT", "PUSH 1"].iter() {
+            repl.eval(line).unwrap();
+        }
+        let saved = repl.eval(&format!(":save {}", path.display())).unwrap();
+        assert!(saved.starts_with("Saved 5 operations to "));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "PUSH 2\nPUSH 3\nADD\nOUTPUT\nPUSH 1\n"
+        );
+
+        repl.eval(":reset").unwrap();
+        fs::write(&path, ".equ TEN 10\nPUSH TEN\nOUTPUT\nHALT\nPUSH 1\n").unwrap();
+        assert_eq!(
+            repl.eval(&format!(":load {}", path.display())).unwrap(),
+            "stack: []\nregisters: A=0 B=0 C=0 D=0"
+        );
+        assert_eq!(
+            repl.session(),
+            &[Op::PushValue(Value(10)), Op::Output, Op::Halt]
+        );
+
+        fs::write(&path, "PUSH 1\nCALLHOST print\n").unwrap();
+        assert!(matches!(
+            repl.eval(&format!(":load {}", path.display())),
+            Err(ReplError::NotAnOperation(_))
+        ));
+        assert_eq!(repl.session().len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+
+        drop(repl);
+        assert_eq!(device.output(), b"5\n10\n");
+    }
+}